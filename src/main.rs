@@ -1,7 +1,8 @@
 use std::path::PathBuf;
 
 use clap::{Args, Parser, Subcommand, ValueEnum};
-use typort_interpreter::main_cli;
+use typort_interpreter::diagnostic::MessageFormat;
+use typort_interpreter::{main_cli, main_repl};
 use typort_lsp::main_lsp;
 
 /// A HDL
@@ -24,7 +25,23 @@ enum Commands {
         path: PathBuf,
         /// main function name
         main: Option<String>,
+        /// compile to machine code instead of running on the vm
+        #[arg(long)]
+        jit: bool,
+        /// how to show errors
+        #[arg(long, value_enum, default_value_t = Format::Human)]
+        message_format: Format,
     },
+    /// read and run definitions and expressions one by one
+    Repl,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    /// annotated source on stderr
+    Human,
+    /// one JSON object per line on stdout
+    Json,
 }
 
 #[tokio::main]
@@ -33,6 +50,13 @@ async fn main() {
 
     match args.command {
         Commands::Lsp => main_lsp().await,
-        Commands::Cli { path, main } => main_cli(&path, main),
+        Commands::Cli { path, main, jit, message_format } => {
+            let format = match message_format {
+                Format::Human => MessageFormat::Human,
+                Format::Json => MessageFormat::Json,
+            };
+            main_cli(&path, main, jit, format)
+        }
+        Commands::Repl => main_repl(),
     }
 }
@@ -0,0 +1,68 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use typort_interpreter::{bytecode, compile_to_mir, find_top, machine::Machine, vm::Interpreter};
+
+const FIB: &str = "
+object main extends App {
+  def fib(n: Int): Int = if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+  fib(20)
+}";
+
+const LOOPS: &str = "
+object main extends App {
+  def run(n: Int): Int = {
+    var sum = 0
+    for(i <- 0 until n) {
+      var j = 0
+      while(j < 10) {
+        sum = (sum + i * j) % 1000003
+        j = j + 1
+      }
+    }
+    sum
+  }
+  run(5000)
+}";
+
+const ARRAYS: &str = "
+object main extends App {
+  def run(n: Int): Int = {
+    var sum = 0
+    for(i <- 0 until n) {
+      val t = (i, i + 1, i * 2)
+      val s = t match {
+        case (a, b, c) => a + b + c
+      }
+      val x = Array(i, s)
+      sum = sum + s
+    }
+    sum
+  }
+  run(5000)
+}";
+
+fn bench_program(c: &mut Criterion, name: &str, code: &str) {
+    let mir = compile_to_mir(code).unwrap();
+    let main = find_top(&mir, None).unwrap().clone();
+    let program = bytecode::compile(&mir);
+    let tree = format!("{:?}", Interpreter::new(mir.clone()).run(&main));
+    let machine = format!("{:?}", Machine::new(program.clone()).run(main.name.data));
+    assert_eq!(tree, machine, "{name}");
+
+    let mut group = c.benchmark_group(name);
+    group.bench_function("tree", |b| {
+        b.iter(|| Interpreter::new(mir.clone()).run(&main).unwrap())
+    });
+    group.bench_function("machine", |b| {
+        b.iter(|| Machine::new(program.clone()).run(main.name.data).unwrap())
+    });
+    group.finish();
+}
+
+fn vm_benches(c: &mut Criterion) {
+    bench_program(c, "fib", FIB);
+    bench_program(c, "loops", LOOPS);
+    bench_program(c, "arrays", ARRAYS);
+}
+
+criterion_group!(benches, vm_benches);
+criterion_main!(benches);
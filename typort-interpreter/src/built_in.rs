@@ -1,25 +1,477 @@
-use crate::vm::{Value, HeapValue, Interpreter};
+use crate::value::{Value, HeapValue, Heap};
 
+/// what a built-in can use of the vm running it
+pub trait Host {
+    fn heap(&mut self) -> &mut Heap;
+    /// call a function value, the heap is not collected before the built-in
+    /// returns since the values it holds are not roots
+    fn call(&mut self, f: Value, args: Vec<Value>) -> Result<Value, String>;
+    /// where `print` writes a line
+    fn print(&mut self, line: &str) {
+        println!("{line}");
+    }
+}
+
+/// built-in functions report a message, the vm adds the location and the trace
+pub type BuiltIn = fn(&mut dyn Host, Vec<Value>) -> Result<Value, String>;
+
+/// every built-in function, the vms and the compilers look them up here
+pub const BUILT_INS: &[(&str, BuiltIn)] = &[
+    ("print", bi_print),
+    ("Array", bi_array),
+    ("Seq", bi_array),
+    ("List", bi_array),
+    ("Map", bi_map),
+    ("log2Up", bi_log2_up),
+    ("isPow2", bi_is_pow2),
+    ("BigInt", bi_big_int),
+    ("assert", bi_assert),
+    ("require", bi_require),
+];
+
+/// methods of the values built-ins make, the receiver is the first argument.
+/// they are tried when the receiver has no method of that name
+pub const BUILT_IN_METHODS: &[(&str, BuiltIn)] = &[
+    ("apply", m_apply),
+    ("length", m_length),
+    ("size", m_length),
+    ("isEmpty", m_is_empty),
+    ("head", m_head),
+    ("last", m_last),
+    ("contains", m_contains),
+    ("reverse", m_reverse),
+    ("updated", m_updated),
+    ("map", m_map),
+    ("filter", m_filter),
+    ("foldLeft", m_fold_left),
+    ("zipWithIndex", m_zip_with_index),
+    ("mkString", m_mk_string),
+    ("keys", m_keys),
+    ("values", m_values),
+    ("format", m_format),
+    ("toString", m_to_string),
+    ("bits", m_bits),
+    ("bit", m_bits),
+];
+
+pub fn built_in(name: &str) -> Option<BuiltIn> {
+    BUILT_INS.iter().find(|(n, _)| *n == name).map(|(_, f)| *f)
+}
+
+pub fn built_in_method(name: &str) -> Option<BuiltIn> {
+    BUILT_IN_METHODS.iter().find(|(n, _)| *n == name).map(|(_, f)| *f)
+}
+
+/// how `print` and string interpolation show a value
+pub fn display(heap: &Heap, value: Value) -> String {
+    fn go(heap: &Heap, value: Value, depth: usize) -> String {
+        let Value::HeapId(id) = value else {
+            return match value {
+                Value::Int(x) => x.to_string(),
+                Value::Bool(x) => x.to_string(),
+                Value::Unit => "()".to_owned(),
+                Value::Closure(_) => "<function>".to_owned(),
+                Value::HeapId(_) => unreachable!(),
+            };
+        };
+        // values may refer to themselves
+        if depth > 8 {
+            return "...".to_owned();
+        }
+        let join = |items: &mut dyn Iterator<Item = String>| items.collect::<Vec<_>>().join(", ");
+        match heap.get(id) {
+            Some(HeapValue::String(s)) => s.clone(),
+            Some(HeapValue::Vec(items)) => {
+                format!("[{}]", join(&mut items.iter().map(|x| go(heap, *x, depth + 1))))
+            }
+            Some(HeapValue::Map(entries)) => format!("Map({})", join(&mut entries.iter().map(|(k, v)| {
+                format!("{} -> {}", go(heap, *k, depth + 1), go(heap, *v, depth + 1))
+            }))),
+            Some(HeapValue::Class(instance)) => {
+                let mut fields: Vec<_> = instance.fields.iter().collect();
+                fields.sort_by_key(|(name, _)| name.as_str());
+                format!("{}({})", instance.class, join(&mut fields.into_iter().map(|(name, v)| {
+                    format!("{name} = {}", go(heap, *v, depth + 1))
+                })))
+            }
+            None => format!("<freed {id}>"),
+        }
+    }
+    go(heap, value, 0)
+}
+
+/// strings are equal by their text, other heap values by identity
+fn value_eq(heap: &Heap, l: Value, r: Value) -> bool {
+    match (l, r) {
+        (Value::Int(l), Value::Int(r)) => l == r,
+        (Value::Bool(l), Value::Bool(r)) => l == r,
+        (Value::Unit, Value::Unit) => true,
+        (Value::Closure(l), Value::Closure(r)) => l == r,
+        (Value::HeapId(l), Value::HeapId(r)) => match (heap.get(l), heap.get(r)) {
+            (Some(HeapValue::String(l)), Some(HeapValue::String(r))) => l == r,
+            _ => l == r,
+        },
+        _ => false,
+    }
+}
+
+/// the arguments of a function taking exactly `N`
+fn fixed<const N: usize>(name: &str, args: Vec<Value>) -> Result<[Value; N], String> {
+    args.try_into().map_err(|args: Vec<Value>| {
+        format!("`{name}` takes {N} arguments but {} were given", args.len())
+    })
+}
+
+/// the receiver and the arguments of a method taking `N - 1`
+fn method<const N: usize>(name: &str, args: Vec<Value>) -> Result<[Value; N], String> {
+    args.try_into().map_err(|args: Vec<Value>| {
+        format!("`{name}` takes {} arguments but {} were given", N - 1, args.len() - 1)
+    })
+}
+
+fn alloc(host: &mut dyn Host, value: HeapValue) -> Value {
+    Value::HeapId(host.heap().alloc(value))
+}
+
+fn int(value: Value) -> Result<i64, String> {
+    match value {
+        Value::Int(x) => Ok(x),
+        _ => Err("expect Int".to_owned()),
+    }
+}
+
+fn bool(value: Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(x) => Ok(x),
+        _ => Err("expect Bool".to_owned()),
+    }
+}
+
+fn string(host: &mut dyn Host, value: Value) -> Result<String, String> {
+    match value {
+        Value::HeapId(id) => match host.heap().get(id) {
+            Some(HeapValue::String(s)) => Ok(s.clone()),
+            _ => Err("expect String".to_owned()),
+        },
+        _ => Err("expect String".to_owned()),
+    }
+}
+
+/// the items of a Seq, a Map gives its entries as tuples
+fn items(host: &mut dyn Host, value: Value) -> Result<Vec<Value>, String> {
+    let entries = match value {
+        Value::HeapId(id) => match host.heap().get(id) {
+            Some(HeapValue::Vec(items)) => return Ok(items.clone()),
+            Some(HeapValue::Map(entries)) => entries.clone(),
+            _ => return Err("expect Seq".to_owned()),
+        },
+        _ => return Err("expect Seq".to_owned()),
+    };
+    Ok(entries.into_iter().map(|(k, v)| alloc(host, HeapValue::Vec(vec![k, v]))).collect())
+}
+
+fn entries(host: &mut dyn Host, value: Value) -> Option<Vec<(Value, Value)>> {
+    match value {
+        Value::HeapId(id) => match host.heap().get(id) {
+            Some(HeapValue::Map(entries)) => Some(entries.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn index(items: &[Value], i: Value) -> Result<usize, String> {
+    let i = int(i)?;
+    match usize::try_from(i) {
+        Ok(idx) if idx < items.len() => Ok(idx),
+        _ => Err(format!("index {i} out of bounds for length {}", items.len())),
+    }
+}
+
+/// add or replace the entry of `key`
+fn insert(heap: &Heap, entries: &mut Vec<(Value, Value)>, key: Value, value: Value) {
+    match entries.iter_mut().find(|(k, _)| value_eq(heap, *k, key)) {
+        Some(entry) => entry.1 = value,
+        None => entries.push((key, value)),
+    }
+}
 
-pub fn bi_print(vm: &mut Interpreter, args: Vec<Value>) -> Value {
+pub fn bi_print(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
     for value in args {
-        match value {
-            Value::HeapId(idx) => {
-                match vm.heap.get(&idx).unwrap() {
-                    HeapValue::Vec(x) => {println!("{x:?}")},
-                    HeapValue::String(s) => {println!("{s}");},
-                    //HeapValue::Class(_) => todo!(),
-                }
+        let line = display(host.heap(), value);
+        host.print(&line);
+    }
+    Ok(Value::Unit)
+}
+
+pub fn bi_array(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    Ok(alloc(host, HeapValue::Vec(args)))
+}
+
+/// `Map(k -> v, ...)`, each argument is a tuple of a key and a value
+pub fn bi_map(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let mut entries = vec![];
+    for arg in args {
+        let pair = match arg {
+            Value::HeapId(id) => match host.heap().get(id) {
+                Some(HeapValue::Vec(pair)) if pair.len() == 2 => (pair[0], pair[1]),
+                _ => return Err("expect a key and a value".to_owned()),
             },
-            _ => {println!("{value:?}");},
+            _ => return Err("expect a key and a value".to_owned()),
+        };
+        insert(host.heap(), &mut entries, pair.0, pair.1);
+    }
+    Ok(alloc(host, HeapValue::Map(entries)))
+}
+
+/// the bits needed to count up to `n - 1`
+pub fn bi_log2_up(_: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [n] = fixed("log2Up", args)?;
+    match int(n)? {
+        n if n < 0 => Err(format!("log2Up of negative number {n}")),
+        n if n <= 1 => Ok(Value::Int(0)),
+        n => Ok(Value::Int(64 - (n - 1).leading_zeros() as i64)),
+    }
+}
+
+pub fn bi_is_pow2(_: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [n] = fixed("isPow2", args)?;
+    let n = int(n)?;
+    Ok(Value::Bool(n > 0 && n & (n - 1) == 0))
+}
+
+/// `BigInt(x)`, `BigInt("ff", 16)`. an `Int` is 64 bits wide, a wider
+/// number is an error for now
+pub fn bi_big_int(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let (value, radix) = match args[..] {
+        [value] => (value, 10),
+        [value, radix] => (value, int(radix)?),
+        _ => return Err(format!("`BigInt` takes 1 or 2 arguments but {} were given", args.len())),
+    };
+    if let Value::Int(x) = value {
+        return Ok(Value::Int(x));
+    }
+    let text = string(host, value)?;
+    let radix = u32::try_from(radix).ok().filter(|r| (2..=36).contains(r))
+        .ok_or(format!("invalid radix {radix}"))?;
+    i64::from_str_radix(&text, radix)
+        .map(Value::Int)
+        .map_err(|e| format!("invalid number {text:?}: {e}"))
+}
+
+/// `assert` and `require` fail with `what` and the optional message
+fn check(host: &mut dyn Host, name: &str, what: &str, args: Vec<Value>) -> Result<Value, String> {
+    let (cond, msg) = match args[..] {
+        [cond] => (cond, None),
+        [cond, msg] => (cond, Some(msg)),
+        _ => return Err(format!("`{name}` takes 1 or 2 arguments but {} were given", args.len())),
+    };
+    match (bool(cond)?, msg) {
+        (true, _) => Ok(Value::Unit),
+        (false, None) => Err(what.to_owned()),
+        (false, Some(msg)) => Err(format!("{what}: {}", display(host.heap(), msg))),
+    }
+}
+
+pub fn bi_assert(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    check(host, "assert", "assertion failed", args)
+}
+
+pub fn bi_require(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    check(host, "require", "requirement failed", args)
+}
+
+/// `xs(i)` on a Seq, `m(k)` on a Map
+pub fn m_apply(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this, i] = method("apply", args)?;
+    if let Some(entries) = entries(host, this) {
+        let heap = host.heap();
+        return match entries.iter().find(|(k, _)| value_eq(heap, *k, i)) {
+            Some((_, v)) => Ok(*v),
+            None => Err(format!("key not found: {}", display(heap, i))),
+        };
+    }
+    let items = match items(host, this) {
+        Ok(items) => items,
+        Err(_) => return Err(format!("{this:?} is not a function")),
+    };
+    Ok(items[index(&items, i)?])
+}
+
+/// the length of a Seq or a String, the entries of a Map
+pub fn m_length(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this] = method("length", args)?;
+    if let Ok(s) = string(host, this) {
+        return Ok(Value::Int(s.chars().count() as i64));
+    }
+    Ok(Value::Int(items(host, this)?.len() as i64))
+}
+
+pub fn m_is_empty(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this] = method("isEmpty", args)?;
+    match m_length(host, vec![this])? {
+        Value::Int(len) => Ok(Value::Bool(len == 0)),
+        _ => unreachable!(),
+    }
+}
+
+pub fn m_head(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this] = method("head", args)?;
+    items(host, this)?.first().copied().ok_or("head of empty Seq".to_owned())
+}
+
+pub fn m_last(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this] = method("last", args)?;
+    items(host, this)?.last().copied().ok_or("last of empty Seq".to_owned())
+}
+
+/// an item of a Seq, a key of a Map or a part of a String
+pub fn m_contains(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this, x] = method("contains", args)?;
+    if let Ok(s) = string(host, this) {
+        return Ok(Value::Bool(s.contains(&string(host, x)?)));
+    }
+    let found = match entries(host, this) {
+        Some(entries) => entries.iter().any(|(k, _)| value_eq(host.heap(), *k, x)),
+        None => items(host, this)?.iter().any(|v| value_eq(host.heap(), *v, x)),
+    };
+    Ok(Value::Bool(found))
+}
+
+pub fn m_reverse(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this] = method("reverse", args)?;
+    if let Ok(s) = string(host, this) {
+        return Ok(alloc(host, HeapValue::String(s.chars().rev().collect())));
+    }
+    let mut items = items(host, this)?;
+    items.reverse();
+    Ok(alloc(host, HeapValue::Vec(items)))
+}
+
+/// a copy with the item at an index or the entry of a key replaced
+pub fn m_updated(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this, key, value] = method("updated", args)?;
+    if let Some(mut entries) = entries(host, this) {
+        insert(host.heap(), &mut entries, key, value);
+        return Ok(alloc(host, HeapValue::Map(entries)));
+    }
+    let mut items = items(host, this)?;
+    let idx = index(&items, key)?;
+    items[idx] = value;
+    Ok(alloc(host, HeapValue::Vec(items)))
+}
+
+pub fn m_map(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this, f] = method("map", args)?;
+    let mut ret = vec![];
+    for x in items(host, this)? {
+        ret.push(host.call(f, vec![x])?);
+    }
+    Ok(alloc(host, HeapValue::Vec(ret)))
+}
+
+pub fn m_filter(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this, f] = method("filter", args)?;
+    let mut ret = vec![];
+    for x in items(host, this)? {
+        if bool(host.call(f, vec![x])?)? {
+            ret.push(x);
+        }
+    }
+    Ok(alloc(host, HeapValue::Vec(ret)))
+}
+
+/// `xs.foldLeft(z)(f)`, both argument lists are given at once
+pub fn m_fold_left(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this, mut acc, f] = method("foldLeft", args)?;
+    for x in items(host, this)? {
+        acc = host.call(f, vec![acc, x])?;
+    }
+    Ok(acc)
+}
+
+pub fn m_zip_with_index(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this] = method("zipWithIndex", args)?;
+    let ret = items(host, this)?.into_iter()
+        .enumerate()
+        .map(|(i, x)| alloc(host, HeapValue::Vec(vec![x, Value::Int(i as i64)])))
+        .collect();
+    Ok(alloc(host, HeapValue::Vec(ret)))
+}
+
+/// `mkString`, `mkString(sep)` or `mkString(start, sep, end)`
+pub fn m_mk_string(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let mut parts = vec![];
+    for arg in args.iter().skip(1) {
+        parts.push(string(host, *arg)?);
+    }
+    let (start, sep, end) = match &parts[..] {
+        [] => ("", "", ""),
+        [sep] => ("", sep.as_str(), ""),
+        [start, sep, end] => (start.as_str(), sep.as_str(), end.as_str()),
+        _ => return Err(format!("`mkString` takes 0, 1 or 3 arguments but {} were given", parts.len())),
+    };
+    let items = items(host, args[0])?;
+    let heap = host.heap();
+    let text = items.iter().map(|x| display(heap, *x)).collect::<Vec<_>>().join(sep);
+    let text = format!("{start}{text}{end}");
+    Ok(alloc(host, HeapValue::String(text)))
+}
+
+pub fn m_keys(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this] = method("keys", args)?;
+    let keys = entries(host, this).ok_or("expect Map")?.into_iter().map(|(k, _)| k).collect();
+    Ok(alloc(host, HeapValue::Vec(keys)))
+}
+
+pub fn m_values(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this] = method("values", args)?;
+    let values = entries(host, this).ok_or("expect Map")?.into_iter().map(|(_, v)| v).collect();
+    Ok(alloc(host, HeapValue::Vec(values)))
+}
+
+/// `"%d of %s".format(a, b)`: `%s` and `%d` show a value, `%x` and `%b` an
+/// `Int` in hex and binary, `%%` is a `%`. `s"..."` is turned into this
+pub fn m_format(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let Some((this, args)) = args.split_first() else {
+        return Err("expect String".to_owned());
+    };
+    let text = string(host, *this)?;
+    let mut args = args.iter();
+    let mut ret = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            ret.push(c);
+            continue;
+        }
+        let conv = chars.next();
+        if conv == Some('%') {
+            ret.push('%');
+            continue;
+        }
+        let arg = *args.next().ok_or("not enough arguments for format")?;
+        match conv {
+            Some('s' | 'd') => ret.push_str(&display(host.heap(), arg)),
+            Some('x') => ret.push_str(&format!("{:x}", int(arg)?)),
+            Some('b') => ret.push_str(&format!("{:b}", int(arg)?)),
+            _ => return Err(format!("invalid format {text:?}")),
         }
     }
-    
-    Value::Unit
+    if args.next().is_some() {
+        return Err("too many arguments for format".to_owned());
+    }
+    Ok(alloc(host, HeapValue::String(ret)))
+}
+
+pub fn m_to_string(host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this] = method("toString", args)?;
+    let text = display(host.heap(), this);
+    Ok(alloc(host, HeapValue::String(text)))
 }
 
-pub fn bi_array(vm: &mut Interpreter, args: Vec<Value>) -> Value {
-    let idx = vm.heap.len();
-    vm.heap.insert(idx, HeapValue::Vec(args));
-    Value::HeapId(idx)
+/// `8 bits`, a width is its number of bits
+pub fn m_bits(_: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+    let [this] = method("bits", args)?;
+    int(this).map(Value::Int)
 }
@@ -0,0 +1,583 @@
+use std::collections::HashMap;
+
+use crate::mir::{self, Capture, Expression, Pattern, Stmt, Var};
+use crate::built_in::BUILT_INS;
+use crate::{Span, Symbol};
+
+/// a register of the running frame
+pub type Reg = u32;
+
+/// register machine instructions, jump targets are indexes into `Proto::code`
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Int(Reg, i64),
+    Bool(Reg, bool),
+    Unit(Reg),
+    /// dest, index into `Program::strings`, a new string is allocated each time
+    String(Reg, u32),
+    Move(Reg, Reg),
+    GetUpvalue(Reg, u32),
+    SetUpvalue(u32, Reg),
+    Add(Reg, Reg, Reg),
+    Sub(Reg, Reg, Reg),
+    Mul(Reg, Reg, Reg),
+    Div(Reg, Reg, Reg),
+    Mod(Reg, Reg, Reg),
+    Eq(Reg, Reg, Reg),
+    Neq(Reg, Reg, Reg),
+    Lt(Reg, Reg, Reg),
+    Le(Reg, Reg, Reg),
+    Gt(Reg, Reg, Reg),
+    Ge(Reg, Reg, Reg),
+    Not(Reg, Reg),
+    Neg(Reg, Reg),
+    Jump(u32),
+    JumpIfFalse(Reg, u32),
+    JumpIfTrue(Reg, u32),
+    /// dest, global, first argument, argument count: a built-in or a native
+    CallGlobal(Reg, u32, Reg, u32),
+    /// dest, closure, first argument, argument count
+    CallValue(Reg, Reg, Reg, u32),
+    /// dest, method, receiver, argument count: the arguments follow the receiver
+    CallMethod(Reg, Symbol, Reg, u32),
+    /// dest, class, the register for `this`, argument count: the arguments
+    /// follow it
+    New(Reg, Symbol, Reg, u32),
+    /// dest, object, field: a method without arguments is called
+    GetField(Reg, Reg, Symbol),
+    /// object, field, value
+    SetField(Reg, Symbol, Reg),
+    /// dest, index into `Program::protos`
+    Closure(Reg, u32),
+    /// dest, first item, item count
+    Tuple(Reg, Reg, u32),
+    /// dest, value, length: if the value is a tuple of this length
+    IsTuple(Reg, Reg, u32),
+    /// dest, tuple, index
+    GetItem(Reg, Reg, u32),
+    /// close the upvalues pointing to this register and above
+    Close(Reg),
+    Return(Reg),
+    MatchError(Reg),
+    /// fail with the message at this index of `Program::strings`
+    Error(u32),
+}
+
+/// a compiled function, class body or lambda
+#[derive(Debug, Clone)]
+pub struct Proto {
+    pub name: Symbol,
+    pub params: u32,
+    pub max_regs: u32,
+    /// captures resolved to registers of the enclosing function
+    pub captures: Vec<Capture>,
+    pub code: Vec<Op>,
+    /// the source location of each op, for runtime errors
+    pub spans: Vec<Span<()>>,
+}
+
+/// the compiled code of a class
+#[derive(Debug, Clone, Default)]
+pub struct ClassCode {
+    /// the proto of the body
+    pub ctor: u32,
+    /// the bodies of the inherited classes, they run first in this order
+    pub inherited: Vec<u32>,
+    /// every method of an instance, the inherited ones included
+    pub methods: HashMap<Symbol, u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub protos: Vec<Proto>,
+    pub classes: HashMap<Symbol, ClassCode>,
+    pub globals: Vec<Symbol>,
+    global_idx: HashMap<Symbol, u32>,
+    pub strings: Vec<String>,
+}
+
+impl Program {
+    fn global(&mut self, name: Symbol) -> u32 {
+        if let Some(idx) = self.global_idx.get(&name) {
+            return *idx;
+        }
+        self.globals.push(name);
+        let idx = self.globals.len() as u32 - 1;
+        self.global_idx.insert(name, idx);
+        idx
+    }
+    fn string(&mut self, s: String) -> u32 {
+        self.strings.push(s);
+        self.strings.len() as u32 - 1
+    }
+}
+
+pub fn compile(classes: &[mir::Class]) -> Program {
+    let mut program = Program::default();
+    // every class is known before any body is compiled, calls to them create instances
+    for class in classes {
+        program.classes.insert(class.name.data, ClassCode::default());
+    }
+    let mut methods: HashMap<(Symbol, Symbol), u32> = HashMap::new();
+    for class in classes {
+        let params: Vec<usize> = std::iter::once(0).chain(class.args.iter().map(|(slot, _)| slot.data)).collect();
+        let proto = FuncCompiler::new(&mut program, class.name.data)
+            .compile(&params, &class.block);
+        program.protos.push(proto);
+        program.classes.get_mut(&class.name.data).unwrap().ctor = program.protos.len() as u32 - 1;
+        for (name, f) in class.funcs.iter() {
+            let proto = FuncCompiler::new(&mut program, *name).compile(&f.params, &f.block);
+            program.protos.push(proto);
+            methods.insert((class.name.data, *name), program.protos.len() as u32 - 1);
+        }
+    }
+    for class in classes {
+        let inherited = class.linearization[1..].iter().rev().map(|c| program.classes[c].ctor).collect();
+        let code = program.classes.get_mut(&class.name.data).unwrap();
+        code.inherited = inherited;
+        code.methods = class.methods.iter().map(|(name, from)| (*name, methods[&(*from, *name)])).collect();
+    }
+    program
+}
+
+struct Loop {
+    /// the first register allocated inside the loop
+    base: Reg,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+struct FuncCompiler<'a> {
+    program: &'a mut Program,
+    name: Symbol,
+    code: Vec<Op>,
+    spans: Vec<Span<()>>,
+    /// MIR stack slot to register, slots are reused after a block ends
+    slots: Vec<Reg>,
+    next_reg: Reg,
+    max_regs: Reg,
+    /// registers captured by a closure, they need a `Close` when freed
+    captured: Vec<bool>,
+    loops: Vec<Loop>,
+}
+
+impl<'a> FuncCompiler<'a> {
+    fn new(program: &'a mut Program, name: Symbol) -> Self {
+        Self {
+            program,
+            name,
+            code: vec![],
+            spans: vec![],
+            slots: vec![],
+            next_reg: 0,
+            max_regs: 0,
+            captured: vec![],
+            loops: vec![],
+        }
+    }
+    fn compile(mut self, params: &[usize], block: &[Stmt]) -> Proto {
+        for slot in params {
+            let reg = self.alloc();
+            self.bind(*slot, reg);
+        }
+        let ret = self.alloc();
+        self.compile_stmts(block, Some(ret));
+        self.emit(Op::Return(ret), None);
+        Proto {
+            name: self.name,
+            params: params.len() as u32,
+            max_regs: self.max_regs,
+            captures: vec![],
+            code: self.code,
+            spans: self.spans,
+        }
+    }
+    fn emit(&mut self, op: Op, span: Option<Span<()>>) -> usize {
+        self.code.push(op);
+        self.spans.push(span.unwrap_or_default());
+        self.code.len() - 1
+    }
+    fn here(&self) -> u32 {
+        self.code.len() as u32
+    }
+    /// point the jump at `at` to the current position
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.code[at] {
+            Op::Jump(t) | Op::JumpIfFalse(_, t) | Op::JumpIfTrue(_, t) => *t = target,
+            op => unreachable!("{op:?} is not a jump"),
+        }
+    }
+    fn alloc(&mut self) -> Reg {
+        let reg = self.next_reg;
+        self.next_reg += 1;
+        self.max_regs = self.max_regs.max(self.next_reg);
+        if self.captured.len() < self.next_reg as usize {
+            self.captured.resize(self.next_reg as usize, false);
+        }
+        reg
+    }
+    fn bind(&mut self, slot: usize, reg: Reg) {
+        if self.slots.len() <= slot {
+            self.slots.resize(slot + 1, 0);
+        }
+        self.slots[slot] = reg;
+    }
+    /// free the registers from `base`, closing the captured ones
+    fn free_to(&mut self, base: Reg) {
+        self.close_from(base);
+        for c in self.captured[base as usize..].iter_mut() {
+            *c = false;
+        }
+        self.next_reg = base;
+    }
+    fn close_from(&mut self, base: Reg) {
+        if self.captured[base as usize..self.next_reg as usize].contains(&true) {
+            self.emit(Op::Close(base), None);
+        }
+    }
+    /// the register holding the value of `e`, locals are used in place
+    fn expr_reg(&mut self, e: &Expression) -> Reg {
+        if let Expression::Name(Span { data: Var::Local(slot), .. }) = e {
+            return self.slots[*slot];
+        }
+        let reg = self.alloc();
+        self.compile_expr(e, reg);
+        reg
+    }
+    fn compile_stmts(&mut self, stmts: &[Stmt], dest: Option<Reg>) {
+        if stmts.is_empty() {
+            if let Some(dest) = dest {
+                self.emit(Op::Unit(dest), None);
+            }
+        }
+        for (idx, s) in stmts.iter().enumerate() {
+            let last = idx + 1 == stmts.len();
+            self.compile_stmt(s, if last { dest } else { None });
+        }
+    }
+    /// a nested block, its registers are freed at the end
+    fn compile_scoped(&mut self, stmts: &[Stmt], dest: Option<Reg>) {
+        let base = self.next_reg;
+        self.compile_stmts(stmts, dest);
+        self.free_to(base);
+    }
+    fn compile_stmt(&mut self, stmt: &Stmt, dest: Option<Reg>) {
+        match stmt {
+            Stmt::Expr(e) => {
+                match dest {
+                    Some(dest) => self.compile_expr(e, dest),
+                    None => {
+                        let base = self.next_reg;
+                        self.expr_reg(e);
+                        self.next_reg = base;
+                    }
+                }
+                return;
+            }
+            Stmt::Let(slot, e) => {
+                let reg = self.alloc();
+                self.compile_expr(e, reg);
+                self.bind(slot.data, reg);
+            }
+            Stmt::Assign(var, e) => match var.data {
+                Var::Local(slot) => {
+                    let reg = self.slots[slot];
+                    self.compile_expr(e, reg);
+                }
+                Var::Upvalue(idx) => {
+                    let base = self.next_reg;
+                    let reg = self.expr_reg(e);
+                    self.emit(Op::SetUpvalue(idx as u32, reg), None);
+                    self.next_reg = base;
+                }
+                Var::Global(name) => {
+                    let msg = self.program.string(format!("can not assign to `{name}`"));
+                    self.emit(Op::Error(msg), Some(var.with_data(())));
+                }
+            },
+            Stmt::SetField(obj, name, e) => {
+                let base = self.next_reg;
+                let o = self.expr_reg(obj);
+                let v = self.expr_reg(e);
+                self.emit(Op::SetField(o, name.data, v), Some(name.with_data(())));
+                self.next_reg = base;
+            }
+            Stmt::Return(e) => {
+                let base = self.next_reg;
+                let reg = self.expr_reg(e);
+                self.emit(Op::Return(reg), e.span());
+                self.next_reg = base;
+            }
+            Stmt::Break(at) | Stmt::Continue(at) => match self.loops.last() {
+                Some(l) => {
+                    self.close_from(l.base);
+                    let jump = self.emit(Op::Jump(0), None);
+                    let l = self.loops.last_mut().unwrap();
+                    if matches!(stmt, Stmt::Break(_)) {
+                        l.breaks.push(jump);
+                    } else {
+                        l.continues.push(jump);
+                    }
+                }
+                None => {
+                    let kind = if matches!(stmt, Stmt::Break(_)) { "break" } else { "continue" };
+                    let msg = self.program.string(format!("{kind} outside of a loop"));
+                    self.emit(Op::Error(msg), Some(at.clone()));
+                }
+            },
+            Stmt::While(cond, block, step) => {
+                let start = self.here();
+                let base = self.next_reg;
+                let c = self.expr_reg(cond);
+                let exit = self.emit(Op::JumpIfFalse(c, 0), cond.span());
+                self.next_reg = base;
+                self.loops.push(Loop { base, breaks: vec![], continues: vec![] });
+                self.compile_scoped(block, None);
+                let l = self.loops.pop().unwrap();
+                for at in l.continues {
+                    self.patch(at);
+                }
+                self.compile_scoped(step, None);
+                self.emit(Op::Jump(start), None);
+                self.patch(exit);
+                for at in l.breaks {
+                    self.patch(at);
+                }
+            }
+            Stmt::Block(b) => {
+                self.compile_scoped(b, dest);
+                return;
+            }
+            Stmt::Func { name, slot, params, captures, return_type: _, block } => {
+                // the register of the block's slot holds it, calls go through `CallValue`
+                let proto = self.compile_func(name.data, params, captures, block);
+                self.emit(Op::Closure(self.slots[*slot], proto), Some(name.with_data(())));
+            }
+        }
+        if let Some(dest) = dest {
+            self.emit(Op::Unit(dest), None);
+        }
+    }
+    fn compile_func(&mut self, name: Symbol, params: &[usize], captures: &[Capture], block: &[Stmt]) -> u32 {
+        let captures = captures
+            .iter()
+            .map(|c| match c {
+                Capture::Local(slot) => {
+                    let reg = self.slots[*slot];
+                    self.captured[reg as usize] = true;
+                    Capture::Local(reg as usize)
+                }
+                Capture::Upvalue(idx) => Capture::Upvalue(*idx),
+            })
+            .collect();
+        let mut proto = FuncCompiler::new(self.program, name).compile(params, block);
+        proto.captures = captures;
+        self.program.protos.push(proto);
+        self.program.protos.len() as u32 - 1
+    }
+    fn compile_binary(&mut self, op: fn(Reg, Reg, Reg) -> Op, dest: Reg, l: &Expression, r: &Expression, at: Option<Span<()>>) {
+        let base = self.next_reg;
+        let a = self.expr_reg(l);
+        let b = self.expr_reg(r);
+        self.emit(op(dest, a, b), at);
+        self.next_reg = base;
+    }
+    fn compile_unary(&mut self, op: fn(Reg, Reg) -> Op, dest: Reg, e: &Expression) {
+        let base = self.next_reg;
+        let a = self.expr_reg(e);
+        self.emit(op(dest, a), e.span());
+        self.next_reg = base;
+    }
+    /// evaluate `args` into fresh registers in a row, returns the first one
+    fn compile_args(&mut self, args: &[Expression]) -> Reg {
+        let first = self.next_reg;
+        for arg in args {
+            let reg = self.alloc();
+            self.compile_expr(arg, reg);
+        }
+        first
+    }
+    fn compile_expr(&mut self, expr: &Expression, dest: Reg) {
+        match expr {
+            Expression::Int(x) => {
+                self.emit(Op::Int(dest, x.data), None);
+            }
+            Expression::String(s) => {
+                let c = self.program.string(s.data.clone());
+                self.emit(Op::String(dest, c), None);
+            }
+            Expression::Bool(x) => {
+                self.emit(Op::Bool(dest, x.data), None);
+            }
+            Expression::Name(var) => {
+                let op = match var.data {
+                    Var::Local(slot) => Op::Move(dest, self.slots[slot]),
+                    Var::Upvalue(idx) => Op::GetUpvalue(dest, idx as u32),
+                    // a `def` is in the register of its block
+                    Var::Global(name) => Op::Error(self.program.string(format!("unknown value `{name}`"))),
+                };
+                if !matches!(op, Op::Move(a, b) if a == b) {
+                    self.emit(op, Some(var.with_data(())));
+                }
+            }
+            Expression::Add(l, r) => self.compile_binary(Op::Add, dest, l, r, l.span()),
+            Expression::Sub(l, r) => self.compile_binary(Op::Sub, dest, l, r, l.span()),
+            Expression::Mul(l, r) => self.compile_binary(Op::Mul, dest, l, r, l.span()),
+            Expression::Div(l, r) => self.compile_binary(Op::Div, dest, l, r, l.span()),
+            Expression::Mod(l, r) => self.compile_binary(Op::Mod, dest, l, r, l.span()),
+            Expression::Eq(l, r) => self.compile_binary(Op::Eq, dest, l, r, expr.span()),
+            Expression::Neq(l, r) => self.compile_binary(Op::Neq, dest, l, r, expr.span()),
+            Expression::Lt(l, r) => self.compile_binary(Op::Lt, dest, l, r, l.span()),
+            Expression::Le(l, r) => self.compile_binary(Op::Le, dest, l, r, l.span()),
+            Expression::Gt(l, r) => self.compile_binary(Op::Gt, dest, l, r, l.span()),
+            Expression::Ge(l, r) => self.compile_binary(Op::Ge, dest, l, r, l.span()),
+            Expression::And(l, r) | Expression::Or(l, r) => {
+                // `l` goes to a temporary, `dest` may be read by `r`
+                let base = self.next_reg;
+                let a = self.expr_reg(l);
+                let jump = if matches!(expr, Expression::And(..)) {
+                    Op::JumpIfFalse(a, 0)
+                } else {
+                    Op::JumpIfTrue(a, 0)
+                };
+                let short = self.emit(jump, l.span());
+                self.next_reg = base;
+                self.compile_expr(r, dest);
+                let end = self.emit(Op::Jump(0), None);
+                self.patch(short);
+                self.emit(Op::Bool(dest, matches!(expr, Expression::Or(..))), None);
+                self.patch(end);
+            }
+            Expression::Not(e) => self.compile_unary(Op::Not, dest, e),
+            Expression::Neg(e) => self.compile_unary(Op::Neg, dest, e),
+            Expression::Field(obj, name) => {
+                let base = self.next_reg;
+                let o = self.expr_reg(obj);
+                self.emit(Op::GetField(dest, o, name.data), Some(name.with_data(())));
+                self.next_reg = base;
+            }
+            Expression::MethodCall(obj, name, args) => {
+                let base = self.next_reg;
+                let first = self.alloc();
+                self.compile_expr(obj, first);
+                self.compile_args(args);
+                self.emit(Op::CallMethod(dest, name.data, first, args.len() as u32), expr.span());
+                self.next_reg = base;
+            }
+            Expression::Apply(f, args) => {
+                let base = self.next_reg;
+                let f_reg = self.alloc();
+                self.compile_expr(f, f_reg);
+                let first = self.compile_args(args);
+                self.emit(Op::CallValue(dest, f_reg, first, args.len() as u32), expr.span());
+                self.next_reg = base;
+            }
+            Expression::Call(name, args)
+                if self.program.classes.contains_key(&name.data)
+                    && !BUILT_INS.iter().any(|(n, _)| name.data == *n) =>
+            {
+                let base = self.next_reg;
+                let this = self.alloc();
+                self.compile_args(args);
+                self.emit(Op::New(dest, name.data, this, args.len() as u32), Some(name.with_data(())));
+                self.next_reg = base;
+            }
+            Expression::Call(name, args) => {
+                let base = self.next_reg;
+                let first = self.compile_args(args);
+                let global = self.program.global(name.data);
+                self.emit(Op::CallGlobal(dest, global, first, args.len() as u32), Some(name.with_data(())));
+                self.next_reg = base;
+            }
+            Expression::If(cond, then_body, else_body) => {
+                let base = self.next_reg;
+                let c = self.expr_reg(cond);
+                let to_else = self.emit(Op::JumpIfFalse(c, 0), cond.span());
+                self.next_reg = base;
+                self.compile_scoped(then_body, Some(dest));
+                let to_end = self.emit(Op::Jump(0), None);
+                self.patch(to_else);
+                match else_body {
+                    Some(e) => self.compile_scoped(e, Some(dest)),
+                    None => {
+                        self.emit(Op::Unit(dest), None);
+                    }
+                }
+                self.patch(to_end);
+            }
+            Expression::Block(b) => self.compile_scoped(b, Some(dest)),
+            Expression::Tuple(v) => {
+                let base = self.next_reg;
+                let first = self.compile_args(v);
+                self.emit(Op::Tuple(dest, first, v.len() as u32), expr.span());
+                self.next_reg = base;
+            }
+            Expression::Lambda(params, captures, block) => {
+                let proto = self.compile_func(Symbol::LAMBDA, params, captures, block);
+                self.emit(Op::Closure(dest, proto), expr.span());
+            }
+            Expression::Match(e, cases) => {
+                let base = self.next_reg;
+                let value = self.expr_reg(e);
+                let mut to_end = vec![];
+                for case in cases {
+                    let case_base = self.next_reg;
+                    let mut to_next = vec![];
+                    self.compile_pattern(&case.pattern, value, &mut to_next);
+                    if let Some(guard) = &case.guard {
+                        let guard_base = self.next_reg;
+                        let g = self.expr_reg(guard);
+                        to_next.push(self.emit(Op::JumpIfFalse(g, 0), guard.span()));
+                        self.next_reg = guard_base;
+                    }
+                    self.compile_stmts(&case.block, Some(dest));
+                    self.free_to(case_base);
+                    to_end.push(self.emit(Op::Jump(0), None));
+                    for at in to_next {
+                        self.patch(at);
+                    }
+                }
+                self.emit(Op::MatchError(value), e.span());
+                for at in to_end {
+                    self.patch(at);
+                }
+                self.next_reg = base;
+            }
+        }
+    }
+    /// test `value` against `pattern`, jumps that skip to the next case are
+    /// added to `to_next`. binds stay allocated until the case ends
+    fn compile_pattern(&mut self, pattern: &Pattern, value: Reg, to_next: &mut Vec<usize>) {
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Literal(e) => {
+                let base = self.next_reg;
+                let lit = self.expr_reg(e);
+                let eq = self.alloc();
+                self.emit(Op::Eq(eq, lit, value), e.span());
+                to_next.push(self.emit(Op::JumpIfFalse(eq, 0), None));
+                self.next_reg = base;
+            }
+            Pattern::Bind(slot) => {
+                let reg = self.alloc();
+                self.emit(Op::Move(reg, value), None);
+                self.bind(slot.data, reg);
+            }
+            Pattern::Tuple(v) => {
+                let test = self.alloc();
+                self.emit(Op::IsTuple(test, value, v.len() as u32), None);
+                to_next.push(self.emit(Op::JumpIfFalse(test, 0), None));
+                for (idx, p) in v.iter().enumerate() {
+                    let item = self.alloc();
+                    self.emit(Op::GetItem(item, value, idx as u32), None);
+                    self.compile_pattern(p, item, to_next);
+                }
+            }
+            Pattern::Extractor(name, _) => {
+                let msg = self.program.string(format!("extractor pattern `{}` is not supported yet", name.data));
+                self.emit(Op::Error(msg), Some(name.with_data(())));
+            }
+        }
+    }
+}
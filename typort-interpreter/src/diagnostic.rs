@@ -0,0 +1,326 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::value::RuntimeError;
+use crate::{FileId, Span};
+
+type Line = usize;
+type Col = usize;
+/// zero based, the end is exclusive
+pub type Range = ((Line, Col), (Line, Col));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// a location related to a diagnostic, e.g. where a value was defined
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub file: FileId,
+    pub range: Range,
+    pub msg: String,
+}
+
+/// a fix that replaces the code at `range`
+#[derive(Clone, Debug)]
+pub struct Suggestion {
+    pub msg: String,
+    pub file: FileId,
+    pub range: Range,
+    pub replacement: String,
+}
+
+/// a problem found in the source, it points to the code at `range` and may
+/// point to other places with `labels`
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// e.g. `E0104`, the same kind of problem always has the same code
+    pub code: Option<&'static str>,
+    pub msg: String,
+    pub file: FileId,
+    pub range: Range,
+    /// what is shown under the code at `range`
+    pub label: Option<String>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    /// an error
+    pub fn new<T>(msg: String, at: &Span<T>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            msg,
+            file: at.file,
+            range: at.range,
+            label: None,
+            labels: vec![],
+            notes: vec![],
+            suggestions: vec![],
+        }
+    }
+    pub fn warning<T>(msg: String, at: &Span<T>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Self::new(msg, at)
+        }
+    }
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+    /// the text under the code the diagnostic points to
+    pub fn with_label(mut self, msg: impl Into<String>) -> Self {
+        self.label = Some(msg.into());
+        self
+    }
+    /// point to another place
+    pub fn with_secondary<T>(mut self, at: &Span<T>, msg: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            file: at.file,
+            range: at.range,
+            msg: msg.into(),
+        });
+        self
+    }
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+    pub fn with_suggestion<T>(mut self, msg: impl Into<String>, at: &Span<T>, replacement: impl Into<String>) -> Self {
+        self.suggestions.push(Suggestion {
+            msg: msg.into(),
+            file: at.file,
+            range: at.range,
+            replacement: replacement.into(),
+        });
+        self
+    }
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+    /// the syntax error the parser stopped at
+    pub fn from_parse_error(e: &typort_parser::ParseError) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: Some("E0001"),
+            msg: e.msg.clone(),
+            file: e.file,
+            range: e.range,
+            label: Some("unexpected code here".to_owned()),
+            labels: vec![],
+            notes: vec![],
+            suggestions: vec![],
+        }
+    }
+    /// a runtime error, the functions it happened in are labelled
+    pub fn from_runtime_error(e: &RuntimeError) -> Self {
+        let mut diag = Diagnostic::new(e.msg.clone(), &e.at);
+        if let Some(frame) = e.trace.first() {
+            diag = diag.with_label(format!("in `{}`", frame.func));
+        }
+        for (frame, callee) in e.trace.iter().skip(1).zip(&e.trace) {
+            diag = diag.with_secondary(&frame.at, format!("`{}` called here, in `{}`", callee.func, frame.func));
+        }
+        diag
+    }
+
+    /// show the diagnostic with the code it points to, like
+    /// ```text
+    /// error[E0104]: "x" is immutable
+    ///  --> main.typort:3:5
+    ///   |
+    /// 3 |     x = 2
+    ///   |     ^ assigned here
+    /// ```
+    pub fn render(&self, sources: &dyn Sources) -> String {
+        let mut out = String::new();
+        match self.code {
+            Some(code) => write!(out, "{}[{code}]: {}", self.severity, self.msg),
+            None => write!(out, "{}: {}", self.severity, self.msg),
+        }.unwrap();
+        let primary = Label {
+            file: self.file,
+            range: self.range,
+            msg: self.label.clone().unwrap_or_default(),
+        };
+        let labels: Vec<_> = std::iter::once((&primary, '^')).chain(self.labels.iter().map(|l| (l, '-'))).collect();
+        let width = labels.iter().map(|(l, _)| (l.range.0.0 + 1).to_string().len()).max().unwrap_or(1);
+        let pad = " ".repeat(width);
+        let mut last_file = None;
+        for (label, mark) in labels {
+            let ((line, col), (end_line, end_col)) = label.range;
+            // the path is shown again when a label is in another file
+            if last_file != Some(label.file) {
+                let arrow = if last_file.is_none() { "-->" } else { ":::" };
+                let path = sources.path(label.file).map(|p| p.display().to_string()).unwrap_or("<unknown>".to_owned());
+                write!(out, "\n{pad}{arrow} {path}:{}:{}", line + 1, col + 1).unwrap();
+                write!(out, "\n{pad} |").unwrap();
+            }
+            last_file = Some(label.file);
+            let Some(text) = sources.text(label.file).and_then(|t| t.lines().nth(line)) else {
+                if !label.msg.is_empty() {
+                    write!(out, "\n{pad} = {}", label.msg).unwrap();
+                }
+                continue;
+            };
+            let len = if end_line == line { end_col.saturating_sub(col) } else { text.chars().count().saturating_sub(col) };
+            let indent: String = text.chars().take(col).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+            write!(out, "\n{:>width$} | {text}", line + 1).unwrap();
+            write!(out, "\n{pad} | {indent}{}", mark.to_string().repeat(len.max(1))).unwrap();
+            if !label.msg.is_empty() {
+                write!(out, " {}", label.msg).unwrap();
+            }
+        }
+        for note in self.notes.iter() {
+            write!(out, "\n{pad} = note: {note}").unwrap();
+        }
+        for s in self.suggestions.iter() {
+            write!(out, "\n{pad} = help: {}: `{}`", s.msg, s.replacement).unwrap();
+        }
+        out
+    }
+    /// one line of JSON for scripts, lines and columns start from 1
+    pub fn to_json(&self, sources: &dyn Sources) -> String {
+        let span = |file: FileId, range: Range, primary: bool, label: &Option<String>| {
+            format!(
+                "{{\"file\":{},\"line_start\":{},\"column_start\":{},\"line_end\":{},\"column_end\":{},\"primary\":{primary},\"label\":{}}}",
+                json_path(sources, file),
+                range.0.0 + 1,
+                range.0.1 + 1,
+                range.1.0 + 1,
+                range.1.1 + 1,
+                label.as_deref().map(json_string).unwrap_or("null".to_owned()),
+            )
+        };
+        let spans: Vec<_> = std::iter::once(span(self.file, self.range, true, &self.label))
+            .chain(self.labels.iter().map(|l| span(l.file, l.range, false, &Some(l.msg.clone()))))
+            .collect();
+        let notes: Vec<_> = self.notes.iter().map(|n| json_string(n)).collect();
+        let suggestions: Vec<_> = self.suggestions.iter().map(|s| format!(
+            "{{\"message\":{},\"replacement\":{},\"span\":{}}}",
+            json_string(&s.msg),
+            json_string(&s.replacement),
+            span(s.file, s.range, false, &None),
+        )).collect();
+        format!(
+            "{{\"severity\":\"{}\",\"code\":{},\"message\":{},\"spans\":[{}],\"notes\":[{}],\"suggestions\":[{}],\"rendered\":{}}}",
+            self.severity,
+            self.code.map(json_string).unwrap_or("null".to_owned()),
+            json_string(&self.msg),
+            spans.join(","),
+            notes.join(","),
+            suggestions.join(","),
+            json_string(&self.render(sources)),
+        )
+    }
+}
+
+/// where diagnostics find the files they point into
+pub trait Sources {
+    fn path(&self, file: FileId) -> Option<&Path>;
+    fn text(&self, file: FileId) -> Option<&str>;
+}
+
+/// files by their `FileId`, the first one added is `FileId(0)`
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<(PathBuf, String)>,
+}
+
+impl SourceMap {
+    pub fn add(&mut self, path: impl Into<PathBuf>, text: impl Into<String>) -> FileId {
+        self.files.push((path.into(), text.into()));
+        FileId(self.files.len() as u32 - 1)
+    }
+}
+
+impl Sources for SourceMap {
+    fn path(&self, file: FileId) -> Option<&Path> {
+        self.files.get(file.0 as usize).map(|f| f.0.as_path())
+    }
+    fn text(&self, file: FileId) -> Option<&str> {
+        self.files.get(file.0 as usize).map(|f| f.1.as_str())
+    }
+}
+
+/// how the cli shows diagnostics
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// annotated source on stderr
+    #[default]
+    Human,
+    /// one JSON object per line on stdout
+    Json,
+}
+
+/// report diagnostics, returns if any of them is an error
+pub fn emit(diags: &[Diagnostic], sources: &dyn Sources, format: MessageFormat) -> bool {
+    for d in diags {
+        match format {
+            MessageFormat::Human => eprintln!("{}\n", d.render(sources)),
+            MessageFormat::Json => println!("{}", d.to_json(sources)),
+        }
+    }
+    diags.iter().any(Diagnostic::is_error)
+}
+
+/// report an error which is not about any code, e.g. a missing file
+pub fn emit_message(msg: &str, format: MessageFormat) {
+    emit_line("error", msg, format)
+}
+
+/// report a note which is not about any code, e.g. how the code was run
+pub fn emit_note(msg: &str, format: MessageFormat) {
+    emit_line("note", msg, format)
+}
+
+fn emit_line(severity: &str, msg: &str, format: MessageFormat) {
+    match format {
+        MessageFormat::Human => eprintln!("{severity}: {msg}"),
+        MessageFormat::Json => println!(
+            "{{\"severity\":\"{severity}\",\"code\":null,\"message\":{},\"spans\":[],\"notes\":[],\"suggestions\":[],\"rendered\":{}}}",
+            json_string(msg),
+            json_string(&format!("{severity}: {msg}")),
+        ),
+    }
+}
+
+fn json_path(sources: &dyn Sources, file: FileId) -> String {
+    sources.path(file)
+        .map(|p| json_string(&p.display().to_string()))
+        .unwrap_or("null".to_owned())
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -1,303 +1,1339 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use typort_parser::ast::{self, ClassKind, Ident, Symbol, TypeExpr};
+
+use crate::index::{anonymous_class, Call, Def, DefId, SymbolIndex, SymbolKind};
+use crate::ty::Type;
 use crate::{Diagnostic, Span};
 
 #[derive(Debug, Clone)]
 pub enum Expression {
     Int(Span<i64>),
     String(Span<String>),
-    Bool(bool),
-    Name(Span<String>),
+    Bool(Span<bool>),
+    Name(Ident),
+    Field(Box<Expression>, Ident),
     Add(Box<Expression>, Box<Expression>),
     Sub(Box<Expression>, Box<Expression>),
     Mul(Box<Expression>, Box<Expression>),
     Div(Box<Expression>, Box<Expression>),
+    Mod(Box<Expression>, Box<Expression>),
     Eq(Box<Expression>, Box<Expression>),
     Neq(Box<Expression>, Box<Expression>),
-    Call(Span<String>, Vec<Expression>),
-    ObjCall(Span<String>, Span<String>, Vec<Expression>),
+    Lt(Box<Expression>, Box<Expression>),
+    Le(Box<Expression>, Box<Expression>),
+    Gt(Box<Expression>, Box<Expression>),
+    Ge(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Neg(Box<Expression>),
+    Call(Ident, Vec<Expression>),
+    MethodCall(Box<Expression>, Ident, Vec<Expression>),
     If(Box<Expression>, Vec<Stmt>, Option<Vec<Stmt>>),
+    Block(Vec<Stmt>),
+    Tuple(Vec<Expression>),
+    New(Ident, Vec<Expression>, Option<Vec<Stmt>>),
+    Lambda(Vec<(Ident, Option<TypeExpr>)>, Vec<Stmt>),
+    Match(Box<Expression>, Vec<Case>),
+}
+
+impl Expression {
+    /// where diagnostics about this expression point to
+    pub fn span(&self) -> Option<Span<()>> {
+        match self {
+            Expression::Int(x) => Some(x.with_data(())),
+            Expression::String(x) => Some(x.with_data(())),
+            Expression::Bool(x) => Some(x.with_data(())),
+            Expression::Name(x) | Expression::Call(x, _) | Expression::New(x, _, _) => Some(x.with_data(())),
+            Expression::Field(a, _)
+            | Expression::Add(a, _)
+            | Expression::Sub(a, _)
+            | Expression::Mul(a, _)
+            | Expression::Div(a, _)
+            | Expression::Mod(a, _)
+            | Expression::Eq(a, _)
+            | Expression::Neq(a, _)
+            | Expression::Lt(a, _)
+            | Expression::Le(a, _)
+            | Expression::Gt(a, _)
+            | Expression::Ge(a, _)
+            | Expression::And(a, _)
+            | Expression::Or(a, _)
+            | Expression::Not(a)
+            | Expression::Neg(a)
+            | Expression::MethodCall(a, _, _)
+            | Expression::If(a, _, _)
+            | Expression::Match(a, _) => a.span(),
+            Expression::Block(b) => b.iter().find_map(Stmt::span),
+            Expression::Lambda(params, b) => params
+                .first()
+                .map(|p| p.0.with_data(()))
+                .or_else(|| b.iter().find_map(Stmt::span)),
+            Expression::Tuple(v) => v.iter().find_map(Expression::span),
+        }
+    }
+    /// call `f` on this expression and then on the ones nested in it
+    pub fn visit(&self, f: &mut dyn FnMut(&Expression)) {
+        f(self);
+        let visit_all = |stmts: &[Stmt], f: &mut dyn FnMut(&Expression)| {
+            stmts.iter().for_each(|s| s.visit(f))
+        };
+        match self {
+            Expression::Int(_) | Expression::String(_) | Expression::Bool(_) | Expression::Name(_) => {}
+            Expression::Field(a, _) | Expression::Not(a) | Expression::Neg(a) => a.visit(f),
+            Expression::Add(a, b)
+            | Expression::Sub(a, b)
+            | Expression::Mul(a, b)
+            | Expression::Div(a, b)
+            | Expression::Mod(a, b)
+            | Expression::Eq(a, b)
+            | Expression::Neq(a, b)
+            | Expression::Lt(a, b)
+            | Expression::Le(a, b)
+            | Expression::Gt(a, b)
+            | Expression::Ge(a, b)
+            | Expression::And(a, b)
+            | Expression::Or(a, b) => {
+                a.visit(f);
+                b.visit(f);
+            }
+            Expression::Call(_, args) | Expression::Tuple(args) => args.iter().for_each(|a| a.visit(f)),
+            Expression::MethodCall(a, _, args) => {
+                a.visit(f);
+                args.iter().for_each(|a| a.visit(f));
+            }
+            Expression::If(c, a, b) => {
+                c.visit(f);
+                visit_all(a, f);
+                visit_all(b.as_deref().unwrap_or_default(), f);
+            }
+            Expression::Block(b) | Expression::Lambda(_, b) => visit_all(b, f),
+            Expression::New(_, args, body) => {
+                args.iter().for_each(|a| a.visit(f));
+                visit_all(body.as_deref().unwrap_or_default(), f);
+            }
+            Expression::Match(e, cases) => {
+                e.visit(f);
+                for case in cases {
+                    if let Some(g) = &case.guard {
+                        g.visit(f);
+                    }
+                    visit_all(&case.block, f);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub block: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Literal(Expression),
+    Bind(Ident),
+    Typed(Ident, TypeExpr),
+    Tuple(Vec<Pattern>),
+    Extractor(Ident, Vec<Pattern>),
 }
 
 #[derive(Debug, Clone)]
 pub struct Func {
-    pub name: Span<String>,
-    pub params: Vec<(Span<String>, Span<String>)>,
-    pub return_type: Option<Span<String>>,
+    pub name: Ident,
+    pub params: Vec<(Ident, TypeExpr)>,
+    pub return_type: Option<TypeExpr>,
     pub block: Vec<Stmt>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Expr(Expression),
-    Let(Span<String>, Expression),
-    Assign(Span<String>, Expression),
+    Let(Ident, Option<TypeExpr>, Expression),
+    /// an abstract `val` or `var`, only at the top of a class body
+    Decl(Ident, TypeExpr),
+    Assign(Ident, Expression),
     Return(Expression),
-    While(Expression, Vec<Stmt>),
+    Break(Span<()>),
+    Continue(Span<()>),
+    /// condition, body, and the statements run after each iteration, even
+    /// when the body does `continue`
+    While(Expression, Vec<Stmt>, Vec<Stmt>),
     Block(Vec<Stmt>),
     Func {
-        name: Span<String>,
-        params: Vec<(Span<String>, Span<String>)>,
-        return_type: Option<Span<String>>,
-        block: Vec<Stmt>,
+        overrides: bool,
+        name: Ident,
+        params: Vec<(Ident, TypeExpr)>,
+        return_type: Option<TypeExpr>,
+        /// `None` for an abstract method, only at the top of a class body
+        block: Option<Vec<Stmt>>,
+    }
+}
+
+impl Stmt {
+    pub fn span(&self) -> Option<Span<()>> {
+        match self {
+            Stmt::Expr(e) | Stmt::Return(e) | Stmt::While(e, _, _) => e.span(),
+            Stmt::Let(a, _, _) | Stmt::Decl(a, _) | Stmt::Assign(a, _) | Stmt::Func { name: a, .. } => {
+                Some(a.with_data(()))
+            }
+            Stmt::Break(a) | Stmt::Continue(a) => Some(a.clone()),
+            Stmt::Block(b) => b.iter().find_map(Stmt::span),
+        }
+    }
+    /// call `f` on every expression in this statement, outer ones first
+    pub fn visit(&self, f: &mut dyn FnMut(&Expression)) {
+        match self {
+            Stmt::Expr(e) | Stmt::Let(_, _, e) | Stmt::Assign(_, e) | Stmt::Return(e) => e.visit(f),
+            Stmt::Decl(_, _) | Stmt::Break(_) | Stmt::Continue(_) => {}
+            Stmt::While(c, body, step) => {
+                c.visit(f);
+                body.iter().chain(step).for_each(|s| s.visit(f));
+            }
+            Stmt::Block(b) | Stmt::Func { block: Some(b), .. } => b.iter().for_each(|s| s.visit(f)),
+            Stmt::Func { block: None, .. } => {}
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Class {
-    //TODO: add type for object, class, case class, abstract class
-    pub name: Span<String>,
-    pub args: Vec<(Span<String>, Span<String>)>,
-    pub extends: Option<Span<String>>,
-    pub with: Vec<Span<String>>,
-    //pub value: Vec<Span<String>>,
-    pub func: HashMap<String, usize>,
+    //TODO: add type for case class
+    /// objects are `ClassKind::Class`
+    pub kind: ClassKind,
+    pub name: Ident,
+    pub args: Vec<(Ident, TypeExpr)>,
+    pub extends: Option<Ident>,
+    pub with: Vec<Ident>,
+    //pub value: Vec<Ident>,
+    pub func: HashMap<Symbol, usize>,
     pub block: Vec<Stmt>,
 }
 
 type Mutable = bool;
 
+/// a name in scope
+#[derive(Clone, Copy)]
+struct Binding {
+    mutable: Mutable,
+    /// `None` for `this`
+    def: Option<DefId>,
+    /// a `def` in a block, it is only in scope to find its uses
+    func: bool,
+}
+
 /// convert parser result to hir
 /// 1. for -> while
 /// 2. xxx() -> xxx.apply()
+/// 3. a op b -> a.op(b)
 struct HirConverter {
-    values: Vec<HashMap<String, Mutable>>,
+    /// the values in scope
+    values: Vec<HashMap<Symbol, Binding>>,
+    /// methods of the class being converted, including inherited ones
+    methods: HashMap<Symbol, DefId>,
+    /// traits and abstract classes, they can not be instantiated
+    abstract_classes: HashSet<Symbol>,
+    /// loops around the current statement inside the current function
+    loops: usize,
+    /// if the current statement is inside a `def` or a lambda
+    in_func: bool,
     diag: Vec<Diagnostic>,
+    index: SymbolIndex,
+    /// the class being converted
+    class: Option<Symbol>,
+    /// the anonymous class whose body is being converted, and the depth of
+    /// the scope of its members
+    anonymous: Option<(Symbol, usize)>,
+    /// the clock domains of the `ClockingArea`s around the current statement
+    clock_domains: Vec<String>,
 }
 
 impl HirConverter {
     pub fn new() -> Self {
-        HirConverter { values: vec![Default::default()], diag: vec![] }
-    }
-    pub fn add_param(
-        &'_ mut self,
-        param: &[(
-            typort_parser::simple_example::Span<String>,
-            typort_parser::simple_example::Span<String>,
-        )],
-    ) {
-        param.iter().for_each(|p| {
-            self.values.last_mut().unwrap().insert(p.0.data.to_owned(), false);
+        HirConverter {
+            values: vec![Default::default()],
+            methods: Default::default(),
+            abstract_classes: Default::default(),
+            loops: 0,
+            in_func: false,
+            diag: vec![],
+            index: Default::default(),
+            class: None,
+            anonymous: None,
+            clock_domains: vec![],
+        }
+    }
+    /// convert the body of a `def` or a lambda, loops outside of it can not be left from inside
+    fn convert_func_body<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let loops = std::mem::replace(&mut self.loops, 0);
+        let in_func = std::mem::replace(&mut self.in_func, true);
+        let ret = f(self);
+        self.loops = loops;
+        self.in_func = in_func;
+        ret
+    }
+    fn convert_loop_body(&mut self, block: ast::Block) -> Vec<Stmt> {
+        self.loops += 1;
+        let ret = self.convert_block(block);
+        self.loops -= 1;
+        ret
+    }
+    /// search from the innermost scope, so inner values shadow outer ones
+    fn lookup(&self, name: Symbol) -> Option<Mutable> {
+        self.values.iter().rev().find_map(|scope| scope.get(&name).filter(|b| !b.func).map(|b| b.mutable))
+    }
+    /// the definition `name` refers to, a value, a `def` or a method
+    fn lookup_def(&self, name: Symbol) -> Option<DefId> {
+        self.values.iter().rev()
+            .find_map(|scope| scope.get(&name).and_then(|b| b.def))
+            .or_else(|| self.methods.get(&name).copied())
+    }
+    fn defined_at(&self, name: Symbol) -> Option<Span<()>> {
+        self.values.iter().rev()
+            .find_map(|scope| scope.get(&name).filter(|b| !b.func).and_then(|b| b.def))
+            .map(|def| self.index.defs[def].at.clone())
+    }
+    /// shadowing an outer value is fine, defining a name twice in one scope is not
+    fn define(&mut self, name: &Ident, mutable: Mutable, kind: SymbolKind) -> DefId {
+        // the members of a class are in the index before its body is converted
+        let def = self.index.def_at(name).unwrap_or_else(|| {
+            let (kind, class) = match self.anonymous {
+                Some((class, depth)) if depth == self.values.len() && kind == SymbolKind::Local => {
+                    (SymbolKind::Field, Some(class))
+                }
+                _ => (kind, self.class),
+            };
+            self.index.add(Def { mutable, ..Def::new(name, kind, class) })
         });
+        let binding = Binding { mutable, def: Some(def), func: false };
+        if let Some(first) = self.values.last_mut().unwrap().insert(name.data, binding).filter(|b| !b.func) {
+            let first = &self.index.defs[first.def.unwrap()].at;
+            self.diag.push(
+                Diagnostic::new(format!("redefine {}", name.data), name)
+                    .with_code("E0101")
+                    .with_label("defined again here")
+                    .with_secondary(first, "first defined here")
+                    .with_note("a value may shadow one of an outer block, not one of the same block"),
+            )
+        }
+        def
+    }
+    /// keep what a `val` or a `var` is set to, for the type and the hardware it is
+    fn describe(&mut self, def: DefId, ty: &Option<TypeExpr>, value: &Expression) {
+        if let Some(ty) = ty {
+            self.ref_type(ty);
+        }
+        let def = &mut self.index.defs[def];
+        def.ty = ty.clone();
+        def.value = Some(value.clone());
+        def.clock_domain = self.clock_domains.last().cloned();
+    }
+    /// record a use of a name if it is defined
+    fn use_name(&mut self, name: &Ident) {
+        if let Some(def) = self.lookup_def(name.data) {
+            self.index.add_ref(name, def)
+        }
+    }
+    /// record the uses of the classes named in a type
+    fn ref_type(&mut self, ty: &TypeExpr) {
+        match ty {
+            TypeExpr::Name(n, args) => {
+                if let Some(def) = self.index.classes.get(&n.data) {
+                    self.index.add_ref(n, *def)
+                }
+                args.iter().for_each(|t| self.ref_type(t))
+            }
+            TypeExpr::Tuple(v) => v.iter().for_each(|t| self.ref_type(t)),
+            TypeExpr::Func(a, b) => {
+                a.iter().for_each(|t| self.ref_type(t));
+                self.ref_type(b)
+            }
+        }
+    }
+    fn add_call(&mut self, callee: Option<DefId>, at: &Ident, args: &[Expression]) {
+        if let Some(callee) = callee {
+            self.index.calls.push(Call {
+                callee,
+                at: at.with_data(()),
+                args: args.to_vec(),
+            })
+        }
+    }
+    /// the arguments of a call in the order of the parameters of `callee`, a
+    /// named one is put where its parameter is. they are checked against the
+    /// parameters of a function or a class. the names cannot be given when
+    /// the parameters are not known
+    fn convert_args(&mut self, callee: Option<DefId>, at: &Ident, args: Vec<ast::Expression>) -> Vec<Expression> {
+        let mut positional = vec![];
+        let mut named = vec![];
+        for arg in args {
+            match arg {
+                ast::Expression::Named(name, a) => {
+                    let a = self.convert_expr(*a);
+                    named.push((name, a))
+                }
+                a => positional.push(self.convert_expr(a)),
+            }
+        }
+        // a value holding a closure is called with `apply`, its parameters are not known
+        let callee = callee.filter(|def| {
+            matches!(self.index.defs[*def].kind, SymbolKind::Func | SymbolKind::Method | SymbolKind::Class)
+        });
+        let Some(callee) = callee else {
+            for (name, _) in named.iter() {
+                self.diag.push(
+                    Diagnostic::new(format!("the parameters of \"{}\" are not known, \"{}\" cannot be given by name", at.data, name.data), name)
+                        .with_code("E0115"),
+                );
+            }
+            positional.extend(named.into_iter().map(|(_, a)| a));
+            return positional;
+        };
+        let params = self.index.defs[callee].params.clone();
+        let note = format!("the signature is {}", self.index.signature(callee));
+        let given = positional.len() + named.len();
+        let mut slots: Vec<Option<Expression>> = positional.into_iter().map(Some).collect();
+        if slots.len() < params.len() {
+            slots.resize(params.len(), None);
+        }
+        for (name, a) in named {
+            let position = params.iter().position(|(p, _)| p.data == name.data);
+            if let Some(i) = position.filter(|i| slots[*i].is_none()) {
+                slots[i] = Some(a);
+                continue;
+            }
+            match position {
+                Some(_) => self.diag.push(
+                    Diagnostic::new(format!("\"{}\" is given more than once", name.data), &name)
+                        .with_code("E0115")
+                        .with_note(note.clone()),
+                ),
+                None => self.diag.push(
+                    Diagnostic::new(format!("\"{}\" has no parameter named \"{}\"", at.data, name.data), &name)
+                        .with_code("E0115")
+                        .with_note(note.clone()),
+                ),
+            }
+        }
+        let missing = params.iter().zip(&slots).find(|(_, a)| a.is_none()).map(|(p, _)| p.0.data);
+        let msg = match missing {
+            _ if given != params.len() => Some(format!("\"{}\" takes {} arguments but {given} were given", at.data, params.len())),
+            // as many arguments as parameters, but one is misnamed
+            Some(p) => Some(format!("\"{}\" is missing an argument for \"{p}\"", at.data)),
+            None => None,
+        };
+        if let Some(msg) = msg {
+            self.diag.push(Diagnostic::new(msg, at).with_code("E0114").with_note(note.clone()));
+        }
+        for (a, (_, ty)) in slots.iter().zip(&params) {
+            let (Some(a), expected) = (a, Type::of_type_expr(ty)) else { continue };
+            match Type::of(a) {
+                Some(found) if !expected.accepts(&found) => self.diag.push(
+                    Diagnostic::new(format!("expected {expected}, found {found}"), &a.span().unwrap_or(at.with_data(())))
+                        .with_code("E0116")
+                        .with_note(note.clone()),
+                ),
+                _ => {}
+            }
+        }
+        slots.into_iter().flatten().collect()
+    }
+    /// the arguments of a call of `method` on `receiver`, named ones are put
+    /// in the order of its parameters when its class is known
+    fn convert_method_args(&mut self, receiver: &Expression, method: &Ident, args: Vec<ast::Expression>) -> Vec<Expression> {
+        if !args.iter().any(|a| matches!(a, ast::Expression::Named(..))) {
+            return args.into_iter().map(|x| self.convert_expr(x)).collect();
+        }
+        let callee = self.index.class_of(receiver, self.class).and_then(|c| self.index.member(c, method.data));
+        self.convert_args(callee, method, args)
+    }
+    /// record the use of a member of the class of `receiver`
+    fn use_member(&mut self, receiver: &Expression, member: &Ident) {
+        self.index.add_member_use(receiver, member, self.class)
+    }
+    /// the value or method in scope whose name is closest to `name`
+    fn similar_name(&self, name: Symbol) -> Option<Symbol> {
+        let name = name.as_str();
+        self.values.iter()
+            .flat_map(|scope| scope.keys())
+            .chain(self.methods.keys())
+            .map(|x| (edit_distance(name, x.as_str()), *x))
+            .filter(|(d, x)| *d <= name.len().max(x.as_str().len()).div_ceil(3) && *x != "this")
+            .min_by_key(|(d, x)| (*d, x.as_str()))
+            .map(|(_, x)| x)
+    }
+    fn convert_block(&mut self, block: ast::Block) -> Vec<Stmt> {
+        self.values.push(Default::default());
+        let ret = block.0.into_iter().map(|x| self.convert_stmt(x)).collect();
+        self.values.pop();
+        ret
+    }
+    /// like `convert_block`, but abstract members are allowed
+    fn convert_class_body(&mut self, block: ast::Block) -> Vec<Stmt> {
+        self.values.push(Default::default());
+        let ret = block.0
+            .into_iter()
+            .map(|x| match x {
+                ast::Stmt::Decl(a, t) => {
+                    self.ref_type(&t);
+                    Stmt::Decl(a, t)
+                }
+                ast::Stmt::Func(f) if f.block.is_none() => {
+                    f.params.iter().map(|p| &p.1).chain(&f.return_type).for_each(|t| self.ref_type(t));
+                    Stmt::Func {
+                        overrides: f.overrides,
+                        name: f.name,
+                        params: f.params,
+                        return_type: f.return_type,
+                        block: None,
+                    }
+                }
+                x => self.convert_stmt(x),
+            })
+            .collect();
+        self.values.pop();
+        ret
+    }
+    fn check_instantiate(&mut self, name: &Ident) {
+        if self.abstract_classes.contains(&name.data) {
+            self.diag.push(
+                Diagnostic::new(format!("\"{}\" is abstract and can not be instantiated", name.data), name)
+                    .with_code("E0102")
+                    .with_note("only a class extending it and implementing its abstract members can be"),
+            )
+        }
     }
     pub fn convert_stmt(
         &mut self,
-        value: typort_parser::simple_example::Stmt,
+        value: ast::Stmt,
     ) -> Stmt {
         match value {
-            typort_parser::simple_example::Stmt::Expr(e) => Stmt::Expr(self.convert_expr(e)),
-            typort_parser::simple_example::Stmt::Val(a, b) => {
-                if self.values.last_mut().unwrap().insert(a.data.to_owned(), false).is_some() {
-                    self.diag.push(Diagnostic {
-                        msg: format!("redefine {}", a.data),
-                        range: a.range,
-                    })
-                }
-                Stmt::Let(a.into(), self.convert_expr(b))
+            ast::Stmt::Expr(e) => Stmt::Expr(self.convert_expr(e)),
+            ast::Stmt::Val(a, t, b) => {
+                let b = self.convert_expr(b);
+                let def = self.define(&a, false, SymbolKind::Local);
+                self.describe(def, &t, &b);
+                Stmt::Let(a, t, b)
             }
-            typort_parser::simple_example::Stmt::Var(a, b) => {
-                if self.values.last_mut().unwrap().insert(a.data.to_owned(), true).is_some() {
-                    self.diag.push(Diagnostic {
-                        msg: format!("redefine {}", a.data),
-                        range: a.range,
-                    })
-                }
-                Stmt::Let(a.into(), self.convert_expr(b))
+            ast::Stmt::Var(a, t, b) => {
+                let b = self.convert_expr(b);
+                let def = self.define(&a, true, SymbolKind::Local);
+                self.describe(def, &t, &b);
+                Stmt::Let(a, t, b)
             }
-            typort_parser::simple_example::Stmt::Assign(a, b) => {
-                if let Some(mutable) = self.values.last().unwrap().get(&a.data) {
+            ast::Stmt::Decl(a, _) => {
+                self.diag.push(
+                    Diagnostic::new(format!("\"{}\" has no value", a.data), &a)
+                        .with_code("E0103")
+                        .with_note("abstract members can only be declared at the top of a class body"),
+                );
+                Stmt::Block(vec![])
+            }
+            ast::Stmt::Assign(a, b) => {
+                self.use_name(&a);
+                if let Some(mutable) = self.lookup(a.data) {
                     if !mutable {
-                        self.diag.push(Diagnostic {
-                            msg: format!("\"{}\" is immutable", a.data),
-                            range: a.range,
-                        })
+                        let mut diag = Diagnostic::new(format!("\"{}\" is immutable", a.data), &a)
+                            .with_code("E0104")
+                            .with_label("assigned here");
+                        if let Some(at) = self.defined_at(a.data).filter(|at| at.len > 0) {
+                            diag = diag.with_secondary(&at, "defined with `val` here");
+                        }
+                        self.diag.push(diag.with_note("declare it with `var` to assign to it"))
                     }
                 } else {
-                    self.diag.push(Diagnostic {
-                        msg: format!("\"{}\" not defined", a.data),
-                        range: a.range,
-                    })
+                    let mut diag = Diagnostic::new(format!("\"{}\" not defined", a.data), &a).with_code("E0105");
+                    if let Some(similar) = self.similar_name(a.data) {
+                        diag = diag.with_suggestion("a value with a similar name exists", &a, similar.as_str());
+                    }
+                    self.diag.push(diag)
+                }
+                Stmt::Assign(a, self.convert_expr(b))
+            }
+            ast::Stmt::Return(e) => {
+                if !self.in_func {
+                    if let Some(at) = e.span() {
+                        self.diag.push(Diagnostic::new("return outside of a function".to_owned(), &at).with_code("E0106"))
+                    }
+                }
+                Stmt::Return(self.convert_expr(e))
+            }
+            ast::Stmt::Break(a) => {
+                if self.loops == 0 {
+                    self.diag.push(Diagnostic::new("break outside of a loop".to_owned(), &a).with_code("E0107"))
                 }
-                Stmt::Assign(a.into(), self.convert_expr(b))
+                Stmt::Break(a)
             }
-            typort_parser::simple_example::Stmt::Return(e) => Stmt::Return(self.convert_expr(e)),
-            typort_parser::simple_example::Stmt::While(e, v) => Stmt::While(
+            ast::Stmt::Continue(a) => {
+                if self.loops == 0 {
+                    self.diag.push(Diagnostic::new("continue outside of a loop".to_owned(), &a).with_code("E0107"))
+                }
+                Stmt::Continue(a)
+            }
+            ast::Stmt::While(e, v) => Stmt::While(
                 self.convert_expr(e),
-                v.0.into_iter().map(|x| self.convert_stmt(x)).collect(),
+                self.convert_loop_body(v),
+                vec![],
             ),
-            typort_parser::simple_example::Stmt::For(v, from, to, b) => {
-                let name = v.data.to_owned();
-                if self.values.last_mut().unwrap().insert(name.to_owned(), true).is_some() {
-                    self.diag.push(Diagnostic {
-                        msg: format!("redefine {name}"),
-                        range: v.range,
-                    })
-                }
+            ast::Stmt::For(v, range, b) => {
+                let (from, inclusive, to) = match range {
+                    ast::Expression::Infix(from, op, to)
+                        if op.data == Symbol::UNTIL || op.data == Symbol::TO =>
+                    {
+                        (*from, op.data == Symbol::TO, *to)
+                    }
+                    _ => {
+                        self.diag.push(
+                            Diagnostic::new("only `a until b` and `a to b` are supported in for".to_owned(), &v)
+                                .with_code("E0108"),
+                        );
+                        return Stmt::Block(vec![]);
+                    }
+                };
+                // the range is evaluated once, before the loop, in a slot
+                // no code can name
+                let from = self.convert_expr(from);
+                let to = self.convert_expr(to);
+                let end = v.with_data(Symbol::intern(&format!("{}$end", v.data)));
+                self.values.push(Default::default());
+                self.define(&v, false, SymbolKind::Local);
+                let cond = if inclusive { Expression::Le } else { Expression::Lt };
                 let ret = Stmt::Block(vec![
-                    Stmt::Let(v.clone().into(), self.convert_expr(from)),
+                    Stmt::Let(v.clone(), None, from),
+                    Stmt::Let(end.clone(), None, to),
                     Stmt::While(
-                        Expression::Neq(
-                            Box::new(Expression::Name(v.clone().into())),
-                            Box::new(self.convert_expr(to)),
+                        cond(
+                            Box::new(Expression::Name(v.clone())),
+                            Box::new(Expression::Name(end)),
                         ),
-                        [
-                            b.0.into_iter().map(|x| self.convert_stmt(x)).collect(),
-                            vec![Stmt::Assign(
-                                v.clone().into(),
-                                Expression::Add(
-                                    Box::new(Expression::Name(v.into())),
-                                    Box::new(Expression::Int(Span { data: 1 })),
-                                ),
-                            )],
-                        ]
-                        .concat(),
+                        self.convert_loop_body(b),
+                        vec![Stmt::Assign(
+                            v.clone(),
+                            Expression::Add(
+                                Box::new(Expression::Name(v.clone())),
+                                Box::new(Expression::Int(v.with_data(1))),
+                            ),
+                        )],
                     ),
                 ]);
-                self.values.last_mut().unwrap().remove(&name);
+                self.values.pop();
                 ret
             }
-            typort_parser::simple_example::Stmt::Func(f) => {
+            ast::Stmt::Func(f) => {
+                if self.index.def_at(&f.name).is_none() {
+                    let def = self.index.add(Def {
+                        params: f.params.clone(),
+                        ty: f.return_type.clone(),
+                        ..Def::new(&f.name, SymbolKind::Func, self.class)
+                    });
+                    let binding = Binding { mutable: false, def: Some(def), func: true };
+                    self.values.last_mut().unwrap().insert(f.name.data, binding);
+                }
+                if let Some(t) = &f.return_type {
+                    self.ref_type(t);
+                }
+                // the body may capture values of the enclosing scopes
                 self.values.push(Default::default());
                 for param in f.params.iter() {
-                    if self.values.last_mut().unwrap().insert(param.0.data.to_owned(), false).is_some() {
-                        self.diag.push(Diagnostic {
-                            msg: format!("redefine {}", param.0.data),
-                            range: param.0.range,
-                        })
-                    }
+                    let def = self.define(&param.0, false, SymbolKind::Param);
+                    self.index.defs[def].ty = Some(param.1.clone());
+                    self.ref_type(&param.1);
                 }
+                let block = match f.block {
+                    Some(b) => self.convert_func_body(|this| this.convert_block(b)),
+                    None => {
+                        self.diag.push(
+                            Diagnostic::new(format!("\"{}\" has no body", f.name.data), &f.name)
+                                .with_code("E0109")
+                                .with_note("abstract methods can only be declared at the top of a class body"),
+                        );
+                        vec![]
+                    }
+                };
                 let ret = Stmt::Func {
-                    name: f.name.into(),
-                    params: f.params.into_iter().map(|x| (x.0.into(), x.1.into())).collect(),
-                    return_type: f.return_type.map(|x| x.into()),
-                    block: f.block.0.into_iter().map(|x| self.convert_stmt(x)).collect(),
+                    overrides: f.overrides,
+                    name: f.name,
+                    params: f.params,
+                    return_type: f.return_type,
+                    block: Some(block),
                 };
                 self.values.pop();
                 ret
             }
         }
     }
+    fn convert_binary(
+        &mut self,
+        a: ast::Expression,
+        b: ast::Expression,
+    ) -> (Box<Expression>, Box<Expression>) {
+        (Box::new(self.convert_expr(a)), Box::new(self.convert_expr(b)))
+    }
     pub fn convert_expr(
         &mut self,
-        value: typort_parser::simple_example::Expression,
+        value: ast::Expression,
     ) -> Expression {
         match value {
-            typort_parser::simple_example::Expression::Int(x) => Expression::Int(x.into()),
-            typort_parser::simple_example::Expression::String(x) => Expression::String(x.into()),
-            typort_parser::simple_example::Expression::Bool(x) => Expression::Bool(x),
-            typort_parser::simple_example::Expression::Name(x) => {
-                if !self.values.last().unwrap().contains_key(&x.data) {
-                    self.diag.push(Diagnostic {
-                        msg: format!("use of undeclared value {}", x.data),
-                        range: x.range,
-                    })
+            ast::Expression::Int(x) => Expression::Int(x),
+            ast::Expression::String(x) => Expression::String(x),
+            ast::Expression::Bool(x) => Expression::Bool(x),
+            ast::Expression::Name(x) => {
+                self.use_name(&x);
+                if self.lookup(x.data).is_none() && !self.methods.contains_key(&x.data) {
+                    let mut diag = Diagnostic::new(format!("use of undeclared value {}", x.data), &x)
+                        .with_code("E0110")
+                        .with_label("not found in this scope");
+                    if let Some(similar) = self.similar_name(x.data) {
+                        diag = diag.with_suggestion("a value with a similar name exists", &x, similar.as_str());
+                    }
+                    self.diag.push(diag)
                 }
-                Expression::Name(x.into())
+                Expression::Name(x)
             }
-            typort_parser::simple_example::Expression::ObjVal(a, _type) => {
-                if !self.values.last().unwrap().contains_key(&a.data) {
-                    self.diag.push(Diagnostic {
-                        msg: format!("use of undeclared value {}", a.data),
-                        range: a.range,
-                    })
-                }
-                Expression::Name(a.into()) //TODO: b
+            ast::Expression::Field(a, b) => {
+                let a = self.convert_expr(*a);
+                self.use_member(&a, &b);
+                Expression::Field(Box::new(a), b)
             }
-            typort_parser::simple_example::Expression::Add(a, b) => Expression::Add(
-                Box::new(self.convert_expr(*a)),
-                Box::new(self.convert_expr(*b)),
-            ),
-            typort_parser::simple_example::Expression::Sub(a, b) => Expression::Sub(
-                Box::new(self.convert_expr(*a)),
-                Box::new(self.convert_expr(*b)),
-            ),
-            typort_parser::simple_example::Expression::Mul(a, b) => Expression::Mul(
-                Box::new(self.convert_expr(*a)),
-                Box::new(self.convert_expr(*b)),
-            ),
-            typort_parser::simple_example::Expression::Div(a, b) => Expression::Div(
-                Box::new(self.convert_expr(*a)),
-                Box::new(self.convert_expr(*b)),
-            ),
-            typort_parser::simple_example::Expression::Eq(a, b) => Expression::Eq(
-                Box::new(self.convert_expr(*a)),
-                Box::new(self.convert_expr(*b)),
-            ),
-            typort_parser::simple_example::Expression::Neq(a, b) => Expression::Neq(
+            ast::Expression::Add(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Add(a, b)
+            }
+            ast::Expression::Sub(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Sub(a, b)
+            }
+            ast::Expression::Mul(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Mul(a, b)
+            }
+            ast::Expression::Div(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Div(a, b)
+            }
+            ast::Expression::Mod(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Mod(a, b)
+            }
+            ast::Expression::Eq(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Eq(a, b)
+            }
+            ast::Expression::Neq(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Neq(a, b)
+            }
+            ast::Expression::Lt(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Lt(a, b)
+            }
+            ast::Expression::Le(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Le(a, b)
+            }
+            ast::Expression::Gt(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Gt(a, b)
+            }
+            ast::Expression::Ge(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Ge(a, b)
+            }
+            ast::Expression::And(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::And(a, b)
+            }
+            ast::Expression::Or(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Or(a, b)
+            }
+            ast::Expression::Not(a) => {
+                Expression::Not(Box::new(self.convert_expr(*a)))
+            }
+            ast::Expression::Neg(a) => {
+                Expression::Neg(Box::new(self.convert_expr(*a)))
+            }
+            ast::Expression::Infix(a, op, b) if op.data == "->" => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Tuple(vec![*a, *b])
+            }
+            ast::Expression::Infix(a, op, b) => Expression::MethodCall(
                 Box::new(self.convert_expr(*a)),
-                Box::new(self.convert_expr(*b)),
+                op,
+                vec![self.convert_expr(*b)],
             ),
-            typort_parser::simple_example::Expression::Call(a, b) => {
-                if self.values.last().unwrap().contains_key(&a.data) {
-                    Expression::ObjCall(
-                        a.into(),
-                        Span { data: "apply".to_owned() },
-                        b.into_iter().map(|x| self.convert_expr(x)).collect(),
+            ast::Expression::Call(a, b) => {
+                if self.lookup(a.data).is_none() && !self.methods.contains_key(&a.data) {
+                    self.check_instantiate(&a);
+                }
+                let callee = self.lookup_def(a.data).or_else(|| self.index.classes.get(&a.data).copied());
+                if let Some(def) = callee {
+                    self.index.add_ref(&a, def)
+                }
+                // a value is called with the `apply` of its class
+                let callee = match callee {
+                    Some(def) if self.lookup(a.data).is_some() && self.index.defs[def].kind != SymbolKind::Func => {
+                        let class = self.index.class_of(&Expression::Name(a.clone()), self.class);
+                        class.and_then(|c| self.index.member(c, Symbol::APPLY)).or(callee)
+                    }
+                    _ => callee,
+                };
+                let args = self.convert_args(callee, &a, b);
+                self.add_call(callee, &a, &args);
+                if self.lookup(a.data).is_some() {
+                    Expression::MethodCall(
+                        Box::new(Expression::Name(a.clone())),
+                        a.with_data(Symbol::APPLY),
+                        args,
                     )
                 } else {
-                    Expression::Call(
-                        a.into(),
-                        b.into_iter().map(|x| self.convert_expr(x)).collect(),
-                    )
+                    Expression::Call(a, args)
                 }
             }
-            typort_parser::simple_example::Expression::ObjCall(a, b, c) => Expression::ObjCall(
-                a.into(),
-                b.into(),
-                c.into_iter().map(|x| self.convert_expr(x)).collect(),
-            ),
-            typort_parser::simple_example::Expression::If(c, b, e) => Expression::If(
+            ast::Expression::MethodCall(a, b, c) => {
+                let a = self.convert_expr(*a);
+                self.use_member(&a, &b);
+                let args = self.convert_method_args(&a, &b, c);
+                self.index.add_method_call(&b, &args);
+                Expression::MethodCall(Box::new(a), b, args)
+            }
+            // `xs.foldLeft(z)(f)` takes both argument lists at once
+            ast::Expression::Apply(a, b) if matches!(&*a, ast::Expression::MethodCall(_, m, _) if m.data == "foldLeft") => {
+                let ast::Expression::MethodCall(obj, m, mut args) = *a else { unreachable!() };
+                args.extend(b);
+                self.convert_expr(ast::Expression::MethodCall(obj, m, args))
+            }
+            ast::Expression::Apply(a, b) => {
+                let apply = a.span().unwrap_or_default().with_data(Symbol::APPLY);
+                let a = self.convert_expr(*a);
+                let args = self.convert_method_args(&a, &apply, b);
+                Expression::MethodCall(Box::new(a), apply, args)
+            }
+            ast::Expression::If(c, b, e) => Expression::If(
                 Box::new(self.convert_expr(*c)),
-                b.0.into_iter().map(|x| self.convert_stmt(x)).collect(),
-                e.map(|x| x.0.into_iter().map(|x| self.convert_stmt(x)).collect()),
+                self.convert_block(b),
+                e.map(|x| self.convert_block(x)),
+            ),
+            ast::Expression::Block(b) => {
+                Expression::Block(self.convert_block(b))
+            }
+            ast::Expression::Tuple(v) => {
+                Expression::Tuple(v.into_iter().map(|x| self.convert_expr(x)).collect())
+            }
+            ast::Expression::New(a, b, c) => {
+                //TODO: an anonymous class body may implement the abstract members
+                if c.is_none() {
+                    self.check_instantiate(&a);
+                }
+                let class = self.index.classes.get(&a.data).copied();
+                if let Some(def) = class {
+                    self.index.add_ref(&a, def)
+                }
+                let args = self.convert_args(class, &a, b);
+                self.add_call(class, &a, &args);
+                // the vals of the body are the fields of an anonymous class
+                let anonymous = anonymous_class(&a);
+                self.index.set_parents(anonymous, vec![a.data]);
+                let outer = self.anonymous.replace((anonymous, self.values.len() + 1));
+                let clocked = a.data == "ClockingArea" && !args.is_empty();
+                if clocked {
+                    self.clock_domains.push(name_of(&args[0]));
+                }
+                let body = c.map(|x| self.convert_block(x));
+                if clocked {
+                    self.clock_domains.pop();
+                }
+                self.anonymous = outer;
+                Expression::New(a, args, body)
+            }
+            // the names are taken off the arguments of a call by `convert_args`
+            ast::Expression::Named(_, a) => self.convert_expr(*a),
+            ast::Expression::Lambda(params, body) => {
+                self.values.push(Default::default());
+                for param in params.iter() {
+                    let def = self.define(&param.0, false, SymbolKind::Param);
+                    self.index.defs[def].ty = param.1.clone();
+                }
+                let block = self.convert_func_body(|this| match *body {
+                    ast::Expression::Block(b) => this.convert_block(b),
+                    e => vec![Stmt::Expr(this.convert_expr(e))],
+                });
+                self.values.pop();
+                Expression::Lambda(
+                    params,
+                    block,
+                )
+            }
+            // `s"a $b"` is `"a %s".format(b)`
+            ast::Expression::Interpolated(at, parts) => {
+                let mut text = String::new();
+                let mut args = vec![];
+                for part in parts {
+                    match part {
+                        ast::Expression::String(x) => text.push_str(&x.data.replace('%', "%%")),
+                        e => {
+                            text.push_str("%s");
+                            args.push(self.convert_expr(e));
+                        }
+                    }
+                }
+                Expression::MethodCall(
+                    Box::new(Expression::String(at.with_data(text))),
+                    at.with_data(Symbol::FORMAT),
+                    args,
+                )
+            }
+            ast::Expression::Match(e, cases) => {
+                let e = self.convert_expr(*e);
+                let cases = cases
+                    .into_iter()
+                    .map(|c| {
+                        self.values.push(Default::default());
+                        let pattern = self.convert_pattern(c.pattern);
+                        let guard = c.guard.map(|x| self.convert_expr(x));
+                        let block = self.convert_block(c.block);
+                        self.values.pop();
+                        Case { pattern, guard, block }
+                    })
+                    .collect();
+                Expression::Match(Box::new(e), cases)
+            }
+        }
+    }
+    fn convert_pattern(&mut self, value: ast::Pattern) -> Pattern {
+        match value {
+            ast::Pattern::Wildcard => Pattern::Wildcard,
+            ast::Pattern::Literal(e) => Pattern::Literal(self.convert_expr(e)),
+            ast::Pattern::Bind(a) => {
+                self.define(&a, false, SymbolKind::Local);
+                Pattern::Bind(a)
+            }
+            ast::Pattern::Typed(a, b) => {
+                self.define(&a, false, SymbolKind::Local);
+                Pattern::Typed(a, b)
+            }
+            ast::Pattern::Tuple(v) => {
+                Pattern::Tuple(v.into_iter().map(|x| self.convert_pattern(x)).collect())
+            }
+            ast::Pattern::Extractor(a, v) => Pattern::Extractor(
+                a,
+                v.into_iter().map(|x| self.convert_pattern(x)).collect(),
             ),
         }
     }
 }
 
-pub fn parse_to_hir(from: Vec<typort_parser::simple_example::TopItem>) -> Vec<Class> {
-    from.into_iter()
-        .map(|x| {
-            let mut converter = HirConverter::new();
-            let ret = match x {
-                typort_parser::simple_example::TopItem::Class(c) => {
-                    converter.add_param(&c.args);
-                    Class {
-                        name: c.name.into(),
-                        args: c
-                            .args
-                            .into_iter()
-                            .map(|x| (x.0.into(), x.1.into()))
-                            .collect(),
-                        extends: c.extends.map(|x| x.into()),
-                        with: c.with.into_iter().map(|x| x.into()).collect(),
-                        func: Default::default(),
-                        block: c
-                            .block
-                            .0
-                            .into_iter()
-                            .map(|x| converter.convert_stmt(x))
-                            .collect(),
+impl HirConverter {
+    /// `this` and the members of the class and everything it inherits from
+    /// are visible in the whole class body
+    fn define_members(&mut self, lin: &[Symbol], infos: &HashMap<Symbol, ClassInfo>) {
+        let scope = self.values.last_mut().unwrap();
+        scope.insert("this".into(), Binding { mutable: false, def: None, func: false });
+        for info in lin.iter().rev().filter_map(|c| infos.get(c)) {
+            for m in info.members.iter() {
+                let Some(def) = self.index.def_at(&m.name) else { continue };
+                match m.field {
+                    Some(mutable) => {
+                        scope.insert(m.name.data, Binding { mutable, def: Some(def), func: false });
+                    }
+                    None => {
+                        self.methods.insert(m.name.data, def);
                     }
                 }
-                typort_parser::simple_example::TopItem::Object(o) => Class {
-                    name: o.name.into(),
-                    args: vec![],
-                    extends: o.extends.map(|x| x.into()),
-                    with: o.with.into_iter().map(|x| x.into()).collect(),
-                    func: Default::default(),
-                    block: o
-                        .block
-                        .0
-                        .into_iter()
-                        .map(|x| converter.convert_stmt(x))
-                        .collect(),
-                },
-            };
-            if !converter.diag.is_empty() {
-                println!("{:?}", converter.diag); //TODO: do not print
             }
-            ret
+        }
+    }
+}
+
+/// the number of characters to insert, remove or replace to make `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (prev + (ca != *cb) as usize).min(row[j] + 1).min(row[j + 1] + 1);
+            prev = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+/// check the members of a class against the ones it inherits: a method that
+/// replaces an inherited one must say `override`, `override` must replace
+/// something, and a class that is not abstract must implement every
+/// abstract member
+fn check_members(name: &Ident, lin: &[Symbol], infos: &HashMap<Symbol, ClassInfo>) -> Vec<Diagnostic> {
+    let mut diag = vec![];
+    let Some(info) = infos.get(&name.data) else { return diag };
+    let inherited = |m: &Member, concrete: bool| {
+        lin[1..].iter().find(|c| {
+            infos.get(c).is_some_and(|i| {
+                i.members.iter().any(|x| x.name.data == m.name.data && !(concrete && x.is_abstract))
+            })
+        })
+    };
+    for m in info.members.iter().filter(|m| m.field.is_none() && !m.is_abstract) {
+        match (inherited(m, true), m.overrides) {
+            (Some(p), false) => diag.push(
+                Diagnostic::new(format!("\"{}\" overrides the method in {} and needs `override`", m.name.data, p), &m.name)
+                    .with_code("E0111")
+                    .with_note("write `override def` to replace an inherited method"),
+            ),
+            (None, true) if inherited(m, false).is_none() => diag.push(
+                Diagnostic::new(format!("\"{}\" overrides nothing", m.name.data), &m.name).with_code("E0112"),
+            ),
+            _ => {}
+        }
+    }
+    if info.kind != ClassKind::Class {
+        return diag;
+    }
+    let mut missing: Vec<(Symbol, Symbol)> = vec![];
+    for c in lin.iter() {
+        let Some(i) = infos.get(c) else { continue };
+        for m in i.members.iter().filter(|m| m.is_abstract) {
+            let implemented = lin.iter().filter_map(|c| infos.get(c)).any(|i| {
+                i.members.iter().any(|x| x.name.data == m.name.data && !x.is_abstract)
+            });
+            if !implemented && !missing.iter().any(|x| x.0 == m.name.data) {
+                missing.push((m.name.data, *c));
+            }
+        }
+    }
+    for (m, owner) in missing {
+        diag.push(
+            Diagnostic::new(format!("\"{}\" does not implement \"{}\" of {}", name.data, m, owner), name)
+                .with_code("E0113"),
+        );
+    }
+    diag
+}
+
+/// the linearization of a class: the class itself, then the classes it
+/// inherits from with the ones mixed in last coming first. `parents` gives the
+/// `extends` class followed by the `with` list, as written
+pub fn linearize(name: Symbol, parents: &impl Fn(Symbol) -> Vec<Symbol>) -> Vec<Symbol> {
+    fn go(name: Symbol, parents: &impl Fn(Symbol) -> Vec<Symbol>, visiting: &mut Vec<Symbol>) -> Vec<Symbol> {
+        if visiting.contains(&name) {
+            return vec![];
+        }
+        visiting.push(name);
+        let mut all = vec![];
+        for p in parents(name).into_iter().rev() {
+            all.extend(go(p, parents, visiting));
+        }
+        visiting.pop();
+        // when a class is inherited twice, the occurrence closest to the base wins
+        let mut ret = vec![name];
+        for (idx, c) in all.iter().enumerate() {
+            if !all[idx + 1..].contains(c) {
+                ret.push(*c);
+            }
+        }
+        ret
+    }
+    go(name, parents, &mut vec![])
+}
+
+/// a `def`, `val`, `var` or class parameter
+struct Member {
+    name: Ident,
+    /// `Some` for the ones that are not `def`s
+    field: Option<Mutable>,
+    overrides: bool,
+    is_abstract: bool,
+}
+
+/// the members a class declares, collected before any body is converted so
+/// they can be used before their definition and from subclasses
+struct ClassInfo {
+    kind: ClassKind,
+    parents: Vec<Symbol>,
+    members: Vec<Member>,
+}
+
+impl ClassInfo {
+    fn new(kind: ClassKind, args: &[(Ident, TypeExpr)], extends: &Option<Ident>, with: &[Ident], block: &ast::Block) -> Self {
+        let field = |name: &Ident, mutable, is_abstract| Member {
+            name: name.clone(),
+            field: Some(mutable),
+            overrides: false,
+            is_abstract,
+        };
+        let mut members: Vec<_> = args.iter().map(|a| field(&a.0, false, false)).collect();
+        for stmt in block.0.iter() {
+            match stmt {
+                ast::Stmt::Val(a, _, _) => members.push(field(a, false, false)),
+                ast::Stmt::Var(a, _, _) => members.push(field(a, true, false)),
+                ast::Stmt::Decl(a, _) => members.push(field(a, false, true)),
+                ast::Stmt::Func(f) => members.push(Member {
+                    name: f.name.clone(),
+                    field: None,
+                    overrides: f.overrides,
+                    is_abstract: f.block.is_none(),
+                }),
+                _ => {}
+            }
+        }
+        ClassInfo {
+            kind,
+            parents: extends.iter().chain(with).map(|x| x.data).collect(),
+            members,
+        }
+    }
+}
+
+fn class_infos(from: &[ast::TopItem]) -> HashMap<Symbol, ClassInfo> {
+    let mut infos = HashMap::new();
+    for item in from.iter() {
+        match item {
+            ast::TopItem::Class(c) => {
+                infos.insert(c.name.data, ClassInfo::new(c.kind, &c.args, &c.extends, &c.with, &c.block));
+            }
+            ast::TopItem::Object(o) => {
+                infos.insert(o.name.data, ClassInfo::new(ClassKind::Class, &[], &o.extends, &o.with, &o.block));
+            }
+            ast::TopItem::Import(_) => {}
+        }
+    }
+    infos
+}
+
+/// the diagnostics about class members and inheritance, without converting
+/// any body
+pub fn check_classes(from: &[ast::TopItem]) -> Vec<Diagnostic> {
+    let infos = class_infos(from);
+    let parents = |name| infos.get(&name).map(|i: &ClassInfo| i.parents.clone()).unwrap_or_default();
+    from.iter()
+        .flat_map(|x| match x {
+            ast::TopItem::Class(ast::Class { name, .. }) | ast::TopItem::Object(ast::Object { name, .. }) => {
+                check_members(name, &linearize(name.data, &parents), &infos)
+            }
+            ast::TopItem::Import(_) => vec![],
         })
         .collect()
 }
+
+/// the text of a name or of a path like `a.b`, e.g. a clock domain
+fn name_of(e: &Expression) -> String {
+    match e {
+        Expression::Name(x) => x.data.to_string(),
+        Expression::Field(a, b) => format!("{}.{}", name_of(a), b.data),
+        Expression::Call(f, _) => f.data.to_string(),
+        _ => "_".to_owned(),
+    }
+}
+
+/// add the classes and their members to the index, so they can be found
+/// before their definition and from other classes
+fn index_classes(from: &[ast::TopItem], index: &mut SymbolIndex) {
+    for item in from.iter() {
+        let (name, kind, args, extends, with, block) = match item {
+            ast::TopItem::Class(c) => {
+                let kind = if c.kind == ClassKind::Trait { SymbolKind::Trait } else { SymbolKind::Class };
+                (&c.name, kind, &c.args[..], &c.extends, &c.with, &c.block)
+            }
+            ast::TopItem::Object(o) => (&o.name, SymbolKind::Object, &[][..], &o.extends, &o.with, &o.block),
+            ast::TopItem::Import(_) => continue,
+        };
+        let class = Some(name.data);
+        index.add(Def { params: args.to_vec(), ..Def::new(name, kind, None) });
+        index.set_parents(name.data, extends.iter().chain(with).map(|x| x.data).collect());
+        for (a, t) in args {
+            index.add(Def { ty: Some(t.clone()), ..Def::new(a, SymbolKind::Field, class) });
+        }
+        for stmt in block.0.iter() {
+            let def = match stmt {
+                ast::Stmt::Val(a, t, _) => Def { ty: t.clone(), ..Def::new(a, SymbolKind::Field, class) },
+                ast::Stmt::Decl(a, t) => Def { ty: Some(t.clone()), ..Def::new(a, SymbolKind::Field, class) },
+                ast::Stmt::Var(a, t, _) => Def { mutable: true, ty: t.clone(), ..Def::new(a, SymbolKind::Field, class) },
+                ast::Stmt::Func(f) => Def {
+                    params: f.params.clone(),
+                    ty: f.return_type.clone(),
+                    ..Def::new(&f.name, SymbolKind::Method, class)
+                },
+                _ => continue,
+            };
+            index.add(def);
+        }
+    }
+    // the parents may be defined after the class, or in another file
+    for item in from.iter() {
+        let (extends, with) = match item {
+            ast::TopItem::Class(c) => (&c.extends, &c.with),
+            ast::TopItem::Object(o) => (&o.extends, &o.with),
+            // every file is in the same namespace, `import a.b.C` names the class `C`
+            ast::TopItem::Import(i) => {
+                if let Some(class) = i.path.last().filter(|_| !i.wildcard) {
+                    if let Some(def) = index.classes.get(&class.data) {
+                        index.add_ref(class, *def)
+                    }
+                }
+                continue;
+            }
+        };
+        for parent in extends.iter().chain(with) {
+            if let Some(def) = index.classes.get(&parent.data) {
+                index.add_ref(parent, *def)
+            }
+        }
+    }
+}
+
+/// convert the top items of every file together, with the diagnostics found
+pub fn parse_to_hir(from: Vec<ast::TopItem>) -> (Vec<Class>, Vec<Diagnostic>) {
+    let (classes, diag, _) = parse_to_hir_indexed(from);
+    (classes, diag)
+}
+
+/// like `parse_to_hir`, with the definitions and uses of every name
+pub fn parse_to_hir_indexed(from: Vec<ast::TopItem>) -> (Vec<Class>, Vec<Diagnostic>, SymbolIndex) {
+    let outline = Outline::new(&from);
+    let mut classes = vec![];
+    let mut diag = vec![];
+    let mut fragments = vec![];
+    for item in from {
+        if let Some((class, d, fragment)) = outline.convert(item) {
+            classes.push(class);
+            diag.extend(d);
+            fragments.push(fragment);
+        }
+    }
+    let index = outline.merge(&fragments);
+    (classes, diag, index)
+}
+
+/// what the conversion of a class needs to know of the other ones: their
+/// members and what they inherit from. each class is converted on its own,
+/// so a class which did not change does not need to be converted again
+pub struct Outline {
+    infos: HashMap<Symbol, ClassInfo>,
+    abstract_classes: HashSet<Symbol>,
+    /// the classes and their members
+    index: SymbolIndex,
+    fingerprint: u64,
+}
+
+impl Outline {
+    pub fn new(from: &[ast::TopItem]) -> Self {
+        let mut index = SymbolIndex::default();
+        index_classes(from, &mut index);
+        let infos = class_infos(from);
+        let abstract_classes = infos.iter()
+            .filter(|(_, i)| i.kind != ClassKind::Class)
+            .map(|(name, _)| *name)
+            .collect();
+        Outline {
+            fingerprint: fingerprint(from),
+            infos,
+            abstract_classes,
+            index,
+        }
+    }
+    /// the same for the outlines of classes with the same names, member
+    /// signatures and parents, in the same order. a class converted with one of them is
+    /// converted the same with the other if it did not change
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+    /// `name` and the classes it inherits from, the closest first
+    pub fn linearize(&self, name: Symbol) -> Vec<Symbol> {
+        linearize(name, &|c| self.infos.get(&c).map(|i| i.parents.clone()).unwrap_or_default())
+    }
+    /// convert a class or an object, with the definitions and uses of names in it
+    pub fn convert(&self, item: ast::TopItem) -> Option<(Class, Vec<Diagnostic>, SymbolIndex)> {
+        let mut converter = HirConverter::new();
+        let name = match &item {
+            ast::TopItem::Class(c) => c.name.clone(),
+            ast::TopItem::Object(o) => o.name.clone(),
+            //TODO: resolve imported names
+            ast::TopItem::Import(_) => return None,
+        };
+        let lin = self.linearize(name.data);
+        converter.index = self.index.clone();
+        converter.class = Some(name.data);
+        converter.abstract_classes = self.abstract_classes.clone();
+        converter.define_members(&lin, &self.infos);
+        converter.diag.extend(check_members(&name, &lin, &self.infos));
+        if let ast::TopItem::Class(c) = &item {
+            c.args.iter().for_each(|a| converter.ref_type(&a.1));
+        }
+        let ret = match item {
+            ast::TopItem::Class(c) => Class {
+                kind: c.kind,
+                name: c.name,
+                args: c.args,
+                extends: c.extends,
+                with: c.with,
+                func: Default::default(),
+                block: converter.convert_class_body(c.block),
+            },
+            ast::TopItem::Object(o) => Class {
+                kind: ClassKind::Class,
+                name: o.name,
+                args: vec![],
+                extends: o.extends,
+                with: o.with,
+                func: Default::default(),
+                block: converter.convert_class_body(o.block),
+            },
+            ast::TopItem::Import(_) => return None,
+        };
+        Some((ret, converter.diag, converter.index))
+    }
+    /// the index of every class from the ones `convert` gave
+    pub fn merge<'a>(&self, fragments: impl IntoIterator<Item = &'a SymbolIndex>) -> SymbolIndex {
+        let mut index = self.index.clone();
+        for fragment in fragments {
+            index.merge(&self.index, fragment);
+        }
+        index.resolve();
+        index
+    }
+}
+
+/// a hash of the names, member signatures and parents of the classes, see `Outline::fingerprint`
+fn fingerprint(from: &[ast::TopItem]) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    let ty = |t: &Option<ast::TypeExpr>| t.as_ref().map(|t| t.to_string());
+    for item in from.iter() {
+        let (name, kind, args, extends, with, block) = match item {
+            ast::TopItem::Class(c) => (c.name.data, c.kind as u8, &c.args[..], &c.extends, &c.with, &c.block),
+            ast::TopItem::Object(o) => (o.name.data, u8::MAX, &[][..], &o.extends, &o.with, &o.block),
+            ast::TopItem::Import(i) => {
+                i.path.iter().for_each(|p| p.data.hash(&mut hasher));
+                i.wildcard.hash(&mut hasher);
+                continue;
+            }
+        };
+        (name, kind).hash(&mut hasher);
+        args.iter().for_each(|a| (a.0.data, a.1.to_string()).hash(&mut hasher));
+        extends.iter().chain(with).for_each(|p| p.data.hash(&mut hasher));
+        for stmt in block.0.iter() {
+            match stmt {
+                ast::Stmt::Val(a, t, _) => (0, a.data, ty(t)).hash(&mut hasher),
+                ast::Stmt::Var(a, t, _) => (1, a.data, ty(t)).hash(&mut hasher),
+                ast::Stmt::Decl(a, t) => (2, a.data, t.to_string()).hash(&mut hasher),
+                ast::Stmt::Func(f) => {
+                    (3, f.name.data, f.overrides, f.block.is_some(), ty(&f.return_type)).hash(&mut hasher);
+                    f.params.iter().for_each(|p| (p.0.data, p.1.to_string()).hash(&mut hasher));
+                    // the end of the parameters
+                    u8::MAX.hash(&mut hasher);
+                }
+                _ => {}
+            }
+        }
+        // the end of a class
+        u8::MAX.hash(&mut hasher);
+    }
+    hasher.finish()
+}
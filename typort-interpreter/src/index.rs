@@ -0,0 +1,563 @@
+use std::collections::HashMap;
+
+use typort_parser::ast::{Ident, Symbol, TypeExpr};
+
+use crate::hir::{linearize, Expression, Stmt};
+use crate::ty::Type;
+use crate::{FileId, Span};
+
+/// an index into `SymbolIndex::defs`
+pub type DefId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Class,
+    Trait,
+    Object,
+    /// a `val` or a `var` of a class, or a class parameter
+    Field,
+    Method,
+    /// a parameter of a `def` or a lambda
+    Param,
+    /// a `val` or a `var` in a block
+    Local,
+    /// a `def` in a block
+    Func,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::In => write!(f, "in"),
+            Direction::Out => write!(f, "out"),
+        }
+    }
+}
+
+/// what is known of a hardware value from the expression creating it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signal {
+    /// `UInt`, `SInt`, `Bits`, `Bool` or a `Bundle`
+    pub ty: String,
+    pub width: Option<i64>,
+    pub direction: Option<Direction>,
+    pub register: bool,
+    /// the clock domain of a register, or of a signal in a `ClockingArea`
+    pub clock_domain: Option<String>,
+}
+
+impl std::fmt::Display for Signal {
+    /// `UInt(9 bits)`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.width {
+            Some(w) if self.ty != "Bool" => write!(f, "{}({w} bits)", self.ty),
+            _ => write!(f, "{}", self.ty),
+        }
+    }
+}
+
+/// the clock domain of registers outside of any `ClockingArea`
+pub const DEFAULT_CLOCK_DOMAIN: &str = "clkDomain";
+
+/// the members of the hardware types, by the `Signal::ty` they are of
+pub const HARDWARE_MEMBERS: &[(&str, &[&str])] = &[
+    ("Stream", &["valid", "ready", "payload", "fire", "isStall", "queue", "m2sPipe", "s2mPipe", "translateWith"]),
+    ("Flow", &["valid", "payload", "toStream", "m2sPipe"]),
+    ("UInt", &["getWidth", "resize", "asBits", "asSInt", "asBool", "msb", "lsb", "orR", "andR", "xorR"]),
+    ("SInt", &["getWidth", "resize", "asBits", "asUInt", "asBool", "msb", "lsb", "abs", "orR", "andR", "xorR"]),
+    ("Bits", &["getWidth", "resize", "asUInt", "asSInt", "asBool", "msb", "lsb", "orR", "andR", "xorR"]),
+    ("Bool", &["asBits", "asUInt", "rise", "fall", "edge", "set", "clear"]),
+];
+
+/// a name defined in the source
+#[derive(Clone, Debug)]
+pub struct Def {
+    pub name: Symbol,
+    pub kind: SymbolKind,
+    pub at: Span<()>,
+    /// the class or object it is defined in, `None` for a class
+    pub class: Option<Symbol>,
+    pub mutable: bool,
+    /// the declared type, or the return type of a `def`
+    pub ty: Option<TypeExpr>,
+    /// the parameters of a `def` or of a class
+    pub params: Vec<(Ident, TypeExpr)>,
+    /// what a `val` or a `var` is set to
+    pub value: Option<Expression>,
+    /// the clock domain of the `ClockingArea` it is defined in
+    pub clock_domain: Option<String>,
+    pub signal: Option<Signal>,
+}
+
+impl Def {
+    pub fn new(name: &Ident, kind: SymbolKind, class: Option<Symbol>) -> Self {
+        Def {
+            name: name.data,
+            kind,
+            at: name.with_data(()),
+            class,
+            mutable: false,
+            ty: None,
+            params: vec![],
+            value: None,
+            clock_domain: None,
+            signal: None,
+        }
+    }
+}
+
+/// a call of a `def`, or the creation of an instance of a class
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub callee: DefId,
+    /// the name of the callee
+    pub at: Span<()>,
+    pub args: Vec<Expression>,
+}
+
+/// what the class of the receiver of a member is known from
+#[derive(Clone, Debug)]
+enum Receiver {
+    Class(Symbol),
+    /// the use of a name or of a member, the class is the one of the definition it refers to
+    Ref(Span<()>),
+}
+
+/// every definition and every use of a name, built while converting to HIR
+#[derive(Clone, Debug, Default)]
+pub struct SymbolIndex {
+    pub defs: Vec<Def>,
+    /// the uses of names and what they refer to
+    pub refs: Vec<(Span<()>, DefId)>,
+    pub calls: Vec<Call>,
+    /// classes, traits and objects by name
+    pub classes: HashMap<Symbol, DefId>,
+    /// the members each class declares
+    members: HashMap<Symbol, Vec<DefId>>,
+    parents: HashMap<Symbol, Vec<Symbol>>,
+    /// `defs` and `refs` by where they start
+    def_at: HashMap<(FileId, usize), DefId>,
+    ref_at: HashMap<(FileId, usize), DefId>,
+    /// the uses of members and the calls of methods, they are resolved once
+    /// the values of every class are known
+    member_uses: Vec<(Receiver, Ident)>,
+    method_calls: Vec<(Span<()>, Vec<Expression>)>,
+}
+
+impl SymbolIndex {
+    pub fn add(&mut self, def: Def) -> DefId {
+        let id = self.defs.len();
+        self.def_at.insert((def.at.file, def.at.offset), id);
+        match (def.kind, def.class) {
+            (SymbolKind::Class | SymbolKind::Trait | SymbolKind::Object, _) => {
+                self.classes.insert(def.name, id);
+            }
+            (SymbolKind::Field | SymbolKind::Method, Some(class)) => {
+                self.members.entry(class).or_default().push(id);
+            }
+            _ => {}
+        }
+        self.defs.push(def);
+        id
+    }
+    pub fn add_ref<T>(&mut self, at: &Span<T>, def: DefId) {
+        self.ref_at.insert((at.file, at.offset), def);
+        self.refs.push((at.with_data(()), def));
+    }
+    /// a use of a member of `receiver`, `this` is an instance of `this`
+    pub fn add_member_use(&mut self, receiver: &Expression, member: &Ident, this: Option<Symbol>) {
+        let receiver = match receiver {
+            Expression::New(c, _, Some(_)) => Receiver::Class(anonymous_class(c)),
+            Expression::New(c, _, None) => Receiver::Class(c.data),
+            Expression::Call(c, _) if self.classes.contains_key(&c.data) => Receiver::Class(c.data),
+            Expression::Name(x) if x.data == "this" => match this {
+                Some(this) => Receiver::Class(this),
+                None => return,
+            },
+            Expression::Name(x) | Expression::Field(_, x) | Expression::MethodCall(_, x, _) => Receiver::Ref(x.with_data(())),
+            _ => return,
+        };
+        self.member_uses.push((receiver, member.clone()))
+    }
+    /// a call of the method `name`
+    pub fn add_method_call(&mut self, name: &Ident, args: &[Expression]) {
+        self.method_calls.push((name.with_data(()), args.to_vec()))
+    }
+    /// add what was found converting a class, `self` and `fragment` both
+    /// started as `outline`
+    pub fn merge(&mut self, outline: &SymbolIndex, fragment: &SymbolIndex) {
+        let base = outline.defs.len();
+        let offset = self.defs.len() - base;
+        let id = |def: DefId| if def < base { def } else { def + offset };
+        for (i, def) in fragment.defs.iter().enumerate() {
+            if i >= base {
+                self.add(def.clone());
+            } else if def.value.is_some() {
+                // a member of the class, with its value
+                self.defs[i] = def.clone();
+            }
+        }
+        for (at, def) in fragment.refs.iter().skip(outline.refs.len()) {
+            self.add_ref(at, id(*def));
+        }
+        self.calls.extend(fragment.calls.iter().map(|c| Call { callee: id(c.callee), ..c.clone() }));
+        for (class, parents) in fragment.parents.iter() {
+            self.parents.entry(*class).or_insert_with(|| parents.clone());
+        }
+        self.member_uses.extend(fragment.member_uses.iter().cloned());
+        self.method_calls.extend(fragment.method_calls.iter().cloned());
+    }
+    /// resolve the uses of members and find what hardware each value is, it
+    /// is done once every class is added
+    pub fn resolve(&mut self) {
+        for (receiver, member) in std::mem::take(&mut self.member_uses) {
+            let class = match receiver {
+                Receiver::Class(c) => Some(c),
+                Receiver::Ref(at) => self.ref_at(&at).and_then(|def| self.class_of_def(def)),
+            };
+            if let Some(def) = class.and_then(|c| self.member(c, member.data)) {
+                self.add_ref(&member, def)
+            }
+        }
+        for (at, args) in std::mem::take(&mut self.method_calls) {
+            if let Some(callee) = self.ref_at(&at) {
+                self.calls.push(Call { callee, at, args })
+            }
+        }
+        for def in 0..self.defs.len() {
+            let d = &self.defs[def];
+            let signal = d.value.as_ref().and_then(|v| self.signal(v, d.clock_domain.as_deref()));
+            self.defs[def].signal = signal;
+        }
+    }
+    /// the classes `class` extends and mixes in, as written
+    pub fn set_parents(&mut self, class: Symbol, parents: Vec<Symbol>) {
+        self.parents.insert(class, parents);
+    }
+    /// the definition whose name starts at `at`
+    pub fn def_at<T>(&self, at: &Span<T>) -> Option<DefId> {
+        self.def_at.get(&(at.file, at.offset)).copied()
+    }
+    /// what the name starting at `at` refers to
+    pub fn ref_at<T>(&self, at: &Span<T>) -> Option<DefId> {
+        self.ref_at.get(&(at.file, at.offset)).copied()
+    }
+    /// the definition named or defined at a position of a file
+    pub fn at(&self, file: FileId, line: usize, col: usize) -> Option<DefId> {
+        let contains = |at: &Span<()>| {
+            at.file == file && at.range.0.0 == line && at.range.0.1 <= col && col <= at.range.1.1
+        };
+        self.refs.iter()
+            .find(|(at, _)| contains(at))
+            .map(|(_, def)| *def)
+            .or_else(|| self.defs.iter().position(|d| contains(&d.at)))
+    }
+    /// every use of `def`
+    pub fn references(&self, def: DefId) -> impl Iterator<Item = &Span<()>> {
+        self.refs.iter().filter(move |(_, d)| *d == def).map(|(at, _)| at)
+    }
+    /// the members of `class` and the ones it inherits, the closest first
+    pub fn members(&self, class: Symbol) -> Vec<DefId> {
+        let parents = |c| self.parents.get(&c).cloned().unwrap_or_default();
+        let mut ret: Vec<DefId> = vec![];
+        for c in linearize(class, &parents) {
+            for m in self.members.get(&c).into_iter().flatten() {
+                if !ret.iter().any(|x| self.defs[*x].name == self.defs[*m].name) {
+                    ret.push(*m);
+                }
+            }
+        }
+        ret
+    }
+    pub fn member(&self, class: Symbol, name: Symbol) -> Option<DefId> {
+        self.members(class).into_iter().find(|m| self.defs[*m].name == name)
+    }
+    /// if `class` is or inherits from `base`
+    pub fn inherits(&self, class: Symbol, base: &str) -> bool {
+        let parents = |c| self.parents.get(&c).cloned().unwrap_or_default();
+        linearize(class, &parents).iter().any(|c| *c == base)
+    }
+    /// the class of the instances `e` gives, `this` is an instance of `this`
+    pub fn class_of(&self, e: &Expression, this: Option<Symbol>) -> Option<Symbol> {
+        self.class_of_depth(e, this, 0)
+    }
+    fn class_of_depth(&self, e: &Expression, this: Option<Symbol>, depth: usize) -> Option<Symbol> {
+        // values may be defined in terms of each other
+        if depth > 16 {
+            return None;
+        }
+        match e {
+            Expression::New(c, _, Some(_)) => Some(anonymous_class(c)),
+            Expression::New(c, _, None) => Some(c.data),
+            Expression::Call(c, _) if self.classes.contains_key(&c.data) => Some(c.data),
+            Expression::Name(x) if x.data == "this" => this,
+            Expression::Name(x) => self.class_of_def_depth(self.ref_at(x)?, depth),
+            Expression::Field(_, m) | Expression::MethodCall(_, m, _) => self.class_of_def_depth(self.ref_at(m)?, depth),
+            _ => None,
+        }
+    }
+    /// the class of the instances a definition holds or gives
+    pub fn class_of_def(&self, def: DefId) -> Option<Symbol> {
+        self.class_of_def_depth(def, 0)
+    }
+    fn class_of_def_depth(&self, def: DefId, depth: usize) -> Option<Symbol> {
+        let def = &self.defs[def];
+        if let Some(TypeExpr::Name(n, _)) = &def.ty {
+            return self.classes.contains_key(&n.data).then_some(n.data);
+        }
+        self.class_of_depth(def.value.as_ref()?, def.class, depth + 1)
+    }
+    /// the value of an expression made of `Int`s and constant `val`s, e.g. a width
+    pub fn constant(&self, e: &Expression) -> Option<i64> {
+        self.constant_depth(e, 0)
+    }
+    fn constant_depth(&self, e: &Expression, depth: usize) -> Option<i64> {
+        if depth > 16 {
+            return None;
+        }
+        let c = |e| self.constant_depth(e, depth + 1);
+        match e {
+            Expression::Int(x) => Some(x.data),
+            Expression::Add(a, b) => c(a)?.checked_add(c(b)?),
+            Expression::Sub(a, b) => c(a)?.checked_sub(c(b)?),
+            Expression::Mul(a, b) => c(a)?.checked_mul(c(b)?),
+            Expression::Div(a, b) => c(a)?.checked_div(c(b)?),
+            Expression::MethodCall(a, m, args) if (m.data == "bits" || m.data == "bit") && args.is_empty() => c(a),
+            Expression::Call(f, args) if f.data == "log2Up" && args.len() == 1 => {
+                let x = c(&args[0])?;
+                Some(if x <= 1 { 0 } else { 64 - (x - 1).leading_zeros() as i64 })
+            }
+            Expression::Name(x) => {
+                let def = &self.defs[self.ref_at(x)?];
+                if def.mutable {
+                    return None;
+                }
+                c(def.value.as_ref()?)
+            }
+            _ => None,
+        }
+    }
+    /// the hardware `e` creates, `clock_domain` is the one of the
+    /// `ClockingArea` it is in
+    pub fn signal(&self, e: &Expression, clock_domain: Option<&str>) -> Option<Signal> {
+        let mut signal = self.signal_depth(e, 0)?;
+        if let Some(domain) = clock_domain {
+            signal.clock_domain = Some(domain.to_owned());
+        } else if signal.register && signal.clock_domain.is_none() {
+            signal.clock_domain = Some(DEFAULT_CLOCK_DOMAIN.to_owned());
+        }
+        Some(signal)
+    }
+    fn signal_depth(&self, e: &Expression, depth: usize) -> Option<Signal> {
+        if depth > 16 {
+            return None;
+        }
+        let s = |e| self.signal_depth(e, depth + 1);
+        let wire = |ty: &str, width| Signal {
+            ty: ty.to_owned(),
+            width,
+            direction: None,
+            register: false,
+            clock_domain: None,
+        };
+        let max = |a: &Signal, b: &Signal| Some(a.width?.max(b.width?));
+        match e {
+            Expression::Call(f, args) => match (f.data.as_str(), &args[..]) {
+                ("in", [a]) | ("out", [a]) => Some(Signal {
+                    direction: Some(if f.data == "in" { Direction::In } else { Direction::Out }),
+                    ..s(a)?
+                }),
+                ("master", [a]) | ("slave", [a]) => Some(Signal {
+                    direction: Some(if f.data == "master" { Direction::Out } else { Direction::In }),
+                    ..s(a)?
+                }),
+                ("Stream", [_]) | ("Flow", [_]) => Some(wire(f.data.as_str(), None)),
+                ("Reg", [a]) | ("RegInit", [a]) | ("RegNext", [a]) | ("RegNext", [a, _]) => Some(Signal {
+                    register: true,
+                    direction: None,
+                    ..s(a)?
+                }),
+                ("UInt", [w]) | ("SInt", [w]) | ("Bits", [w]) => Some(wire(f.data.as_str(), self.constant(w))),
+                ("UInt", []) | ("SInt", []) | ("Bits", []) => Some(wire(f.data.as_str(), None)),
+                ("Bool", []) => Some(wire("Bool", Some(1))),
+                ("U", [_, w]) | ("S", [_, w]) | ("B", [_, w]) => {
+                    let ty = match f.data.as_str() { "U" => "UInt", "S" => "SInt", _ => "Bits" };
+                    Some(wire(ty, self.constant(w)))
+                }
+                ("U", [a]) | ("S", [a]) | ("B", [a]) => {
+                    let ty = match f.data.as_str() { "U" => "UInt", "S" => "SInt", _ => "Bits" };
+                    let width = self.constant(a).map(|x| (64 - x.unsigned_abs().leading_zeros() as i64).max(1));
+                    Some(wire(ty, width))
+                }
+                ("True", []) | ("False", []) => Some(wire("Bool", Some(1))),
+                (c, _) if self.classes.contains_key(&f.data) && self.inherits(f.data, "Bundle") => Some(wire(c, None)),
+                _ => None,
+            },
+            Expression::New(c, _, _) if c.data == "Bundle" || self.inherits(c.data, "Bundle") => Some(wire(c.data.as_str(), None)),
+            // a name is the same hardware as the one it refers to
+            Expression::Name(x) | Expression::Field(_, x) => {
+                let def = &self.defs[self.ref_at(x)?];
+                def.signal.clone().or_else(|| s(def.value.as_ref()?))
+            }
+            // an operand that is not a signal, e.g. `1`, takes the width of the other one
+            Expression::Add(a, b) | Expression::Sub(a, b) => {
+                let a = s(a)?;
+                let b = s(b).unwrap_or(a.clone());
+                Some(wire(&a.ty, max(&a, &b)))
+            }
+            Expression::Mul(a, b) => {
+                let (a, b) = (s(a)?, s(b)?);
+                Some(wire(&a.ty, a.width.zip(b.width).map(|(a, b)| a + b)))
+            }
+            Expression::And(a, b) | Expression::Or(a, b) => {
+                let (a, b) = (s(a)?, s(b)?);
+                Some(wire(&a.ty, max(&a, &b)))
+            }
+            Expression::Not(a) => s(a).map(|a| wire(&a.ty, a.width)),
+            Expression::Eq(a, _)
+            | Expression::Neq(a, _)
+            | Expression::Lt(a, _)
+            | Expression::Le(a, _)
+            | Expression::Gt(a, _)
+            | Expression::Ge(a, _) => s(a).map(|_| wire("Bool", Some(1))),
+            Expression::MethodCall(a, m, args) => {
+                let a = s(a)?;
+                match (m.data.as_str(), &args[..]) {
+                    ("asUInt", []) => Some(wire("UInt", a.width)),
+                    ("asSInt", []) => Some(wire("SInt", a.width)),
+                    ("asBits", []) => Some(wire("Bits", a.width)),
+                    ("asBool", []) | ("orR", []) | ("andR", []) | ("xorR", []) | ("msb", []) | ("lsb", []) => {
+                        Some(wire("Bool", Some(1)))
+                    }
+                    ("resize", [w]) => Some(wire(&a.ty, self.constant(w))),
+                    ("+^", [b]) | ("-^", [b]) => {
+                        let b = s(b).unwrap_or(a.clone());
+                        Some(wire(&a.ty, max(&a, &b).map(|w| w + 1)))
+                    }
+                    ("##", [b]) => {
+                        let b = s(b)?;
+                        Some(wire("Bits", a.width.zip(b.width).map(|(a, b)| a + b)))
+                    }
+                    ("&" | "|" | "^" | "+" | "-", [b]) => {
+                        let b = s(b).unwrap_or(a.clone());
+                        Some(wire(&a.ty, max(&a, &b)))
+                    }
+                    ("===" | "=/=" | "<" | "<=" | ">" | ">=", [_]) => Some(wire("Bool", Some(1))),
+                    ("<<", [n]) => Some(wire(&a.ty, a.width.zip(self.constant(n)).map(|(a, n)| a + n))),
+                    (">>", [n]) => Some(wire(&a.ty, a.width.zip(self.constant(n)).map(|(a, n)| (a - n).max(0)))),
+                    _ => None,
+                }
+            }
+            Expression::If(_, a, _) => match a.last() {
+                Some(Stmt::Expr(e)) => s(e),
+                _ => None,
+            },
+            Expression::Block(b) => match b.last() {
+                Some(Stmt::Expr(e)) => s(e),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    /// the declared type of a definition, or the one known from its value
+    pub fn type_of(&self, def: DefId) -> Option<String> {
+        let d = &self.defs[def];
+        if let Some(ty) = &d.ty {
+            return Some(ty.to_string());
+        }
+        if let Some(signal) = &d.signal {
+            return Some(signal.to_string());
+        }
+        let value = d.value.as_ref()?;
+        // what a `def` is declared to return
+        if let Expression::Call(f, _) | Expression::MethodCall(_, f, _) = value {
+            let callee = self.ref_at(f).map(|def| &self.defs[def]);
+            if let Some(ty) = callee.filter(|c| matches!(c.kind, SymbolKind::Method | SymbolKind::Func)).and_then(|c| c.ty.as_ref()) {
+                return Some(ty.to_string());
+            }
+        }
+        Type::of(value)
+            .map(|t| t.to_string())
+            .or_else(|| self.class_of(value, d.class).map(|c| class_name(c).to_owned()))
+    }
+    /// how the definition is declared, e.g. `def f(a: Int): Int`
+    pub fn signature(&self, def: DefId) -> String {
+        let d = &self.defs[def];
+        let params = || {
+            d.params.iter().map(|(n, t)| format!("{}: {t}", n.data)).collect::<Vec<_>>().join(", ")
+        };
+        let ty = self.type_of(def).map(|t| format!(": {t}")).unwrap_or_default();
+        match d.kind {
+            SymbolKind::Class if d.params.is_empty() => format!("class {}", d.name),
+            SymbolKind::Class => format!("class {}({})", d.name, params()),
+            SymbolKind::Trait => format!("trait {}", d.name),
+            SymbolKind::Object => format!("object {}", d.name),
+            SymbolKind::Method | SymbolKind::Func => format!("def {}({}){ty}", d.name, params()),
+            SymbolKind::Param => format!("{}{ty}", d.name),
+            SymbolKind::Field | SymbolKind::Local if d.mutable => format!("var {}{ty}", d.name),
+            SymbolKind::Field | SymbolKind::Local => format!("val {}{ty}", d.name),
+        }
+    }
+}
+
+/// the name given to the class of `new c { ... }`, it inherits from `c`
+pub fn anonymous_class(c: &Ident) -> Symbol {
+    Symbol::intern(&format!("{}@{}:{}", c.data, c.file.0, c.offset))
+}
+
+/// the name of a class as written, the one an anonymous class extends
+pub fn class_name(c: Symbol) -> &'static str {
+    c.as_str().split('@').next().unwrap_or_default()
+}
+
+/// where an expression starts, if it is known
+pub fn start_of(e: &Expression) -> Option<Span<()>> {
+    match e {
+        // their spans are the ones of what is after the keyword or the operator
+        Expression::If(..)
+        | Expression::Match(..)
+        | Expression::Not(_)
+        | Expression::Neg(_)
+        | Expression::New(..)
+        | Expression::Block(_)
+        | Expression::Tuple(_) => None,
+        Expression::Lambda(params, _) if params.is_empty() => None,
+        Expression::Field(a, _)
+        | Expression::Add(a, _)
+        | Expression::Sub(a, _)
+        | Expression::Mul(a, _)
+        | Expression::Div(a, _)
+        | Expression::Mod(a, _)
+        | Expression::Eq(a, _)
+        | Expression::Neq(a, _)
+        | Expression::Lt(a, _)
+        | Expression::Le(a, _)
+        | Expression::Gt(a, _)
+        | Expression::Ge(a, _)
+        | Expression::And(a, _)
+        | Expression::Or(a, _)
+        | Expression::MethodCall(a, _, _) => start_of(a),
+        e => e.span(),
+    }
+}
+
+/// the `//` comment lines right above `line`, without the slashes
+pub fn doc_comment(text: &str, line: usize) -> Option<String> {
+    let lines: Vec<&str> = text.lines().take(line).collect();
+    let doc: Vec<&str> = lines.iter()
+        .rev()
+        .map(|l| l.trim())
+        .take_while(|l| l.starts_with("//"))
+        .map(|l| l.trim_start_matches('/').trim())
+        .collect();
+    if doc.is_empty() {
+        return None;
+    }
+    Some(doc.into_iter().rev().collect::<Vec<_>>().join("\n"))
+}
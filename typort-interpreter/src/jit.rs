@@ -1,11 +1,65 @@
-use crate::{mir::*, Span};
+use crate::mir::{Capture, Class, Expression, Pattern, Stmt, Var};
+use crate::value::{Frame, RuntimeError, Value as VmValue, VmResult};
+use crate::built_in::BUILT_INS;
+use crate::native::Natives;
+use crate::{FileId, Span, Symbol};
 use cranelift::prelude::*;
+use cranelift::codegen::ir::FuncRef;
 use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{DataDescription, Linkage, Module, FuncId};
-use std::collections::HashMap;
-use std::slice;
+use cranelift_module::{Linkage, Module, FuncId};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+/// what went wrong in jitted code
+#[derive(Clone, Debug)]
+enum Failure {
+    Msg(String),
+    /// the value that no case matched
+    MatchError,
+}
+
+thread_local! {
+    /// the failure being unwound: the failure index, the value it is about and
+    /// the sites of the frames left so far, innermost first
+    static FAILED: RefCell<Option<(usize, i64, Vec<usize>)>> = const { RefCell::new(None) };
+}
+
+extern "C" fn jit_fail(failure: i64, site: i64, value: i64) {
+    FAILED.with(|f| *f.borrow_mut() = Some((failure as usize, value, vec![site as usize])));
+}
+
+extern "C" fn jit_unwind(site: i64) {
+    FAILED.with(|f| {
+        if let Some((_, _, sites)) = &mut *f.borrow_mut() {
+            sites.push(site as usize);
+        }
+    });
+}
+
+extern "C" fn jit_failed() -> i64 {
+    FAILED.with(|f| f.borrow().is_some() as i64)
+}
+
+/// why `JIT::compile` failed
+#[derive(Clone, Debug)]
+pub enum JitError {
+    /// the code uses what only the vm runs
+    Unsupported(String),
+    /// cranelift could not compile or link it
+    Module(String),
+}
+
+impl std::fmt::Display for JitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JitError::Unsupported(msg) | JitError::Module(msg) => write!(f, "{msg}"),
+        }
+    }
+}
 
 /// The basic JIT class.
+#[allow(clippy::upper_case_acronyms)]
 pub struct JIT {
     /// The function builder context, which is reused across multiple
     /// FunctionBuilder instances.
@@ -16,12 +70,28 @@ pub struct JIT {
     /// context per thread, though this isn't in the simple demo here.
     ctx: codegen::Context,
 
-    /// The data description, which is to data objects what `ctx` is to functions.
-    data_description: DataDescription,
-
     /// The module, with the jit backend, which manages the JIT'd
     /// functions.
     module: JITModule,
+
+    /// the host functions jitted code reports failures with
+    host: Host<FuncId>,
+    failures: Vec<Failure>,
+    /// the function and location of each place that can fail or unwind
+    sites: Vec<(Symbol, Span<()>)>,
+    /// class bodies and their parameter count
+    classes: HashMap<Symbol, Callee>,
+    /// the bodies of the classes each class inherits from, in the order they run
+    inherited: HashMap<Symbol, Vec<FuncId>>,
+    /// the names of the natives, a call to one is not supported
+    natives: HashSet<Symbol>,
+}
+
+#[derive(Clone, Copy)]
+struct Host<T> {
+    fail: T,
+    unwind: T,
+    failed: T,
 }
 
 impl Default for JIT {
@@ -35,113 +105,222 @@ impl Default for JIT {
         let isa = isa_builder
             .finish(settings::Flags::new(flag_builder))
             .unwrap();
-        let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
-
-        let module = JITModule::new(builder);
+        let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+        builder.symbol("jit_fail", jit_fail as *const u8);
+        builder.symbol("jit_unwind", jit_unwind as *const u8);
+        builder.symbol("jit_failed", jit_failed as *const u8);
+
+        let mut module = JITModule::new(builder);
+        let host = Host {
+            fail: declare_host(&mut module, "jit_fail", 3, false),
+            unwind: declare_host(&mut module, "jit_unwind", 1, false),
+            failed: declare_host(&mut module, "jit_failed", 0, true),
+        };
         Self {
             builder_context: FunctionBuilderContext::new(),
             ctx: module.make_context(),
-            data_description: DataDescription::new(),
             module,
+            host,
+            failures: vec![],
+            sites: vec![],
+            classes: HashMap::new(),
+            inherited: HashMap::new(),
+            natives: HashSet::new(),
         }
     }
 }
 
+fn declare_host(module: &mut JITModule, name: &str, params: usize, ret: bool) -> FuncId {
+    let mut sig = module.make_signature();
+    for _ in 0..params {
+        sig.params.push(AbiParam::new(types::I64));
+    }
+    if ret {
+        sig.returns.push(AbiParam::new(types::I64));
+    }
+    module
+        .declare_function(name, Linkage::Import, &sig)
+        .expect("problem declaring host function")
+}
+
+/// a function to compile: a class body, a method or a `def`
+struct JitFunc<'a> {
+    name: Symbol,
+    params: Vec<usize>,
+    block: &'a [Stmt],
+    /// the class of `this` in slot 0, for class bodies and methods
+    this: Option<Symbol>,
+    /// the `def`s a `def` captures, by their index in the functions being
+    /// compiled. it can not capture other values
+    upvalues: Vec<usize>,
+}
+
+/// a compiled function and its parameter count
+type Callee = (FuncId, usize);
+
+/// where the name of a `def` is, it tells the `def`s apart
+type DefAt = (FileId, usize);
+
+fn def_at(name: &Span<Symbol>) -> DefAt {
+    (name.file, name.offset)
+}
+
 impl JIT {
-    /// Compile a string in the toy language into machine code.
-    pub fn compile(&mut self, funcs: Vec<Func<'_>>) -> Result<*const u8, String> {
-        let mut main_id = FuncId::new(0);
-
-        for f in funcs {
-            // First, parse the string, producing AST nodes.
-            let Func{
-                name,
-                params,
-                return_type,
-                block
-            } = f;
-
-            // Then, translate the AST nodes into Cranelift IR.
-            self.translate(params, block)?;
-
-            // Next, declare the function to jit. Functions must be declared
-            // before they can be called, or defined.
-            //
-            // TODO: This may be an area where the API should be streamlined; should
-            // we have a version of `declare_function` that automatically declares
-            // the function?
+    /// a jit for code checked with `natives`, the code calling them is not
+    /// compiled
+    pub fn with_natives(natives: &Natives) -> Self {
+        Self { natives: natives.iter().map(|n| n.name).collect(), ..Self::default() }
+    }
+    /// Compile every class, its methods and the `def`s in them into machine
+    /// code, only `Int` and `Bool` values without closures or fields are
+    /// supported
+    pub fn compile(&mut self, classes: &[Class]) -> Result<(), JitError> {
+        let by_name: HashMap<Symbol, &Class> = classes.iter().map(|c| (c.name.data, c)).collect();
+        let mut funcs = vec![];
+        for class in classes {
+            funcs.push(JitFunc {
+                name: class.name.data,
+                params: std::iter::once(0).chain(class.args.iter().map(|(slot, _)| slot.data)).collect(),
+                block: &class.block,
+                this: Some(class.name.data),
+                upvalues: vec![],
+            });
+            // an inherited method is compiled again for each class, so calls
+            // on `this` are resolved statically
+            for (name, from) in class.methods.iter() {
+                let f = &by_name[from].funcs[name];
+                funcs.push(JitFunc {
+                    name: *name,
+                    params: f.params.clone(),
+                    block: &f.block,
+                    this: Some(class.name.data),
+                    upvalues: vec![],
+                });
+            }
+        }
+        let method_count = funcs.len();
+        let mut collector = Collector { funcs, def_ids: HashMap::new() };
+        for class in classes {
+            collector.collect(&class.block, &mut DefScope::default())?;
+            for f in class.funcs.values() {
+                collector.collect(&f.block, &mut DefScope::default())?;
+            }
+        }
+        let Collector { funcs, def_ids } = collector;
+
+        // declare everything first, so calls can refer to any function
+        let mut ids = vec![];
+        let mut callable = HashMap::new();
+        let mut methods: HashMap<Symbol, HashMap<Symbol, Callee>> = HashMap::new();
+        for (idx, f) in funcs.iter().enumerate() {
+            let mut sig = self.module.make_signature();
+            for _ in &f.params {
+                sig.params.push(AbiParam::new(types::I64));
+            }
+            sig.returns.push(AbiParam::new(types::I64));
             let id = self
                 .module
-                .declare_function(name.data, Linkage::Export, &self.ctx.func.signature)
-                .map_err(|e| e.to_string())?;
-
-            // Define the function to jit. This finishes compilation, although
-            // there may be outstanding relocations to perform. Currently, jit
-            // cannot finish relocations until all functions to be called are
-            // defined. For this toy demo for now, we'll just finalize the
-            // function below.
+                .declare_function(&format!("{}#{idx}", f.name), Linkage::Local, &sig)
+                .map_err(|e| JitError::Module(e.to_string()))?;
+            ids.push(id);
+            match f.this {
+                Some(class) if class == f.name => {
+                    self.classes.insert(class, (id, f.params.len()));
+                }
+                Some(class) => {
+                    methods.entry(class).or_default().insert(f.name, (id, f.params.len()));
+                }
+                None => {
+                    callable.insert(idx, (f.name, (id, f.params.len())));
+                }
+            }
+        }
+        for class in classes {
+            let inherited = class.linearization[1..].iter().rev().map(|c| self.classes[c].0).collect();
+            self.inherited.insert(class.name.data, inherited);
+        }
+
+        for (idx, (f, id)) in funcs.iter().zip(ids).enumerate() {
+            let methods = if idx < method_count { f.this.and_then(|c| methods.get(&c)) } else { None };
+            self.translate(f, &callable, &def_ids, methods)?;
             self.module
                 .define_function(id, &mut self.ctx)
-                .map_err(|e| e.to_string())?;
-
-            // Now that compilation is finished, we can clear out the context state.
+                .map_err(|e| JitError::Module(e.to_string()))?;
             self.module.clear_context(&mut self.ctx);
-
-            // Finalize the functions which we just defined, which resolves any
-            // outstanding relocations (patching in addresses, now that they're
-            // available).
-            self.module.finalize_definitions().unwrap();
-
-            if name.data == "main" {
-                main_id = id;
-            }
         }
 
-        // We can now retrieve a pointer to the machine code.
-        let code = self.module.get_finalized_function(main_id);
-
-        Ok(code)
+        // Finalize the functions which we just defined, which resolves any
+        // outstanding relocations (patching in addresses, now that they're
+        // available).
+        self.module.finalize_definitions().map_err(|e| JitError::Module(e.to_string()))?;
+        Ok(())
     }
 
-    /// Create a zero-initialized data section.
-    pub fn create_data(&mut self, name: &str, contents: Vec<u8>) -> Result<&[u8], String> {
-        // The steps here are analogous to `compile`, except that data is much
-        // simpler than functions.
-        self.data_description.define(contents.into_boxed_slice());
-        let id = self
-            .module
-            .declare_data(name, Linkage::Export, true, false)
-            .map_err(|e| e.to_string())?;
-
-        self.module
-            .define_data(id, &self.data_description)
-            .map_err(|e| e.to_string())?;
-        self.data_description.clear();
-        self.module.finalize_definitions().unwrap();
-        let buffer = self.module.get_finalized_data(id);
-        // TODO: Can we move the unsafe into cranelift?
-        Ok(unsafe { slice::from_raw_parts(buffer.0, buffer.1) })
+    /// run the bodies of a class and of the classes it inherits from, the
+    /// value of its own body is returned. failures come back with a trace
+    /// like the vm's
+    pub fn run(&mut self, class: Symbol) -> VmResult {
+        let Some(&(id, params)) = self.classes.get(&class) else {
+            return Err(RuntimeError {
+                msg: format!("unknown class `{class}`"),
+                at: Span::default(),
+                trace: vec![],
+            });
+        };
+        if params != 1 {
+            return Err(RuntimeError {
+                msg: format!("`{class}` takes {} arguments but 0 were given", params - 1),
+                at: Span::default(),
+                trace: vec![],
+            });
+        }
+        FAILED.with(|f| f.borrow_mut().take());
+        let mut ret = 0;
+        for id in self.inherited[&class].iter().chain([&id]) {
+            let code = self.module.get_finalized_function(*id);
+            // Safety: `code` was compiled with the default calling convention
+            // from a signature with only `this` returning one i64. there are
+            // no instances in jitted code, so `this` is never read
+            let code_fn = unsafe { mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) };
+            ret = code_fn(0);
+            if FAILED.with(|f| f.borrow().is_some()) {
+                break;
+            }
+        }
+        match FAILED.with(|f| f.borrow_mut().take()) {
+            None => Ok(VmValue::Int(ret)),
+            Some((failure, value, sites)) => {
+                let msg = match &self.failures[failure] {
+                    Failure::Msg(msg) => msg.clone(),
+                    Failure::MatchError => format!("match error: {:?}", VmValue::Int(value)),
+                };
+                let trace: Vec<_> = sites.iter()
+                    .map(|site| {
+                        let (func, at) = &self.sites[*site];
+                        Frame { func: *func, at: at.clone() }
+                    })
+                    .collect();
+                let at = trace.first().map(|f| f.at.clone()).unwrap_or_default();
+                Err(RuntimeError { msg, at, trace })
+            }
+        }
     }
 
-    // Translate from toy-language AST nodes into Cranelift IR.
+    // Translate from MIR into Cranelift IR.
     fn translate(
         &mut self,
-        params: Vec<(Span<usize>, Span<&'_ str>)>,
-        stmts: Vec<Stmt<'_>>,
-    ) -> Result<(), String> {
-        let the_return = 114514;
-
-        // Our toy language currently only supports I64 values, though Cranelift
-        // supports other types.
-        let int = self.module.target_config().pointer_type();
-
-        for _p in &params {
-            self.ctx.func.signature.params.push(AbiParam::new(int));
+        f: &JitFunc<'_>,
+        callable: &HashMap<usize, (Symbol, Callee)>,
+        def_ids: &HashMap<DefAt, usize>,
+        methods: Option<&HashMap<Symbol, Callee>>,
+    ) -> Result<(), JitError> {
+        for _p in &f.params {
+            self.ctx.func.signature.params.push(AbiParam::new(types::I64));
         }
 
         // Our toy language currently only supports one return value, though
         // Cranelift is designed to support more.
-        self.ctx.func.signature.returns.push(AbiParam::new(int));
+        self.ctx.func.signature.returns.push(AbiParam::new(types::I64));
 
         // Create the builder to build a function.
         let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_context);
@@ -151,8 +330,6 @@ impl JIT {
 
         // Since this is the entry block, add block parameters corresponding to
         // the function's parameters.
-        //
-        // TODO: Streamline the API here.
         builder.append_block_params_for_function_params(entry_block);
 
         // Tell the builder to emit code in this block.
@@ -163,27 +340,37 @@ impl JIT {
         // predecessors.
         builder.seal_block(entry_block);
 
-        // The toy language allows variables to be declared implicitly.
-        // Walk the AST and declare all implicitly-declared variables.
-        let variables =
-            declare_variables(int, &mut builder, &params, the_return, &stmts, entry_block);
-
-        // Now translate the statements of the function body.
+        let host = Host {
+            fail: self.module.declare_func_in_func(self.host.fail, builder.func),
+            unwind: self.module.declare_func_in_func(self.host.unwind, builder.func),
+            failed: self.module.declare_func_in_func(self.host.failed, builder.func),
+        };
         let mut trans = FunctionTranslator {
-            int,
             builder,
-            variables,
+            variables: HashMap::new(),
             module: &mut self.module,
+            callable,
+            def_ids,
+            defs: HashMap::new(),
+            upvalues: &f.upvalues,
+            methods,
+            classes: &self.classes,
+            natives: &self.natives,
+            func_refs: HashMap::new(),
+            host,
+            name: f.name,
+            failures: &mut self.failures,
+            sites: &mut self.sites,
+            loops: vec![],
         };
-        for stmt in stmts {
-            trans.translate_stmt(stmt);
+        for (i, slot) in f.params.iter().enumerate() {
+            let val = trans.builder.block_params(entry_block)[i];
+            let var = trans.variable(*slot);
+            trans.builder.def_var(var, val);
         }
 
-        // Set up the return variable of the function. Above, we declared a
-        // variable to hold the return value. Here, we just do a use of that
-        // variable.
-        let return_variable = trans.variables.get(&the_return).unwrap();
-        let return_value = trans.builder.use_var(*return_variable);
+        // Now translate the statements of the function body.
+        let return_value = trans.translate_block(f.block)?;
 
         // Emit the return instruction.
         trans.builder.ins().return_(&[return_value]);
@@ -194,51 +381,239 @@ impl JIT {
     }
 }
 
-/// A collection of state used for translating from toy-language AST nodes
-/// into Cranelift IR.
+/// the `def`s of the function being collected by their slots, and the ones it
+/// captures
+#[derive(Clone, Default)]
+struct DefScope {
+    slots: HashMap<usize, usize>,
+    upvalues: Vec<usize>,
+}
+
+/// finds the `def`s in blocks, they are compiled as functions of their own
+struct Collector<'a> {
+    funcs: Vec<JitFunc<'a>>,
+    /// the index of each `def` in `funcs`
+    def_ids: HashMap<DefAt, usize>,
+}
+
+impl<'a> Collector<'a> {
+    fn collect(&mut self, stmts: &'a [Stmt], scope: &mut DefScope) -> Result<(), JitError> {
+        // the `def`s of a block can call each other, they are known first
+        let mut defs = vec![];
+        for stmt in stmts {
+            if let Stmt::Func { name, slot, params, captures, return_type: _, block } = stmt {
+                let idx = self.funcs.len();
+                self.funcs.push(JitFunc { name: name.data, params: params.clone(), block, this: None, upvalues: vec![] });
+                self.def_ids.insert(def_at(name), idx);
+                scope.slots.insert(*slot, idx);
+                defs.push((idx, name, captures, block));
+            }
+        }
+        for (idx, name, captures, block) in defs {
+            let upvalues = captures.iter()
+                .map(|c| match c {
+                    Capture::Local(slot) => scope.slots.get(slot).copied(),
+                    Capture::Upvalue(i) => scope.upvalues.get(*i).copied(),
+                })
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| JitError::Unsupported(format!("`{}` captures variables, the jit does not support closures", name.data)))?;
+            self.funcs[idx].upvalues = upvalues.clone();
+            self.collect(block, &mut DefScope { slots: HashMap::new(), upvalues })?;
+        }
+        for stmt in stmts {
+            match stmt {
+                Stmt::Expr(e) | Stmt::Let(_, e) | Stmt::Assign(_, e) | Stmt::Return(e) => {
+                    self.collect_in_expr(e, scope)?
+                }
+                Stmt::SetField(obj, _, e) => {
+                    self.collect_in_expr(obj, scope)?;
+                    self.collect_in_expr(e, scope)?;
+                }
+                Stmt::Break(_) | Stmt::Continue(_) | Stmt::Func { .. } => {}
+                Stmt::While(cond, block, step) => {
+                    self.collect_in_expr(cond, scope)?;
+                    self.collect(block, &mut scope.clone())?;
+                    self.collect(step, &mut scope.clone())?;
+                }
+                Stmt::Block(b) => self.collect(b, &mut scope.clone())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn collect_in_expr(&mut self, expr: &'a Expression, scope: &DefScope) -> Result<(), JitError> {
+        match expr {
+            Expression::If(cond, then_body, else_body) => {
+                self.collect_in_expr(cond, scope)?;
+                self.collect(then_body, &mut scope.clone())?;
+                if let Some(body) = else_body {
+                    self.collect(body, &mut scope.clone())?;
+                }
+            }
+            Expression::Block(b) => self.collect(b, &mut scope.clone())?,
+            Expression::Match(e, cases) => {
+                self.collect_in_expr(e, scope)?;
+                for case in cases {
+                    self.collect(&case.block, &mut scope.clone())?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// A collection of state used for translating from MIR into Cranelift IR.
 struct FunctionTranslator<'a> {
-    int: types::Type,
     builder: FunctionBuilder<'a>,
+    /// MIR stack slots, a reused slot is just a new definition
     variables: HashMap<usize, Variable>,
     module: &'a mut JITModule,
+    /// the `def`s by their index in the functions being compiled
+    callable: &'a HashMap<usize, (Symbol, Callee)>,
+    def_ids: &'a HashMap<DefAt, usize>,
+    /// the `def`s of the enclosing blocks by their slots
+    defs: HashMap<usize, usize>,
+    /// the `def`s this function captures
+    upvalues: &'a [usize],
+    /// the methods of `this`, when the function has one
+    methods: Option<&'a HashMap<Symbol, Callee>>,
+    classes: &'a HashMap<Symbol, Callee>,
+    natives: &'a HashSet<Symbol>,
+    func_refs: HashMap<FuncId, FuncRef>,
+    host: Host<FuncRef>,
+    name: Symbol,
+    failures: &'a mut Vec<Failure>,
+    sites: &'a mut Vec<(Symbol, Span<()>)>,
+    /// the `continue` and `break` targets of the enclosing loops
+    loops: Vec<(Block, Block)>,
+}
+
+fn unsupported<T>(what: &str) -> Result<T, JitError> {
+    Err(JitError::Unsupported(format!("{what} is not supported by the jit")))
 }
 
 impl<'a> FunctionTranslator<'a> {
+    fn variable(&mut self, slot: usize) -> Variable {
+        if let Some(var) = self.variables.get(&slot) {
+            return *var;
+        }
+        let var = Variable::new(self.variables.len());
+        self.builder.declare_var(var, types::I64);
+        self.variables.insert(slot, var);
+        var
+    }
+    fn int(&mut self, x: i64) -> Value {
+        self.builder.ins().iconst(types::I64, x)
+    }
+    fn site(&mut self, at: Option<Span<()>>) -> Value {
+        self.sites.push((self.name, at.unwrap_or_default()));
+        let site = self.sites.len() as i64 - 1;
+        self.int(site)
+    }
+    /// code after a jump or a return is never reached, give it a block
+    fn dead_block(&mut self) {
+        let block = self.builder.create_block();
+        self.builder.switch_to_block(block);
+        self.builder.seal_block(block);
+    }
+    /// report `failure` and leave the function
+    fn fail(&mut self, failure: Failure, at: Option<Span<()>>, value: Value) {
+        self.failures.push(failure);
+        let failure = self.int(self.failures.len() as i64 - 1);
+        let site = self.site(at);
+        self.builder.ins().call(self.host.fail, &[failure, site, value]);
+        let zero = self.int(0);
+        self.builder.ins().return_(&[zero]);
+        self.dead_block();
+    }
+    fn fail_msg(&mut self, msg: impl Into<String>, at: Option<Span<()>>) -> Value {
+        let zero = self.int(0);
+        self.fail(Failure::Msg(msg.into()), at, zero);
+        self.int(0)
+    }
+    /// fail with `msg` when `bad` is set
+    fn check(&mut self, bad: Value, msg: &str, at: Option<Span<()>>) {
+        let fail_block = self.builder.create_block();
+        let ok_block = self.builder.create_block();
+        self.builder.ins().brif(bad, fail_block, &[], ok_block, &[]);
+        self.builder.switch_to_block(fail_block);
+        self.builder.seal_block(fail_block);
+        let zero = self.int(0);
+        self.fail(Failure::Msg(msg.to_owned()), at, zero);
+        self.builder.switch_to_block(ok_block);
+        self.builder.seal_block(ok_block);
+    }
+    fn bool(&mut self, cond: Value) -> Value {
+        self.builder.ins().uextend(types::I64, cond)
+    }
+    fn translate_block(&mut self, stmts: &[Stmt]) -> Result<Value, JitError> {
+        let defs = self.defs.clone();
+        for stmt in stmts {
+            if let Stmt::Func { name, slot, .. } = stmt {
+                self.defs.insert(*slot, self.def_ids[&def_at(name)]);
+            }
+        }
+        let mut ret = None;
+        for stmt in stmts {
+            ret = Some(self.translate_stmt(stmt)?);
+        }
+        self.defs = defs;
+        Ok(match ret {
+            Some(ret) => ret,
+            None => self.int(0),
+        })
+    }
     /// When you write out instructions in Cranelift, you get back `Value`s. You
     /// can then use these references in other instructions.
-    fn translate_stmt(&mut self, stmt: Stmt<'_>) -> Value {
+    fn translate_stmt(&mut self, stmt: &Stmt) -> Result<Value, JitError> {
         match stmt {
-            Stmt::Expr(expr) => self.translate_expr(expr),
-            Stmt::Let(name, expr) => {
+            Stmt::Expr(expr) => return self.translate_expr(expr),
+            Stmt::Let(slot, expr) => {
                 // `def_var` is used to write the value of a variable. Note that
                 // variables can have multiple definitions. Cranelift will
                 // convert them into SSA form for itself automatically.
-                let new_value = self.translate_expr(expr);
-                let variable = self.variables.get(&name.data).unwrap();
-                self.builder.def_var(*variable, new_value);
-                new_value
-            },
-            Stmt::Assign(name, expr) => {
-                let new_value = self.translate_expr(expr);
-                let variable = self.variables.get(&name.data).unwrap();
-                self.builder.def_var(*variable, new_value);
-                new_value
+                let new_value = self.translate_expr(expr)?;
+                let variable = self.variable(slot.data);
+                self.builder.def_var(variable, new_value);
             }
+            Stmt::Assign(var, expr) => match var.data {
+                Var::Local(slot) => {
+                    let new_value = self.translate_expr(expr)?;
+                    let variable = self.variable(slot);
+                    self.builder.def_var(variable, new_value);
+                }
+                Var::Upvalue(_) => return unsupported("a captured variable"),
+                Var::Global(name) => {
+                    self.fail_msg(format!("can not assign to `{name}`"), Some(var.with_data(())));
+                }
+            },
             Stmt::Return(expr) => {
-                let new_value = self.translate_expr(expr);
-                let variable = self.variables.get(&114514).unwrap();
-                self.builder.def_var(*variable, new_value);
-                new_value
+                let new_value = self.translate_expr(expr)?;
+                self.builder.ins().return_(&[new_value]);
+                self.dead_block();
+            }
+            Stmt::Break(at) | Stmt::Continue(at) => match self.loops.last() {
+                Some(&(step_block, exit_block)) => {
+                    let target = if matches!(stmt, Stmt::Break(_)) { exit_block } else { step_block };
+                    self.builder.ins().jump(target, &[]);
+                    self.dead_block();
+                }
+                None => {
+                    let kind = if matches!(stmt, Stmt::Break(_)) { "break" } else { "continue" };
+                    self.fail_msg(format!("{kind} outside of a loop"), Some(at.clone()));
+                }
             },
-            Stmt::While(cond, block) => {
+            Stmt::While(cond, block, step) => {
                 let header_block = self.builder.create_block();
                 let body_block = self.builder.create_block();
+                let step_block = self.builder.create_block();
                 let exit_block = self.builder.create_block();
 
                 self.builder.ins().jump(header_block, &[]);
                 self.builder.switch_to_block(header_block);
 
-                let condition_value = self.translate_expr(cond);
+                let condition_value = self.translate_expr(cond)?;
                 self.builder
                     .ins()
                     .brif(condition_value, body_block, &[], exit_block, &[]);
@@ -246,9 +621,14 @@ impl<'a> FunctionTranslator<'a> {
                 self.builder.switch_to_block(body_block);
                 self.builder.seal_block(body_block);
 
-                for expr in block {
-                    self.translate_stmt(expr);
-                }
+                self.loops.push((step_block, exit_block));
+                self.translate_block(block)?;
+                self.loops.pop();
+                self.builder.ins().jump(step_block, &[]);
+
+                self.builder.switch_to_block(step_block);
+                self.builder.seal_block(step_block);
+                self.translate_block(step)?;
                 self.builder.ins().jump(header_block, &[]);
 
                 self.builder.switch_to_block(exit_block);
@@ -257,98 +637,219 @@ impl<'a> FunctionTranslator<'a> {
                 // more backedges to the header to exits to the bottom.
                 self.builder.seal_block(header_block);
                 self.builder.seal_block(exit_block);
-
-                // Just return 0 for now.
-                self.builder.ins().iconst(self.int, 0)
-            },
-            Stmt::Block(b) => {
-                let mut ret = self.builder.ins().iconst(self.int, 0);
-                for stmt in b {
-                    ret = self.translate_stmt(stmt);
-                }
-                ret
-            },
-            Stmt::Func { name, params, return_type, block } => {
-                todo!()//TODO:
             }
+            Stmt::Block(b) => return self.translate_block(b),
+            // compiled on their own by `collect_funcs`
+            Stmt::SetField(..) => return unsupported("a field"),
+            Stmt::Func { .. } => {}
         }
+        Ok(self.int(0))
     }
-    fn translate_expr(&mut self, expr: Expression<'_>) -> Value {
-        match expr {
-            Expression::Int(imm) => self.builder.ins().iconst(self.int, imm.data),
-            Expression::String(idx, data) => {todo!()}
-            Expression::Bool(imm) => self.builder.ins().iconst(self.int, imm as i64),
-            Expression::Name(name) => {
-                let variable = self.variables.get(&name.data).expect("variable not defined");
-                self.builder.use_var(*variable)
+    fn translate_expr(&mut self, expr: &Expression) -> Result<Value, JitError> {
+        Ok(match expr {
+            Expression::Int(imm) => self.int(imm.data),
+            Expression::String(..) => return unsupported("a string"),
+            Expression::Bool(imm) => self.int(imm.data as i64),
+            Expression::Name(name) => match name.data {
+                Var::Local(slot) if self.defs.contains_key(&slot) => return unsupported("a `def` as a value"),
+                Var::Local(slot) => {
+                    let variable = self.variable(slot);
+                    self.builder.use_var(variable)
+                }
+                Var::Upvalue(_) => return unsupported("a captured variable"),
+                Var::Global(name) => return unsupported(&format!("`{name}` as a value")),
             },
             Expression::Add(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().iadd(lhs, rhs)
-            },
+                let (a, b) = self.translate_pair(lhs, rhs)?;
+                let r = self.builder.ins().iadd(a, b);
+                // the sign of the result differs from both operands
+                let x = self.builder.ins().bxor(a, r);
+                let y = self.builder.ins().bxor(b, r);
+                let z = self.builder.ins().band(x, y);
+                let bad = self.builder.ins().icmp_imm(IntCC::SignedLessThan, z, 0);
+                self.check(bad, "integer overflow", lhs.span());
+                r
+            }
             Expression::Sub(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().isub(lhs, rhs)
-            },
+                let (a, b) = self.translate_pair(lhs, rhs)?;
+                let r = self.builder.ins().isub(a, b);
+                // the operands differ in sign and the result has the sign of `b`
+                let x = self.builder.ins().bxor(a, b);
+                let y = self.builder.ins().bxor(a, r);
+                let z = self.builder.ins().band(x, y);
+                let bad = self.builder.ins().icmp_imm(IntCC::SignedLessThan, z, 0);
+                self.check(bad, "integer overflow", lhs.span());
+                r
+            }
             Expression::Mul(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().imul(lhs, rhs)
-            },
-            Expression::Div(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().udiv(lhs, rhs)
-            },
-            Expression::Eq(lhs, rhs) => {
-                self.translate_icmp(IntCC::Equal, *lhs, *rhs)
-            },
-            Expression::Neq(lhs, rhs) => self.translate_icmp(IntCC::NotEqual, *lhs, *rhs),
-            //Expr::Lt(lhs, rhs) => self.translate_icmp(IntCC::SignedLessThan, *lhs, *rhs),
-            //Expr::Le(lhs, rhs) => self.translate_icmp(IntCC::SignedLessThanOrEqual, *lhs, *rhs),
-            //Expr::Gt(lhs, rhs) => self.translate_icmp(IntCC::SignedGreaterThan, *lhs, *rhs),
-            //Expr::Ge(lhs, rhs) => self.translate_icmp(IntCC::SignedGreaterThanOrEqual, *lhs, *rhs),
+                let (a, b) = self.translate_pair(lhs, rhs)?;
+                let r = self.builder.ins().imul(a, b);
+                // the high half is not just the sign of the low half
+                let hi = self.builder.ins().smulhi(a, b);
+                let sign = self.builder.ins().sshr_imm(r, 63);
+                let bad = self.builder.ins().icmp(IntCC::NotEqual, hi, sign);
+                self.check(bad, "integer overflow", lhs.span());
+                r
+            }
+            Expression::Div(lhs, rhs) | Expression::Mod(lhs, rhs) => {
+                let (a, b) = self.translate_pair(lhs, rhs)?;
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, b, 0);
+                self.check(zero, "division by zero", lhs.span());
+                let min = self.builder.ins().icmp_imm(IntCC::Equal, a, i64::MIN);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, b, -1);
+                let bad = self.builder.ins().band(min, minus_one);
+                self.check(bad, "integer overflow", lhs.span());
+                if matches!(expr, Expression::Div(..)) {
+                    self.builder.ins().sdiv(a, b)
+                } else {
+                    self.builder.ins().srem(a, b)
+                }
+            }
+            Expression::Eq(lhs, rhs) => self.translate_icmp(IntCC::Equal, lhs, rhs)?,
+            Expression::Neq(lhs, rhs) => self.translate_icmp(IntCC::NotEqual, lhs, rhs)?,
+            Expression::Lt(lhs, rhs) => self.translate_icmp(IntCC::SignedLessThan, lhs, rhs)?,
+            Expression::Le(lhs, rhs) => self.translate_icmp(IntCC::SignedLessThanOrEqual, lhs, rhs)?,
+            Expression::Gt(lhs, rhs) => self.translate_icmp(IntCC::SignedGreaterThan, lhs, rhs)?,
+            Expression::Ge(lhs, rhs) => self.translate_icmp(IntCC::SignedGreaterThanOrEqual, lhs, rhs)?,
+            Expression::And(lhs, rhs) | Expression::Or(lhs, rhs) => {
+                let a = self.translate_expr(lhs)?;
+                let rhs_block = self.builder.create_block();
+                let merge_block = self.builder.create_block();
+                self.builder.append_block_param(merge_block, types::I64);
+                if matches!(expr, Expression::And(..)) {
+                    self.builder.ins().brif(a, rhs_block, &[], merge_block, &[a]);
+                } else {
+                    self.builder.ins().brif(a, merge_block, &[a], rhs_block, &[]);
+                }
+                self.builder.switch_to_block(rhs_block);
+                self.builder.seal_block(rhs_block);
+                let b = self.translate_expr(rhs)?;
+                self.builder.ins().jump(merge_block, &[b]);
+                self.builder.switch_to_block(merge_block);
+                self.builder.seal_block(merge_block);
+                self.builder.block_params(merge_block)[0]
+            }
+            Expression::Not(e) => {
+                let a = self.translate_expr(e)?;
+                let r = self.builder.ins().icmp_imm(IntCC::Equal, a, 0);
+                self.bool(r)
+            }
+            Expression::Neg(e) => {
+                let a = self.translate_expr(e)?;
+                let bad = self.builder.ins().icmp_imm(IntCC::Equal, a, i64::MIN);
+                self.check(bad, "integer overflow", e.span());
+                self.builder.ins().ineg(a)
+            }
+            Expression::Field(..) => return unsupported("a field access"),
+            Expression::MethodCall(obj, name, args) => {
+                // only methods of `this` are known without instances
+                let (Expression::Name(Span { data: Var::Local(0), .. }), Some(methods)) = (&**obj, self.methods) else {
+                    return unsupported("a method call");
+                };
+                let this = self.translate_expr(obj)?;
+                let callee = methods.get(&name.data).copied();
+                self.translate_call(name.data, callee, expr.span().unwrap_or_default(), Some(this), args)?
+            }
+            Expression::Lambda(..) => return unsupported("a lambda"),
+            Expression::Apply(f, args) => {
+                // only the `def`s are known without closures
+                let def = match &**f {
+                    Expression::Name(Span { data: Var::Local(slot), .. }) => self.defs.get(slot).copied(),
+                    Expression::Name(Span { data: Var::Upvalue(idx), .. }) => self.upvalues.get(*idx).copied(),
+                    _ => None,
+                };
+                let Some((name, callee)) = def.map(|def| self.callable[&def]) else {
+                    return unsupported("calling a function value");
+                };
+                self.translate_call(name, Some(callee), expr.span().unwrap_or_default(), None, args)?
+            }
+            Expression::Tuple(..) => return unsupported("a tuple"),
             Expression::Call(name, args) => {
-                //TODO:
-                if name.data == "println" {
-                    todo!()
-                }else {
-                    self.translate_call(name.data, args)
+                if BUILT_INS.iter().any(|(n, _)| name.data == *n) {
+                    return unsupported(&format!("built-in `{}`", name.data));
                 }
-            },
+                if self.natives.contains(&name.data) {
+                    return unsupported(&format!("native `{}`", name.data));
+                }
+                if self.classes.contains_key(&name.data) {
+                    return unsupported(&format!("creating an instance of `{}`", name.data));
+                }
+                self.translate_call(name.data, None, name.with_data(()), None, args)?
+            }
             Expression::If(cond, then_body, else_body) => {
-                self.translate_if_else(*cond, then_body, else_body)
-            },
-        }
-        /*match expr {
-            Expr::Call(name, args) => self.translate_call(name, args),
-            Expr::GlobalDataAddr(name) => self.translate_global_data_addr(name),
-            Expr::Identifier(name) => {
-                // `use_var` is used to read the value of a variable.
-                let variable = self.variables.get(&name).expect("variable not defined");
-                self.builder.use_var(*variable)
+                self.translate_if_else(cond, then_body, else_body.as_deref())?
             }
-            Expr::WhileLoop(condition, loop_body) => {
-                self.translate_while_loop(*condition, loop_body)
+            Expression::Block(b) => self.translate_block(b)?,
+            Expression::Match(e, cases) => {
+                let value = self.translate_expr(e)?;
+                let merge_block = self.builder.create_block();
+                self.builder.append_block_param(merge_block, types::I64);
+                for case in cases {
+                    let next_block = self.builder.create_block();
+                    self.translate_pattern(&case.pattern, value, next_block)?;
+                    if let Some(guard) = &case.guard {
+                        let g = self.translate_expr(guard)?;
+                        let body_block = self.builder.create_block();
+                        self.builder.ins().brif(g, body_block, &[], next_block, &[]);
+                        self.builder.switch_to_block(body_block);
+                        self.builder.seal_block(body_block);
+                    }
+                    let ret = self.translate_block(&case.block)?;
+                    self.builder.ins().jump(merge_block, &[ret]);
+                    self.builder.switch_to_block(next_block);
+                    self.builder.seal_block(next_block);
+                }
+                self.fail(Failure::MatchError, e.span(), value);
+                let zero = self.int(0);
+                self.builder.ins().jump(merge_block, &[zero]);
+                self.builder.switch_to_block(merge_block);
+                self.builder.seal_block(merge_block);
+                self.builder.block_params(merge_block)[0]
             }
-        }*/
+        })
     }
 
-    fn translate_icmp(&mut self, cmp: IntCC, lhs: Expression<'_>, rhs: Expression<'_>) -> Value {
-        let lhs = self.translate_expr(lhs);
-        let rhs = self.translate_expr(rhs);
-        self.builder.ins().icmp(cmp, lhs, rhs)
+    /// test `value` against `pattern`, jumping to `next_block` if it does not match
+    fn translate_pattern(&mut self, pattern: &Pattern, value: Value, next_block: Block) -> Result<(), JitError> {
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Literal(e) => {
+                let lit = self.translate_expr(e)?;
+                let eq = self.builder.ins().icmp(IntCC::Equal, lit, value);
+                let ok_block = self.builder.create_block();
+                self.builder.ins().brif(eq, ok_block, &[], next_block, &[]);
+                self.builder.switch_to_block(ok_block);
+                self.builder.seal_block(ok_block);
+            }
+            Pattern::Bind(slot) => {
+                let variable = self.variable(slot.data);
+                self.builder.def_var(variable, value);
+            }
+            Pattern::Tuple(_) => return unsupported("a tuple pattern"),
+            Pattern::Extractor(..) => return unsupported("an extractor pattern"),
+        }
+        Ok(())
+    }
+
+    fn translate_pair(&mut self, lhs: &Expression, rhs: &Expression) -> Result<(Value, Value), JitError> {
+        let lhs = self.translate_expr(lhs)?;
+        let rhs = self.translate_expr(rhs)?;
+        Ok((lhs, rhs))
+    }
+
+    fn translate_icmp(&mut self, cmp: IntCC, lhs: &Expression, rhs: &Expression) -> Result<Value, JitError> {
+        let (lhs, rhs) = self.translate_pair(lhs, rhs)?;
+        let r = self.builder.ins().icmp(cmp, lhs, rhs);
+        Ok(self.bool(r))
     }
 
     fn translate_if_else(
         &mut self,
-        condition: Expression<'_>,
-        then_body: Vec<Stmt<'_>>,
-        else_body: Option<Vec<Stmt<'_>>>,
-    ) -> Value {
-        let condition_value = self.translate_expr(condition);
+        condition: &Expression,
+        then_body: &[Stmt],
+        else_body: Option<&[Stmt]>,
+    ) -> Result<Value, JitError> {
+        let condition_value = self.translate_expr(condition)?;
 
         let then_block = self.builder.create_block();
         let else_block = self.builder.create_block();
@@ -359,7 +860,7 @@ impl<'a> FunctionTranslator<'a> {
         // the then and else bodies. Cranelift uses block parameters,
         // so set up a parameter in the merge block, and we'll pass
         // the return values to it from the branches.
-        self.builder.append_block_param(merge_block, self.int);
+        self.builder.append_block_param(merge_block, types::I64);
 
         // Test the if condition and conditionally branch.
         self.builder
@@ -368,22 +869,17 @@ impl<'a> FunctionTranslator<'a> {
 
         self.builder.switch_to_block(then_block);
         self.builder.seal_block(then_block);
-        let mut then_return = self.builder.ins().iconst(self.int, 0);
-        for stmt in then_body {
-            then_return = self.translate_stmt(stmt);
-        }
+        let then_return = self.translate_block(then_body)?;
 
         // Jump to the merge block, passing it the block return value.
         self.builder.ins().jump(merge_block, &[then_return]);
 
         self.builder.switch_to_block(else_block);
         self.builder.seal_block(else_block);
-        let mut else_return = self.builder.ins().iconst(self.int, 0);
-        if let Some(body) = else_body {
-        for stmt in body {
-            else_return = self.translate_stmt(stmt);
-        }
-        }
+        let else_return = match else_body {
+            Some(body) => self.translate_block(body)?,
+            None => self.int(0),
+        };
 
         // Jump to the merge block, passing it the block return value.
         self.builder.ins().jump(merge_block, &[else_return]);
@@ -396,159 +892,57 @@ impl<'a> FunctionTranslator<'a> {
 
         // Read the value of the if-else by reading the merge block
         // parameter.
-        let phi = self.builder.block_params(merge_block)[0];
-
-        phi
+        Ok(self.builder.block_params(merge_block)[0])
     }
 
-    /*fn translate_while_loop(&mut self, condition: Expr, loop_body: Vec<Expr>) -> Value {
-        let header_block = self.builder.create_block();
-        let body_block = self.builder.create_block();
-        let exit_block = self.builder.create_block();
-
-        self.builder.ins().jump(header_block, &[]);
-        self.builder.switch_to_block(header_block);
-
-        let condition_value = self.translate_expr(condition);
-        self.builder
-            .ins()
-            .brif(condition_value, body_block, &[], exit_block, &[]);
-
-        self.builder.switch_to_block(body_block);
-        self.builder.seal_block(body_block);
-
-        for expr in loop_body {
-            self.translate_expr(expr);
-        }
-        self.builder.ins().jump(header_block, &[]);
-
-        self.builder.switch_to_block(exit_block);
-
-        // We've reached the bottom of the loop, so there will be no
-        // more backedges to the header to exits to the bottom.
-        self.builder.seal_block(header_block);
-        self.builder.seal_block(exit_block);
-
-        // Just return 0 for now.
-        self.builder.ins().iconst(self.int, 0)
-    }*/
-
-    fn translate_call(&mut self, name: &str, args: Vec<Expression<'_>>) -> Value {
-        let mut sig = self.module.make_signature();
-
-        // Add a parameter for each argument.
-        for _arg in &args {
-            sig.params.push(AbiParam::new(self.int));
+    /// call a `def` or a method, a method gets `this` before the arguments. a
+    /// failure in the callee leaves this function too and adds the call site
+    /// to the trace
+    fn translate_call(
+        &mut self,
+        name: Symbol,
+        callee: Option<Callee>,
+        call_site: Span<()>,
+        this: Option<Value>,
+        args: &[Expression],
+    ) -> Result<Value, JitError> {
+        let Some((callee, params)) = callee else {
+            return Ok(self.fail_msg(format!("unknown function `{name}`"), Some(call_site)));
+        };
+        let params = params - this.is_some() as usize;
+        if params != args.len() {
+            let msg = format!("`{name}` takes {params} arguments but {} were given", args.len());
+            return Ok(self.fail_msg(msg, Some(call_site)));
         }
+        let local_callee = match self.func_refs.get(&callee) {
+            Some(f) => *f,
+            None => {
+                let f = self.module.declare_func_in_func(callee, self.builder.func);
+                self.func_refs.insert(callee, f);
+                f
+            }
+        };
 
-        // For simplicity for now, just make all calls return a single I64.
-        sig.returns.push(AbiParam::new(self.int));
-
-        // TODO: Streamline the API here?
-        let callee = self
-            .module
-            .declare_function(name, Linkage::Import, &sig)
-            .expect("problem declaring function");
-        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
-
-        let mut arg_values = Vec::new();
+        let mut arg_values: Vec<_> = this.into_iter().collect();
         for arg in args {
-            arg_values.push(self.translate_expr(arg))
+            arg_values.push(self.translate_expr(arg)?)
         }
         let call = self.builder.ins().call(local_callee, &arg_values);
-        self.builder.inst_results(call)[0]
-    }
-
-    /*fn translate_global_data_addr(&mut self, name: String) -> Value {
-        let sym = self
-            .module
-            .declare_data(&name, Linkage::Export, true, false)
-            .expect("problem declaring data object");
-        let local_id = self.module.declare_data_in_func(sym, self.builder.func);
-
-        let pointer = self.module.target_config().pointer_type();
-        self.builder.ins().symbol_value(pointer, local_id)
-    }*/
-}
-
-fn declare_variables(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    params: &[(Span<usize>, Span<&'_ str>)],
-    the_return: usize,
-    stmts: &[Stmt],
-    entry_block: Block,
-) -> HashMap<usize, Variable> {
-    let mut variables = HashMap::new();
-    let mut index = 0;
-
-    for (i, name) in params.iter().enumerate() {
-        // TODO: cranelift_frontend should really have an API to make it easy to set
-        // up param variables.
-        let val = builder.block_params(entry_block)[i];
-        let var = declare_variable(int, builder, &mut variables, &mut index, name.0.data);
-        builder.def_var(var, val);
-    }
-    let zero = builder.ins().iconst(int, 0);
-    let return_variable = declare_variable(int, builder, &mut variables, &mut index, the_return);
-    builder.def_var(return_variable, zero);
-    for expr in stmts {
-        declare_variables_in_stmt(int, builder, &mut variables, &mut index, expr);
-    }
-
-    variables
-}
-
-/// Recursively descend through the AST, translating all implicit
-/// variable declarations.
-fn declare_variables_in_stmt(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    variables: &mut HashMap<usize, Variable>,
-    index: &mut usize,
-    expr: &Stmt<'_>,
-) {
-    match expr {
-        Stmt::Let(name, _) => {
-            declare_variable(int, builder, variables, index, name.data);
-        }
-        Stmt::Expr(Expression::If(_cond, then_body, else_body)) => {
-            for stmt in then_body {
-                declare_variables_in_stmt(int, builder, variables, index, stmt);
-            }
-            if let Some(body) = else_body {
-                for stmt in body {
-                    declare_variables_in_stmt(int, builder, variables, index, stmt);
-                }
-            }
-        }
-        Stmt::Block(b) => {
-            for stmt in b {
-                declare_variables_in_stmt(int, builder, variables, index, stmt);
-            }
-        }
-        //Expr::WhileLoop(ref _condition, ref loop_body) => {
-        //    for stmt in loop_body {
-        //        declare_variables_in_stmt(int, builder, variables, index, stmt);
-        //    }
-        //}
-        _ => (),
-    }
-}
-
-/// Declare a single variable declaration.
-fn declare_variable(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    variables: &mut HashMap<usize, Variable>,
-    index: &mut usize,
-    name: usize,
-) -> Variable {
-    let var = Variable::new(*index);
-    if !variables.contains_key(&name) {
-        variables.insert(name.into(), var);
-        builder.declare_var(var, int);
-        *index += 1;
+        let ret = self.builder.inst_results(call)[0];
+
+        let failed = self.builder.ins().call(self.host.failed, &[]);
+        let failed = self.builder.inst_results(failed)[0];
+        let unwind_block = self.builder.create_block();
+        let ok_block = self.builder.create_block();
+        self.builder.ins().brif(failed, unwind_block, &[], ok_block, &[]);
+        self.builder.switch_to_block(unwind_block);
+        self.builder.seal_block(unwind_block);
+        let site = self.site(Some(call_site));
+        self.builder.ins().call(self.host.unwind, &[site]);
+        let zero = self.int(0);
+        self.builder.ins().return_(&[zero]);
+        self.builder.switch_to_block(ok_block);
+        self.builder.seal_block(ok_block);
+        Ok(ret)
     }
-    var
 }
@@ -1,88 +1,184 @@
 pub mod hir;
 pub mod mir;
-mod built_in;
-mod ty;
-//mod jit;
-mod vm;
-use hir::parse_to_hir;
+pub mod built_in;
+pub mod native;
+pub mod ty;
+mod jit;
+pub mod vm;
+pub mod value;
+pub mod bytecode;
+pub mod machine;
+pub mod session;
+pub mod diagnostic;
+pub mod repl;
+pub mod index;
+pub mod netlist;
+use diagnostic::{emit, emit_message, emit_note, MessageFormat, SourceMap, Sources};
 use mir::hir_to_mir;
-//use typort_parser::simple_example::*;
-use core::mem;
-use std::path::Path;
-
-#[derive(Debug, Clone)]
-pub struct Span<T> {
-    pub data: T,
-}
-
-impl<T> Span<T> {
-    pub fn map<F, O>(self, f: F) -> Span<O>
-    where
-        F: Fn(T) -> O
-    {
-        Span {
-            data: f(self.data)
+use native::Natives;
+use std::path::{Path, PathBuf};
+
+pub use typort_parser::ast::{FileId, Ident, Span, Symbol, TypeExpr};
+pub use diagnostic::{Diagnostic, Severity};
+pub use session::{Design, Session};
+pub use repl::{main_repl, Repl};
+
+/// why `run_code_vm` did not give a value
+#[derive(Debug)]
+pub enum RunError {
+    /// a source file that can not be read
+    Io(PathBuf, std::io::ErrorKind),
+    Parse(Vec<typort_parser::ParseError>),
+    /// no class with the given name, or no object extending `App`
+    NoTop(Option<String>),
+    /// errors found while checking the program, e.g. calls to natives not
+    /// matching their signatures
+    Check(Vec<Diagnostic>),
+    Runtime(value::RuntimeError),
+    /// the program uses something the jit can not compile
+    Jit(String),
+}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Io(path, e) => write!(f, "error: can not read {}: {e}", path.display()),
+            RunError::Parse(errors) => {
+                write!(f, "parse error")?;
+                for e in errors {
+                    write!(f, "\n    {} at line {}", e.msg, e.range.0.0 + 1)?;
+                }
+                Ok(())
+            }
+            RunError::Check(diag) => {
+                write!(f, "check error")?;
+                for d in diag {
+                    write!(f, "\n    {} at line {}", d.msg, d.range.0.0 + 1)?;
+                }
+                Ok(())
+            }
+            RunError::NoTop(Some(top)) => write!(f, "error: can not find `{top}`"),
+            RunError::NoTop(None) => write!(f, "error: can not find an object extending App"),
+            RunError::Runtime(e) => write!(f, "{e}"),
+            RunError::Jit(msg) => write!(f, "error: {msg}"),
         }
     }
 }
 
-impl<T> From<typort_parser::simple_example::Span<T>> for Span<T> {
-    fn from(value: typort_parser::simple_example::Span<T>) -> Self {
-        Span { data: value.data }
-    }
+pub fn main_cli(path: &Path, top: Option<String>, jit: bool, format: MessageFormat) {
+    main_cli_with(path, top, jit, format, &Natives::default())
 }
 
-type Line = usize;
-type Col = usize;
+/// `main_cli` for a tool of its own that registers natives, they run on the
+/// vm only. exits with 1 on errors
+pub fn main_cli_with(path: &Path, top: Option<String>, jit: bool, format: MessageFormat, natives: &Natives) {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            emit_message(&format!("can not read {}: {e}", path.display()), format);
+            std::process::exit(1);
+        }
+    };
+    let ret = if jit {
+        run_code_jit(&text, top, natives).map(|(value, fallback)| {
+            if let Some(why) = fallback {
+                emit_note(&format!("ran on the vm, {why}"), format);
+            }
+            value
+        })
+    } else {
+        run_code_vm_with(&text, top, natives)
+    };
+    let mut sources = SourceMap::default();
+    sources.add(path, text);
+    match ret {
+        Ok(value) => print!("{value:?}"),
+        Err(e) => {
+            report(&e, &sources, format);
+            std::process::exit(1);
+        }
+    }
+}
 
-#[derive(Debug)]
-pub struct Diagnostic {
-    pub msg: String,
-    pub range: ((Line, Col), (Line, Col)),
+/// show an error of `run_code_vm` or `Session::elaborate`
+pub fn report(e: &RunError, sources: &dyn Sources, format: MessageFormat) {
+    let diags: Vec<_> = match e {
+        RunError::Parse(errors) => errors.iter().map(Diagnostic::from_parse_error).collect(),
+        RunError::Check(diags) => diags.clone(),
+        RunError::Runtime(e) => vec![Diagnostic::from_runtime_error(e)],
+        e => return emit_message(e.to_string().trim_start_matches("error: "), format),
+    };
+    emit(&diags, sources, format);
 }
 
-pub fn main_cli(path: &Path, top: Option<String>) {
-    let text = std::fs::read_to_string(path).expect("read file error");
-    print!("{:?}", run_code_vm(&text, top).unwrap())
+/// parse `code` and lower it to MIR
+pub fn compile_to_mir(code: &str) -> Result<Vec<mir::Class>, RunError> {
+    compile_to_mir_with(code, &Natives::default())
 }
 
-/*unsafe fn run_code<I, O>(code: &str, input: I) -> Result<O, String> {
-    let mut jit = jit::JIT::default();
-    let ast = typort_parser::simple_example::file().run(code).ok_or("parse error")?;
-    let hir = parse_to_hir(ast);
-    let mir = hir_to_mir(hir);
-    // Pass the string to the JIT, and it returns a raw pointer to machine code.
-    let code_ptr = jit.compile(mir)?;
-    // Cast the raw pointer to a typed function pointer. This is unsafe, because
-    // this is the critical point where you have to trust that the generated code
-    // is safe to be called.
-    let code_fn = mem::transmute::<_, fn(I) -> O>(code_ptr);
-    // And now we can call it!
-    Ok(code_fn(input))
-}*/
-
-fn run_code_vm(code: &str, top: Option<String>) -> Result<vm::Value, String> {
-    let (ast, parse_fail, _) = typort_parser::simple_example::file().run_with_out(code, Default::default());
-    if !parse_fail.is_empty() {
-        println!("parse fail at {:?}", parse_fail);
-    }
-    let ast = ast.ok_or("parse error")?;
-    let hir = parse_to_hir(ast);
+/// `compile_to_mir` where the calls to `natives` are checked
+pub fn compile_to_mir_with(code: &str, natives: &Natives) -> Result<Vec<mir::Class>, RunError> {
+    let (ast, errors) = typort_parser::parse_file(code, FileId(0));
+    let ast = match ast {
+        Some(ast) if errors.is_empty() => ast,
+        _ => return Err(RunError::Parse(errors)),
+    };
+    let (hir, mut diag) = hir::parse_to_hir(ast);
     //println!("hir: {:#?}", hir);
-    let mir = hir_to_mir(hir);
-    //println!("mir: {:#?}", mir);
-    let mut vm = vm::Interpreter::new(mir);
-    let main = if let Some(top) = top {
-        vm.classes.get(&top).unwrap().clone()
+    diag.extend(natives.check(&hir));
+    if diag.iter().any(Diagnostic::is_error) {
+        return Err(RunError::Check(diag));
+    }
+    Ok(hir_to_mir(hir))
+}
+
+/// the class named `top`, or the first object extending `App`
+pub fn find_top(classes: &[mir::Class], top: Option<String>) -> Result<&mir::Class, RunError> {
+    let main = if let Some(top) = &top {
+        classes.iter().find(|x| x.name.data == Symbol::intern(top))
     } else {
-        vm.classes.iter()
-            .find(|&x| x.1.extends.as_ref().map(|x| &x.data == "App").unwrap_or(false))
-            .map(|x| x.1)
-            .unwrap().clone()
+        classes.iter()
+            .find(|x| x.extends.as_ref().map(|x| &x.data == "App").unwrap_or(false))
     };
-    let ret = vm.translate_block(&main.block);
-    //let ret = vm.run();
-    Ok(ret)
+    main.ok_or(RunError::NoTop(top))
+}
+
+#[cfg(test)]
+fn run_code_vm(code: &str, top: Option<String>) -> Result<value::Value, RunError> {
+    run_code_vm_with(code, top, &Natives::default())
+}
+
+fn run_code_vm_with(code: &str, top: Option<String>, natives: &Natives) -> Result<value::Value, RunError> {
+    let mir = compile_to_mir_with(code, natives)?;
+    //println!("mir: {:#?}", mir);
+    let main = find_top(&mir, top)?.name.data;
+    let mut vm = machine::Machine::with_natives(bytecode::compile(&mir), natives);
+    vm.run(main).map_err(RunError::Runtime)
+}
+
+/// run `code` on the jit, code using what it does not support runs on the
+/// vm and the value comes with what it was
+fn run_code_jit(code: &str, top: Option<String>, natives: &Natives) -> Result<(value::Value, Option<String>), RunError> {
+    let mir = compile_to_mir_with(code, natives)?;
+    let main = find_top(&mir, top)?.name.data;
+    let mut jit = jit::JIT::with_natives(natives);
+    match jit.compile(&mir) {
+        Ok(()) => Ok((jit.run(main).map_err(RunError::Runtime)?, None)),
+        Err(jit::JitError::Unsupported(why)) => {
+            let mut vm = machine::Machine::with_natives(bytecode::compile(&mir), natives);
+            Ok((vm.run(main).map_err(RunError::Runtime)?, Some(why)))
+        }
+        Err(e) => Err(RunError::Jit(e.to_string())),
+    }
+}
+
+/// run `code` on the tree walking `vm::Interpreter`
+#[cfg(test)]
+fn run_code_tree(code: &str, top: Option<String>) -> Result<value::Value, RunError> {
+    let mir = compile_to_mir(code)?;
+    let main = find_top(&mir, top)?.clone();
+    let mut vm = vm::Interpreter::new(mir);
+    vm.run(&main).map_err(RunError::Runtime)
 }
 
 const FIB: &str = include_str!("../../examples/fib.typort");
@@ -90,17 +186,37 @@ const FOR: &str = include_str!("../../examples/for.typort");
 const WHILE: &str = include_str!("../../examples/while.typort");
 const STRING: &str = include_str!("../../examples/string.typort");
 const ARRAY: &str = include_str!("../../examples/array.typort");
+const MATCH: &str = include_str!("../../examples/match.typort");
+const ERROR: &str = include_str!("../../examples/error.typort");
+const CLOSURE: &str = include_str!("../../examples/closure.typort");
+const EARLY_EXIT: &str = include_str!("../../examples/early_exit.typort");
+const GC: &str = include_str!("../../examples/gc.typort");
+const CLASSES: &str = include_str!("../../examples/classes.typort");
+const TRAITS: &str = include_str!("../../examples/traits.typort");
+const STDLIB: &str = include_str!("../../examples/stdlib.typort");
 
-/*#[test]
+#[test]
 fn test_jit() {
-    let ret = unsafe { run_code::<i64, i64>(FIB, 11) };
-    println!("{ret:?}");
-    let ret = unsafe { run_code::<i64, i64>(WHILE, 11) };
-    println!("{ret:?}");
-    let ret = unsafe { run_code::<i64, i64>(FOR, 11) };
-    println!("{ret:?}");
-}*/
-
+    for code in [FIB, FOR, WHILE, EARLY_EXIT, ERROR] {
+        let mir = compile_to_mir(code).unwrap();
+        assert!(jit::JIT::default().compile(&mir).is_ok());
+    }
+    for code in [FIB, FOR, WHILE, EARLY_EXIT, ERROR] {
+        let jit = run_code_jit(code, None, &Natives::default()).map(|(value, fallback)| {
+            assert_eq!(fallback, None);
+            value
+        });
+        assert_eq!(format!("{jit:?}"), format!("{:?}", run_code_vm(code, None)));
+    }
+    // heap values, closures and fields run on the vm, which says why
+    for code in [STRING, ARRAY, MATCH, CLOSURE, CLASSES, TRAITS, GC, STDLIB] {
+        let mir = compile_to_mir(code).unwrap();
+        let ret = jit::JIT::default().compile(&mir);
+        assert!(matches!(ret, Err(jit::JitError::Unsupported(_))), "{ret:?}");
+        let Ok((_, Some(why))) = run_code_jit(code, None, &Natives::default()) else { panic!() };
+        assert!(why.ends_with("is not supported by the jit"), "{why}");
+    }
+}
 #[test]
 fn test_vm() {
     println!("##### fib #####\n");
@@ -118,3 +234,374 @@ fn test_vm() {
     println!("\n##### array #####\n");
     let _ = run_code_vm(ARRAY, None);
 }
+
+#[test]
+fn test_match() {
+    let ret = run_code_vm(MATCH, None);
+    assert!(matches!(ret, Ok(value::Value::Int(1302))), "{ret:?}");
+}
+
+#[test]
+fn test_closure() {
+    let ret = run_code_vm(CLOSURE, None);
+    assert!(matches!(ret, Ok(value::Value::Int(121302))), "{ret:?}");
+}
+
+#[test]
+fn test_for_range() {
+    // the bound is evaluated once
+    let code = "object main extends App {\n  var calls = 0\n  def bound() = {\n    calls = calls + 1\n    3\n  }\n  var sum = 0\n  for (i <- 0 until bound()) {\n    sum = sum + i\n  }\n  sum * 10 + calls\n}\n";
+    assert_eq!(format!("{:?}", run_code_vm(code, None)), "Ok(Int(31))");
+    assert_eq!(format!("{:?}", run_code_tree(code, None)), "Ok(Int(31))");
+}
+
+#[test]
+fn test_nested_defs() {
+    // each `helper` is seen only in the block defining it
+    let code = "object main extends App {
+  def outer(x: Int) = {
+    def helper(y: Int) = y + 1
+    helper(x)
+  }
+  def outer2(x: Int) = {
+    def helper(y: Int) = y * 10
+    helper(outer(x))
+  }
+  def isEven(n: Int): Boolean = {
+    def even(k: Int): Boolean = if (k == 0) { true } else { odd(k - 1) }
+    def odd(k: Int): Boolean = if (k == 0) { false } else { even(k - 1) }
+    even(n)
+  }
+  def sum(n: Int) = {
+    def go(k: Int): Int = if (k == 0) { 0 } else { k + go(k - 1) }
+    go(n)
+  }
+  if (isEven(6)) { outer2(4) * 100 + sum(4) } else { 0 }
+}
+";
+    let ret = run_code_vm(code, None);
+    assert!(matches!(ret, Ok(value::Value::Int(5010))), "{ret:?}");
+    let ret = run_code_tree(code, None);
+    assert!(matches!(ret, Ok(value::Value::Int(5010))), "{ret:?}");
+    let mir = compile_to_mir(code).unwrap();
+    let mut jit = jit::JIT::default();
+    jit.compile(&mir).unwrap();
+    let ret = jit.run(Symbol::intern("main"));
+    assert!(matches!(ret, Ok(value::Value::Int(5010))), "{ret:?}");
+}
+
+#[test]
+fn test_anonymous_class() {
+    let code = "class Counter(start: Int) {
+  def step = 1
+  def next = start + step
+}
+object main extends App {
+  val c = new Counter(10) { override def step = 5 }
+  val b = new Counter(1) { val extra = 7 }
+  c.next * 100 + b.next + b.extra
+}
+";
+    let ret = run_code_vm(code, None);
+    assert!(matches!(ret, Ok(value::Value::Int(1509))), "{ret:?}");
+    let ret = run_code_tree(code, None);
+    assert!(matches!(ret, Ok(value::Value::Int(1509))), "{ret:?}");
+}
+
+#[test]
+fn test_early_exit() {
+    let ret = run_code_vm(EARLY_EXIT, None);
+    assert!(matches!(ret, Ok(value::Value::Int(8250))), "{ret:?}");
+}
+
+#[test]
+fn test_classes() {
+    let ret = run_code_vm(CLASSES, None);
+    assert!(matches!(ret, Ok(value::Value::Int(12206))), "{ret:?}");
+    let parents = |c: Symbol| match c.as_str() {
+        "C" => vec!["A".into(), "B".into()],
+        "B" => vec!["A".into()],
+        _ => vec![],
+    };
+    let lin: Vec<_> = hir::linearize("C".into(), &parents).iter().map(|c| c.as_str()).collect();
+    assert_eq!(lin, vec!["C", "B", "A"]);
+}
+
+#[test]
+fn test_traits() {
+    let ret = run_code_vm(TRAITS, None);
+    assert!(matches!(ret, Ok(value::Value::Int(64))), "{ret:?}");
+    let code = "trait T {\n  def f: Int\n  def g = 1\n}\nclass A extends T {\n  def g = 2\n  override def h = 3\n}\n";
+    let (ast, _) = typort_parser::parse_file(code, FileId(0));
+    let diag: Vec<_> = hir::check_classes(&ast.unwrap()).into_iter().map(|d| (d.msg, d.range.0)).collect();
+    assert_eq!(diag, vec![
+        ("\"g\" overrides the method in T and needs `override`".to_owned(), (5, 6)),
+        ("\"h\" overrides nothing".to_owned(), (6, 15)),
+        ("\"A\" does not implement \"f\" of T".to_owned(), (4, 6)),
+    ]);
+}
+
+#[test]
+fn test_stdlib() {
+    let ret = run_code_vm(STDLIB, None);
+    assert!(matches!(ret, Ok(value::Value::Int(25528435))), "{ret:?}");
+    // a failure in a function called by a built-in is reported at the call
+    let code = "object main extends App {\n  require(2 > 1, s\"ok ${1}\")\n  Seq(1, 0).map(x => 10 / x)\n}\n";
+    for ret in [run_code_vm(code, None), run_code_tree(code, None)] {
+        let Err(RunError::Runtime(e)) = ret else { panic!("{ret:?}") };
+        assert_eq!((e.msg.as_str(), e.at.range.0.0), ("division by zero", 2));
+    }
+    let code = "object main extends App {\n  require(1 > 2, s\"width ${1 + 1}\")\n}\n";
+    let Err(RunError::Runtime(e)) = run_code_vm(code, None) else { panic!() };
+    assert_eq!(e.msg, "requirement failed: width 2");
+}
+
+#[test]
+fn test_natives() {
+    struct RegMap;
+    impl native::Extension for RegMap {
+        fn register(&self, natives: &mut Natives) {
+            natives.register("regOffset", |name: String| match name.as_str() {
+                "ctrl" => Ok(0),
+                "status" => Ok(4),
+                _ => Err(format!("no register {name:?}")),
+            });
+            natives.register_raw("twice", vec![ty::Type::Any, ty::Type::I64], ty::Type::Any, |host, args| {
+                let once = host.call(args[0], vec![args[1]])?;
+                host.call(args[0], vec![once])
+            });
+        }
+    }
+    let mut natives = Natives::default();
+    natives.load(&RegMap);
+    let code = "object main extends App {\n  val f = (x: Int) => x + 1\n  regOffset(\"status\") * 100 + twice(f, 3)\n}\n";
+    let ret = run_code_vm_with(code, None, &natives);
+    assert!(matches!(ret, Ok(value::Value::Int(405))), "{ret:?}");
+    let mir = compile_to_mir_with(code, &natives).unwrap();
+    let main = find_top(&mir, None).unwrap().clone();
+    let ret = vm::Interpreter::with_natives(mir, &natives).run(&main);
+    assert!(matches!(ret, Ok(value::Value::Int(405))), "{ret:?}");
+    // the jit leaves the calls to natives to the vm
+    let code = "object main extends App {\n  regOffset(\"status\") * 100\n}\n";
+    let ret = run_code_jit(code, None, &natives);
+    let Ok((value::Value::Int(400), Some(why))) = ret else { panic!("{ret:?}") };
+    assert_eq!(why, "native `regOffset` is not supported by the jit");
+
+    let code = "object main extends App {\n  regOffset(1) + regOffset(\"a\", 2)\n}\n";
+    let Err(RunError::Check(diag)) = compile_to_mir_with(code, &natives) else { panic!() };
+    let diag: Vec<_> = diag.into_iter().map(|d| (d.msg, d.range.0)).collect();
+    assert_eq!(diag, vec![
+        ("expected String, found Int".to_owned(), (1, 12)),
+        ("\"regOffset\" takes 1 arguments but 2 were given".to_owned(), (1, 17)),
+    ]);
+    let code = "object main extends App {\n  regOffset(\"data\")\n}\n";
+    let Err(RunError::Runtime(e)) = run_code_vm_with(code, None, &natives) else { panic!() };
+    assert_eq!(e.msg, "no register \"data\"");
+}
+
+#[test]
+fn test_session() {
+    let design = Session::new()
+        .add_text("bus.typort", "class Bus(w: Int) {\n  val width = w\n}\n")
+        .add_text("top.typort", "object Top {\n  val bus = new Bus(log2Up(16))\n  val depth = 8\n  print(s\"width ${bus.width}\")\n  bus.width * depth\n}\n")
+        .elaborate("Top")
+        .unwrap();
+    assert!(matches!(design.value, value::Value::Int(32)));
+    assert!(matches!(design.field("depth"), Some(value::Value::Int(8))));
+    let fields: Vec<_> = design.fields().into_iter().map(|(name, v)| (name.as_str().to_owned(), design.display(v))).collect();
+    assert_eq!(fields, vec![("bus".to_owned(), "Bus(w = 4, width = 4)".to_owned()), ("depth".to_owned(), "8".to_owned())]);
+    assert_eq!(design.output, "width 4\n");
+    for code in [FIB, FOR, WHILE, STRING, ARRAY, MATCH, CLOSURE, EARLY_EXIT, CLASSES, TRAITS, STDLIB] {
+        assert!(Session::new().add_text("main.typort", code).elaborate("main").is_ok());
+    }
+
+    let mut session = Session::new();
+    session.add_text("a.typort", "object A {}\n").add_text("b.typort", "object B {\n  x + 1\n}\n");
+    let Err(RunError::Check(diag)) = session.elaborate("B") else { panic!() };
+    assert_eq!((diag[0].file, diag[0].range.0), (FileId(1), (1, 2)));
+    assert_eq!(session.path(diag[0].file), Some(Path::new("b.typort")));
+    let ret = Session::new().add_source("no/such/file.typort").elaborate("Top");
+    assert!(matches!(ret, Err(RunError::Io(_, std::io::ErrorKind::NotFound))), "{ret:?}");
+}
+
+#[test]
+fn test_runtime_error() {
+    let Err(RunError::Runtime(e)) = run_code_vm(ERROR, None) else { panic!() };
+    assert_eq!(e.msg, "division by zero");
+    let trace: Vec<_> = e.trace.iter().map(|f| (f.func.as_str(), f.at.range.0.0 + 1)).collect();
+    assert_eq!(trace, vec![("div", 2), ("half", 4), ("main", 6)]);
+    assert_eq!(
+        e.to_string(),
+        "error: division by zero\n    at div (line 2)\n    at half (line 4)\n    at main (line 6)",
+    );
+    // the body of a parent fails before the constructor of the class starts
+    let code = "class A {\n  val x = 1 / 0\n}\nclass B extends A {}\nobject main extends App {\n  new B\n}\n";
+    let Err(RunError::Runtime(e)) = run_code_vm(code, None) else { panic!() };
+    let trace: Vec<_> = e.trace.iter().map(|f| (f.func.as_str(), f.at.range.0.0 + 1)).collect();
+    assert_eq!((e.msg.as_str(), trace), ("division by zero", vec![("A", 2), ("main", 6)]));
+}
+
+#[test]
+fn test_machine_matches_tree() {
+    for code in [FIB, FOR, WHILE, STRING, MATCH, CLOSURE, EARLY_EXIT, ERROR, CLASSES, TRAITS, STDLIB] {
+        let machine = format!("{:?}", run_code_vm(code, None));
+        let tree = format!("{:?}", run_code_tree(code, None));
+        assert_eq!(machine, tree);
+    }
+}
+
+#[test]
+fn test_gc() {
+    let mir = compile_to_mir(GC).unwrap();
+    let main = find_top(&mir, None).unwrap().name.data;
+    let mut vm = machine::Machine::new(bytecode::compile(&mir));
+    let Ok(value::Value::HeapId(id)) = vm.run(main) else { panic!() };
+    // the string keeps its id while the arrays around it are allocated and freed
+    assert!(matches!(vm.heap.get(id), Some(value::HeapValue::String(s)) if s == "typort"));
+    assert!(vm.heap.len() < 4096, "{}", vm.heap.len());
+}
+
+#[test]
+fn test_gc_closures() {
+    let code = "object main extends App {
+  var total = 0
+  for (i <- 0 until 10000) {
+    val k = i
+    val f = () => k + 1
+    total = total + f()
+  }
+  total
+}
+";
+    let mir = compile_to_mir(code).unwrap();
+    let main = find_top(&mir, None).unwrap().name.data;
+    let mut vm = machine::Machine::new(bytecode::compile(&mir));
+    let ret = vm.run(main);
+    assert!(matches!(ret, Ok(value::Value::Int(50005000))), "{ret:?}");
+    // a closure and the upvalue it captures are freed with the heap
+    assert!(vm.live_closures() < 4096, "{}", vm.live_closures());
+}
+
+#[test]
+fn test_repl() {
+    let mut repl = Repl::new();
+    let mut eval = |input: &str| repl.eval(input).map(|lines| lines.join("\n"));
+    assert_eq!(eval("val x = 3").unwrap(), "val x: Int = 3");
+    assert_eq!(eval("def f(a: Int): Int = {\n  a * x\n}").unwrap(), "def f");
+    assert_eq!(eval("f(2)").unwrap(), "res0: Int = 6");
+    assert_eq!(eval("res0 + 1").unwrap(), "res1: Int = 7");
+    assert_eq!(eval("class Bus(w: Int) {\n  val width = w\n}\nval b = new Bus(x)").unwrap(), "class Bus\nval b: Bus = Bus(w = 3, width = 3)");
+    assert_eq!(eval(":type b.width + 1").unwrap(), "Int");
+    assert_eq!(eval(":type Seq(1, 2).map(a => a * x)").unwrap(), "Seq");
+    assert!(eval("y + 1").unwrap_err().starts_with("check error"));
+    assert!(eval("f(1, 2)").is_err());
+    assert_eq!(eval("f(1)").unwrap(), "res2: Int = 3");
+    assert_eq!(eval("val x = s\"x$x\"").unwrap(), "val x: String = x3");
+    assert!(Repl::is_incomplete("def g(a: Int) = {\n  a + \"}\"\n"));
+    assert!(!Repl::is_incomplete("def g(a: Int) = {\n  a\n}\n"));
+}
+
+#[test]
+fn test_diagnostics() {
+    let code = "object Top {\n  val width = 8\n  width = 4\n  widht + 1\n}\n";
+    let mut sources = SourceMap::default();
+    let file = sources.add("top.typort", code);
+    let Err(RunError::Check(diag)) = compile_to_mir(code) else { panic!() };
+    assert_eq!(diag.len(), 2);
+    assert_eq!(diag[0].render(&sources), [
+        "error[E0104]: \"width\" is immutable",
+        " --> top.typort:3:3",
+        "  |",
+        "3 |   width = 4",
+        "  |   ^^^^^ assigned here",
+        "2 |   val width = 8",
+        "  |       ----- defined with `val` here",
+        "  = note: declare it with `var` to assign to it",
+    ].join("\n"));
+    let suggestion = &diag[1].suggestions[0];
+    assert_eq!((suggestion.file, suggestion.range, suggestion.replacement.as_str()), (file, ((3, 2), (3, 7)), "width"));
+    let json = diag[1].to_json(&sources);
+    assert!(json.starts_with("{\"severity\":\"error\",\"code\":\"E0110\",\"message\":\"use of undeclared value widht\""), "{json}");
+    assert!(json.contains("\"line_start\":4,\"column_start\":3"), "{json}");
+
+    let Err(RunError::Runtime(e)) = run_code_vm(ERROR, None) else { panic!() };
+    let diag = Diagnostic::from_runtime_error(&e);
+    assert_eq!(diag.labels.iter().map(|l| l.range.0.0 + 1).collect::<Vec<_>>(), vec![4, 6]);
+}
+
+#[test]
+fn test_call_args() {
+    let code = "class Fifo(depth: Int, name: String) {\n  val size = depth\n}\nobject Top extends App {\n  def sub(a: Int, b: Int) = a - b\n  val x = sub(b = 1, a = 3)\n  val f = new Fifo(4, 5)\n  sub(1)\n  sub(1, c = 2)\n  x\n}\n";
+    let Err(RunError::Check(diag)) = compile_to_mir(code) else { panic!() };
+    let found: Vec<_> = diag.iter().map(|d| (d.code.unwrap(), d.msg.as_str(), d.range.0.0 + 1)).collect();
+    assert_eq!(found, vec![
+        ("E0116", "expected String, found Int", 7),
+        ("E0114", "\"sub\" takes 2 arguments but 1 were given", 8),
+        ("E0115", "\"sub\" has no parameter named \"c\"", 9),
+        ("E0114", "\"sub\" is missing an argument for \"b\"", 9),
+    ]);
+    assert_eq!(diag[1].notes, vec!["the signature is def sub(a: Int, b: Int)"]);
+    // named arguments are given in the order of the parameters
+    let code = "object Top extends App {\n  def sub(a: Int, b: Int) = a - b\n  sub(b = 1, a = 3)\n}\n";
+    assert_eq!(format!("{:?}", run_code_vm(code, None).unwrap()), "Int(2)");
+    // of a method and of `apply` too, when the class is known
+    let code = "class Sub {\n  def sub(a: Int, b: Int) = a - b\n  def apply(a: Int, b: String) = a\n}\nobject Top extends App {\n  val s = new Sub\n  s.sub(b = 1, a = 3)\n}\n";
+    assert_eq!(format!("{:?}", run_code_vm(code, None).unwrap()), "Int(2)");
+    assert!(compile_to_mir(&code.replace("s.sub(b = 1, a = 3)", "s(b = \"x\", a = 3)")).is_ok());
+    let code = "class Sub {\n  def sub(a: Int, b: Int) = a - b\n}\nobject Top extends App {\n  val f = (a: Int) => a\n  new Sub().sub(a = 1, c = 2)\n  f(a = 1)\n}\n";
+    let Err(RunError::Check(diag)) = compile_to_mir(code) else { panic!() };
+    let found: Vec<_> = diag.iter().map(|d| (d.code.unwrap(), d.msg.as_str(), d.range.0.0 + 1)).collect();
+    assert_eq!(found, vec![
+        ("E0115", "\"sub\" has no parameter named \"c\"", 6),
+        ("E0114", "\"sub\" is missing an argument for \"b\"", 6),
+        ("E0115", "the parameters of \"f\" are not known, \"a\" cannot be given by name", 7),
+    ]);
+}
+
+#[test]
+fn test_index() {
+    use index::{doc_comment, Direction, SymbolKind};
+    let code = "class Adder extends Component {\n  val io = new Bundle {\n    val a = in(UInt(8 bits))\n    val b = in(UInt(8 bits))\n  }\n  // the sum of the inputs\n  val s = io.a +^ io.b\n  val r = Reg(UInt(log2Up(s.getWidth + 8) bits))\n  r := s.resize(4)\n}\n";
+    let (ast, errors) = typort_parser::parse_file(code, FileId(0));
+    assert!(errors.is_empty(), "{errors:?}");
+    let (_, _, index) = hir::parse_to_hir_indexed(ast.unwrap());
+    let s = index.at(FileId(0), 6, 7).unwrap();
+    assert_eq!(index.signature(s), "val s: UInt(9 bits)");
+    assert_eq!(doc_comment(code, index.defs[s].at.range.0.0).as_deref(), Some("the sum of the inputs"));
+    // `io.a` is the field of the anonymous bundle
+    let a = index.at(FileId(0), 6, 13).unwrap();
+    assert_eq!((index.defs[a].kind, index.signature(a).as_str()), (SymbolKind::Field, "val a: UInt(8 bits)"));
+    assert_eq!(index.defs[a].signal.as_ref().unwrap().direction, Some(Direction::In));
+    assert_eq!(index.references(a).count(), 1);
+    let r = index.at(FileId(0), 8, 2).unwrap();
+    let signal = index.defs[r].signal.clone().unwrap();
+    assert_eq!((signal.register, signal.width, signal.clock_domain.as_deref()), (true, None, Some("clkDomain")));
+    assert_eq!(index.references(s).count(), 2);
+
+    // the uses in another file, of an imported class, a member and a parameter
+    let lib = "class Bus(w: Int) {\n  val width = w\n}\n";
+    let top = "import lib.Bus\nobject Top {\n  def twice(x: Int) = x + x\n  val t = twice(new Bus(4).width)\n}\n";
+    let mut items = typort_parser::parse_file(lib, FileId(0)).0.unwrap();
+    items.extend(typort_parser::parse_file(top, FileId(1)).0.unwrap());
+    let (_, diag, index) = hir::parse_to_hir_indexed(items);
+    assert!(diag.is_empty(), "{diag:?}");
+    let refs = |def| index.references(def).map(|at| (at.file.0, at.range.0)).collect::<Vec<_>>();
+    assert_eq!(refs(index.classes[&Symbol::intern("Bus")]), vec![(1, (0, 11)), (1, (3, 20))]);
+    assert_eq!(refs(index.at(FileId(0), 1, 6).unwrap()), vec![(1, (3, 27))]);
+    let x = index.at(FileId(1), 2, 12).unwrap();
+    assert_eq!((index.defs[x].kind, refs(x)), (SymbolKind::Param, vec![(1, (2, 22)), (1, (2, 26))]));
+    let calls: Vec<_> = index.calls.iter().map(|c| (index.defs[c.callee].name.as_str(), c.args.len())).collect();
+    assert_eq!(calls, vec![("Bus", 1), ("twice", 1)]);
+}
+
+#[test]
+fn test_netlist() {
+    let code = "class Acc extends Component {\n  val io = new Bundle {\n    val a = in(UInt(8 bits))\n    val sum = out(UInt(9 bits))\n  }\n  val r = RegInit(U(0, 9 bits))\n  val s = io.a +^ r\n  r := s.resize(9)\n  io.sum := r\n}\n";
+    let (ast, errors) = typort_parser::parse_file(code, FileId(0));
+    assert!(errors.is_empty(), "{errors:?}");
+    let (classes, _, index) = hir::parse_to_hir_indexed(ast.unwrap());
+    let netlist = netlist::Netlist::new(&classes[0], &index);
+    assert_eq!(netlist.to_verilog(), "module Acc (\n  input clk,\n  input reset,\n  input [7:0] io_a,\n  output [8:0] io_sum\n);\n  reg [8:0] r;\n  wire [9:0] s;\n  assign s = (io_a + r);\n  always @(posedge clk) begin\n    if (reset) r <= 0;\n    else r <= s;\n  end\n  assign io_sum = r;\nendmodule\n");
+    let edges: Vec<_> = netlist.connections.iter().map(|c| (c.target.as_str(), c.sources.join(" "))).collect();
+    assert_eq!(edges, vec![("s", "io_a r".to_owned()), ("r", "s".to_owned()), ("io_sum", "r".to_owned())]);
+}
@@ -0,0 +1,657 @@
+use std::rc::Rc;
+
+use crate::bytecode::{Op, Program};
+use crate::built_in::{built_in, built_in_method, BuiltIn, Host};
+use crate::mir::Capture;
+use crate::native::{Native, Natives};
+use crate::value::{Frame, Heap, HeapValue, Instance, RuntimeError, Upvalue, Value, VmResult};
+use crate::{Span, Symbol};
+
+/// a proto together with the upvalues it captured
+#[derive(Clone, Debug)]
+struct Closure {
+    proto: u32,
+    /// indexes into `Machine::upvalues`
+    upvalues: Vec<usize>,
+}
+
+/// values referred to by their index, the index of a freed one is given to a
+/// later one
+#[derive(Debug)]
+struct Slots<T> {
+    items: Vec<Option<T>>,
+    free: Vec<usize>,
+}
+
+impl<T> Default for Slots<T> {
+    fn default() -> Self {
+        Self { items: vec![], free: vec![] }
+    }
+}
+
+impl<T> Slots<T> {
+    fn push(&mut self, item: T) -> usize {
+        match self.free.pop() {
+            Some(id) => {
+                self.items[id] = Some(item);
+                id
+            }
+            None => {
+                self.items.push(Some(item));
+                self.items.len() - 1
+            }
+        }
+    }
+    /// the number of live items
+    fn len(&self) -> usize {
+        self.items.len() - self.free.len()
+    }
+    /// free the items not marked `live`
+    fn sweep(&mut self, live: &[bool]) {
+        for (id, item) in self.items.iter_mut().enumerate() {
+            if !live[id] && item.take().is_some() {
+                self.free.push(id);
+            }
+        }
+    }
+}
+
+impl<T> std::ops::Index<usize> for Slots<T> {
+    type Output = T;
+    fn index(&self, id: usize) -> &T {
+        self.items[id].as_ref().expect("a freed slot")
+    }
+}
+
+impl<T> std::ops::IndexMut<usize> for Slots<T> {
+    fn index_mut(&mut self, id: usize) -> &mut T {
+        self.items[id].as_mut().expect("a freed slot")
+    }
+}
+
+/// `gc_point` collects once this many closures are live
+const FIRST_CLOSURE_GC: usize = 1024;
+
+/// what a global name refers to, calls try them in this order
+#[derive(Clone, Debug, Default)]
+struct Global {
+    built_in: Option<BuiltIn>,
+    native: Option<Native>,
+}
+
+/// where the caller gets the result of a frame
+#[derive(Clone, Copy, Debug)]
+enum Ret {
+    /// this register of the caller
+    To(usize),
+    /// a constructor gives `this` to this register of the caller
+    This(usize),
+    /// the body of an inherited class, its value is dropped
+    Drop,
+}
+
+#[derive(Clone, Debug)]
+struct CallFrame {
+    proto: u32,
+    /// the next op, it is only written back when the frame is left
+    pc: usize,
+    base: usize,
+    ret: Ret,
+    closure: Option<usize>,
+}
+
+/// runs the bytecode of a `Program`, all frames share one register file
+pub struct Machine {
+    program: Rc<Program>,
+    regs: Vec<Value>,
+    frames: Vec<CallFrame>,
+    pub heap: Heap,
+    /// what `print` wrote, when it is captured instead of going to stdout
+    pub output: Option<String>,
+    closures: Slots<Closure>,
+    upvalues: Slots<Upvalue>,
+    /// upvalues still pointing to a register
+    open_upvalues: Vec<usize>,
+    /// like `Heap::should_collect`, for the closures
+    next_closure_gc: usize,
+    globals: Vec<Global>,
+    /// the frames below belong to a run further out, a built-in calling a
+    /// function starts one, `execute` returns when its first frame does
+    stop: usize,
+}
+
+impl Machine {
+    pub fn new(program: Program) -> Self {
+        Self::with_natives(program, &Natives::default())
+    }
+    pub fn with_natives(program: Program, natives: &Natives) -> Self {
+        let globals = program.globals.iter()
+            .map(|name| Global {
+                built_in: built_in(name.as_str()),
+                native: natives.get(*name).cloned(),
+            })
+            .collect();
+        Self {
+            program: Rc::new(program),
+            regs: vec![],
+            frames: vec![],
+            heap: Default::default(),
+            output: None,
+            closures: Default::default(),
+            upvalues: Default::default(),
+            open_upvalues: vec![],
+            next_closure_gc: FIRST_CLOSURE_GC,
+            globals,
+            stop: 0,
+        }
+    }
+    /// create an instance of a class, the value of its body is returned, it
+    /// is the outermost frame of a trace
+    pub fn run(&mut self, class: Symbol) -> VmResult {
+        Ok(self.instantiate(class)?.1)
+    }
+    /// like `run`, the instance is returned too
+    pub fn instantiate(&mut self, class: Symbol) -> VmResult<(Value, Value)> {
+        let Some(code) = self.program.classes.get(&class) else {
+            return Err(RuntimeError {
+                msg: format!("unknown class `{class}`"),
+                at: Span::default(),
+                trace: vec![],
+            });
+        };
+        let params = self.program.protos[code.ctor as usize].params - 1;
+        if params != 0 {
+            return Err(RuntimeError {
+                msg: format!("`{class}` takes {params} arguments but 0 were given"),
+                at: Span::default(),
+                trace: vec![],
+            });
+        }
+        self.frames.clear();
+        self.stop = 0;
+        self.construct(class, 0, Ret::Drop);
+        let value = self.execute()?;
+        // `this` is never assigned, so the first register still holds it
+        Ok((self.regs[0], value))
+    }
+    /// push the frames running the constructors of `class` on a new instance,
+    /// the arguments are already in the registers after `base`
+    fn construct(&mut self, class: Symbol, base: usize, ret: Ret) {
+        let program = self.program.clone();
+        let code = &program.classes[&class];
+        let this = Value::HeapId(self.heap.alloc(HeapValue::Class(Instance {
+            class,
+            fields: Default::default(),
+        })));
+        self.push_frame(code.ctor, None, base, ret);
+        self.regs[base] = this;
+        // the frame on top runs first
+        for proto in code.inherited.iter().rev() {
+            let last = self.frames.last().unwrap();
+            let base = last.base + program.protos[last.proto as usize].max_regs as usize;
+            self.push_frame(*proto, None, base, Ret::Drop);
+            // the arguments of an inherited class are not given yet
+            let params = program.protos[*proto as usize].params as usize;
+            self.regs[base..base + params].fill(Value::Unit);
+            self.regs[base] = this;
+        }
+    }
+    /// the method `name` of `value` if it is an instance
+    fn method(&self, value: Value, name: Symbol) -> Option<u32> {
+        match value {
+            Value::HeapId(id) => match self.heap.get(id) {
+                Some(HeapValue::Class(instance)) => {
+                    self.program.classes[&instance.class].methods.get(&name).copied()
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    fn push_frame(&mut self, proto: u32, closure: Option<usize>, base: usize, ret: Ret) {
+        let need = base + self.program.protos[proto as usize].max_regs as usize;
+        if self.regs.len() < need {
+            self.regs.resize(need, Value::Unit);
+        }
+        self.frames.push(CallFrame { proto, pc: 0, base, ret, closure });
+    }
+    /// close the upvalues pointing to `from` and above
+    fn close(&mut self, from: usize) {
+        if self.open_upvalues.is_empty() {
+            return;
+        }
+        let (upvalues, regs) = (&mut self.upvalues, &self.regs);
+        self.open_upvalues.retain(|&id| match upvalues[id] {
+            Upvalue::Open(idx) if idx >= from => {
+                upvalues[id] = Upvalue::Closed(regs[idx]);
+                false
+            }
+            _ => true,
+        });
+    }
+    fn make_closure(&mut self, proto: u32, base: usize, closure: Option<usize>) -> usize {
+        let program = self.program.clone();
+        let upvalues = program.protos[proto as usize].captures.iter()
+            .map(|c| match c {
+                Capture::Local(reg) => {
+                    let idx = base + reg;
+                    let open = self.open_upvalues.iter()
+                        .find(|&&id| matches!(self.upvalues[id], Upvalue::Open(x) if x == idx));
+                    if let Some(id) = open {
+                        return *id;
+                    }
+                    let id = self.upvalues.push(Upvalue::Open(idx));
+                    self.open_upvalues.push(id);
+                    id
+                }
+                Capture::Upvalue(idx) => self.closures[closure.expect("upvalue outside of a closure")].upvalues[*idx],
+            })
+            .collect();
+        self.closures.push(Closure { proto, upvalues })
+    }
+    /// the number of closures not freed yet
+    pub fn live_closures(&self) -> usize {
+        self.closures.len()
+    }
+    /// collect the heap, the closures and the upvalues if they grew enough.
+    /// the registers up to `top`, the closures of the frames and the open
+    /// upvalues are the roots, the value of an open upvalue is below `top`.
+    /// nothing is collected while a built-in runs a function, it holds values too
+    fn gc_point(&mut self, top: usize) {
+        if self.stop > 0 || !(self.heap.should_collect() || self.closures.len() >= self.next_closure_gc) {
+            return;
+        }
+        let mut live_closures = vec![false; self.closures.items.len()];
+        let mut live_upvalues = vec![false; self.upvalues.items.len()];
+        for id in &self.open_upvalues {
+            live_upvalues[*id] = true;
+        }
+        let frames = self.frames.iter().filter_map(|f| f.closure).map(Value::Closure);
+        let roots: Vec<_> = self.regs[..top].iter().copied().chain(frames).collect();
+        let (closures, upvalues) = (&self.closures, &self.upvalues);
+        self.heap.collect(roots, |value, work| {
+            let Value::Closure(id) = value else { return };
+            if std::mem::replace(&mut live_closures[id], true) {
+                return;
+            }
+            for up in &closures[id].upvalues {
+                if !std::mem::replace(&mut live_upvalues[*up], true) {
+                    if let Upvalue::Closed(value) = upvalues[*up] {
+                        work.push(value);
+                    }
+                }
+            }
+        });
+        self.closures.sweep(&live_closures);
+        self.upvalues.sweep(&live_upvalues);
+        self.next_closure_gc = FIRST_CLOSURE_GC.max(self.closures.len() * 2);
+    }
+    /// the trace is built from the op each frame is at, the frames of this run
+    /// are dropped
+    fn error(&mut self, msg: impl Into<String>) -> RuntimeError {
+        let mut trace = vec![];
+        // the constructors below an inherited body which failed have not started
+        for frame in self.frames.iter().rev().filter(|f| f.pc > 0) {
+            let proto = &self.program.protos[frame.proto as usize];
+            trace.push(Frame { func: proto.name, at: proto.spans[frame.pc - 1].clone() });
+        }
+        if let Some(frame) = self.frames.get(self.stop) {
+            self.close(frame.base);
+        }
+        self.frames.truncate(self.stop);
+        let at = trace.first().map(|f| f.at.clone()).unwrap_or_default();
+        RuntimeError { msg: msg.into(), at, trace }
+    }
+    fn value_eq(&self, l: Value, r: Value) -> Result<bool, String> {
+        match (l, r) {
+            (Value::Int(l), Value::Int(r)) => Ok(l == r),
+            (Value::Bool(l), Value::Bool(r)) => Ok(l == r),
+            (Value::Unit, Value::Unit) => Ok(true),
+            (Value::Closure(l), Value::Closure(r)) => Ok(l == r),
+            (Value::HeapId(l), Value::HeapId(r)) => match (self.heap.get(l), self.heap.get(r)) {
+                (Some(HeapValue::String(l)), Some(HeapValue::String(r))) => Ok(l == r),
+                _ => Ok(l == r),
+            },
+            _ => Err(format!("can not compare {l:?} with {r:?}")),
+        }
+    }
+    fn execute(&mut self) -> VmResult {
+        let program = self.program.clone();
+        'frames: loop {
+            let frame = self.frames.last().expect("no frame to run");
+            let proto = &program.protos[frame.proto as usize];
+            let code = &proto.code[..];
+            let (base, closure, mut pc) = (frame.base, frame.closure, frame.pc);
+            let top = base + proto.max_regs as usize;
+            // leave the frame with an error at the current op
+            macro_rules! fail {
+                ($msg:expr) => {{
+                    let msg = $msg;
+                    self.frames.last_mut().unwrap().pc = pc;
+                    return Err(self.error(msg));
+                }};
+            }
+            macro_rules! reg {
+                ($r:expr) => {
+                    self.regs[base + $r as usize]
+                };
+            }
+            // run a built-in on the registers `start..start + len`
+            macro_rules! native {
+                ($f:expr, $d:expr, $start:expr, $len:expr) => {{
+                    let start = $start;
+                    let args = self.regs[start..start + $len as usize].to_vec();
+                    self.frames.last_mut().unwrap().pc = pc;
+                    match ($f)(self, args) {
+                        Ok(v) => reg!($d) = v,
+                        Err(msg) => fail!(msg),
+                    }
+                }};
+            }
+            loop {
+                let op = code[pc];
+                pc += 1;
+                match op {
+                    Op::Int(d, x) => reg!(d) = Value::Int(x),
+                    Op::Bool(d, x) => reg!(d) = Value::Bool(x),
+                    Op::Unit(d) => reg!(d) = Value::Unit,
+                    Op::String(d, c) => {
+                        self.gc_point(top);
+                        let id = self.heap.alloc(HeapValue::String(program.strings[c as usize].clone()));
+                        reg!(d) = Value::HeapId(id);
+                    }
+                    Op::Move(d, s) => reg!(d) = reg!(s),
+                    Op::GetUpvalue(d, idx) => {
+                        let id = self.closures[closure.unwrap()].upvalues[idx as usize];
+                        reg!(d) = match self.upvalues[id] {
+                            Upvalue::Open(idx) => self.regs[idx],
+                            Upvalue::Closed(value) => value,
+                        };
+                    }
+                    Op::SetUpvalue(idx, s) => {
+                        let value = reg!(s);
+                        let id = self.closures[closure.unwrap()].upvalues[idx as usize];
+                        match &mut self.upvalues[id] {
+                            Upvalue::Open(idx) => self.regs[*idx] = value,
+                            Upvalue::Closed(v) => *v = value,
+                        }
+                    }
+                    Op::Add(d, a, b) => match int_op(reg!(a), reg!(b), i64::checked_add) {
+                        Ok(v) => reg!(d) = v,
+                        Err(msg) => fail!(msg),
+                    },
+                    Op::Sub(d, a, b) => match int_op(reg!(a), reg!(b), i64::checked_sub) {
+                        Ok(v) => reg!(d) = v,
+                        Err(msg) => fail!(msg),
+                    },
+                    Op::Mul(d, a, b) => match int_op(reg!(a), reg!(b), i64::checked_mul) {
+                        Ok(v) => reg!(d) = v,
+                        Err(msg) => fail!(msg),
+                    },
+                    Op::Div(d, a, b) => match int_op(reg!(a), reg!(b), i64::checked_div) {
+                        Ok(v) => reg!(d) = v,
+                        Err(msg) => fail!(msg),
+                    },
+                    Op::Mod(d, a, b) => match int_op(reg!(a), reg!(b), i64::checked_rem) {
+                        Ok(v) => reg!(d) = v,
+                        Err(msg) => fail!(msg),
+                    },
+                    Op::Eq(d, a, b) | Op::Neq(d, a, b) => match self.value_eq(reg!(a), reg!(b)) {
+                        Ok(eq) => reg!(d) = Value::Bool(eq == matches!(op, Op::Eq(..))),
+                        Err(msg) => fail!(msg),
+                    },
+                    Op::Lt(d, a, b) => match int_cmp(reg!(a), reg!(b), |a, b| a < b) {
+                        Ok(v) => reg!(d) = v,
+                        Err(msg) => fail!(msg),
+                    },
+                    Op::Le(d, a, b) => match int_cmp(reg!(a), reg!(b), |a, b| a <= b) {
+                        Ok(v) => reg!(d) = v,
+                        Err(msg) => fail!(msg),
+                    },
+                    Op::Gt(d, a, b) => match int_cmp(reg!(a), reg!(b), |a, b| a > b) {
+                        Ok(v) => reg!(d) = v,
+                        Err(msg) => fail!(msg),
+                    },
+                    Op::Ge(d, a, b) => match int_cmp(reg!(a), reg!(b), |a, b| a >= b) {
+                        Ok(v) => reg!(d) = v,
+                        Err(msg) => fail!(msg),
+                    },
+                    Op::Not(d, s) => match reg!(s) {
+                        Value::Bool(x) => reg!(d) = Value::Bool(!x),
+                        _ => fail!("expect Bool"),
+                    },
+                    Op::Neg(d, s) => match reg!(s) {
+                        Value::Int(x) => match x.checked_neg() {
+                            Some(x) => reg!(d) = Value::Int(x),
+                            None => fail!("integer overflow"),
+                        },
+                        _ => fail!("expect Int"),
+                    },
+                    Op::Jump(t) => pc = t as usize,
+                    Op::JumpIfFalse(c, t) => match reg!(c) {
+                        Value::Bool(true) => {}
+                        Value::Bool(false) => pc = t as usize,
+                        _ => fail!("expect Bool"),
+                    },
+                    Op::JumpIfTrue(c, t) => match reg!(c) {
+                        Value::Bool(true) => pc = t as usize,
+                        Value::Bool(false) => {}
+                        _ => fail!("expect Bool"),
+                    },
+                    Op::CallGlobal(d, g, first, argc) => {
+                        let global = &self.globals[g as usize];
+                        if let Some(f) = global.built_in {
+                            self.gc_point(top);
+                            native!(f, d, base + first as usize, argc);
+                        } else if let Some(native) = global.native.clone() {
+                            native!(|host, args| native.call(host, args), d, base + first as usize, argc);
+                        } else {
+                            fail!(format!("unknown function `{}`", program.globals[g as usize]))
+                        }
+                    }
+                    Op::CallValue(d, f, first, argc) => {
+                        let id = match reg!(f) {
+                            Value::Closure(id) => id,
+                            // indexing a Seq or a Map, the receiver goes
+                            // before the arguments
+                            obj => {
+                                let f = built_in_method("apply").unwrap();
+                                let mut args = vec![obj];
+                                let start = base + first as usize;
+                                args.extend_from_slice(&self.regs[start..start + argc as usize]);
+                                self.frames.last_mut().unwrap().pc = pc;
+                                match f(self, args) {
+                                    Ok(v) => reg!(d) = v,
+                                    Err(msg) => fail!(msg),
+                                }
+                                continue;
+                            }
+                        };
+                        let proto = &program.protos[self.closures[id].proto as usize];
+                        if proto.params != argc {
+                            fail!(format!("`{}` takes {} arguments but {argc} were given", proto.name, proto.params))
+                        }
+                        self.frames.last_mut().unwrap().pc = pc;
+                        self.push_frame(self.closures[id].proto, Some(id), base + first as usize, Ret::To(base + d as usize));
+                        continue 'frames;
+                    }
+                    Op::CallMethod(d, name, first, argc) => {
+                        let this = reg!(first);
+                        let Some(proto) = self.method(this, name) else {
+                            match built_in_method(name.as_str()) {
+                                Some(f) => native!(f, d, base + first as usize, argc + 1),
+                                None => fail!(format!("{this:?} has no member `{name}`")),
+                            }
+                            continue;
+                        };
+                        let params = program.protos[proto as usize].params - 1;
+                        if params != argc {
+                            fail!(format!("`{name}` takes {params} arguments but {argc} were given"))
+                        }
+                        self.frames.last_mut().unwrap().pc = pc;
+                        self.push_frame(proto, None, base + first as usize, Ret::To(base + d as usize));
+                        continue 'frames;
+                    }
+                    Op::New(d, class, this, argc) => {
+                        let params = program.protos[program.classes[&class].ctor as usize].params - 1;
+                        if params != argc {
+                            fail!(format!("`{class}` takes {params} arguments but {argc} were given"))
+                        }
+                        self.gc_point(top);
+                        self.frames.last_mut().unwrap().pc = pc;
+                        self.construct(class, base + this as usize, Ret::This(base + d as usize));
+                        continue 'frames;
+                    }
+                    Op::GetField(d, s, name) => {
+                        let obj = reg!(s);
+                        let field = match obj {
+                            Value::HeapId(id) => match self.heap.get(id) {
+                                Some(HeapValue::Class(instance)) => instance.fields.get(&name).copied(),
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        if let Some(value) = field {
+                            reg!(d) = value;
+                            continue;
+                        }
+                        // a method without arguments is read like a field, it
+                        // runs above the registers of this frame
+                        let Some(proto) = self.method(obj, name) else {
+                            match built_in_method(name.as_str()) {
+                                Some(f) => native!(f, d, base + s as usize, 1),
+                                None => fail!(format!("{obj:?} has no member `{name}`")),
+                            }
+                            continue;
+                        };
+                        let params = program.protos[proto as usize].params - 1;
+                        if params != 0 {
+                            fail!(format!("`{name}` takes {params} arguments but 0 were given"))
+                        }
+                        self.frames.last_mut().unwrap().pc = pc;
+                        self.push_frame(proto, None, top, Ret::To(base + d as usize));
+                        self.regs[top] = obj;
+                        continue 'frames;
+                    }
+                    Op::SetField(o, name, s) => {
+                        let (obj, value) = (reg!(o), reg!(s));
+                        let instance = match obj {
+                            Value::HeapId(id) => match self.heap.get_mut(id) {
+                                Some(HeapValue::Class(instance)) => Some(instance),
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        match instance {
+                            Some(instance) => {
+                                instance.fields.insert(name, value);
+                            }
+                            None => fail!(format!("{obj:?} has no field `{name}`")),
+                        }
+                    }
+                    Op::Closure(d, proto) => {
+                        self.gc_point(top);
+                        let id = self.make_closure(proto, base, closure);
+                        reg!(d) = Value::Closure(id);
+                    }
+                    Op::Tuple(d, first, n) => {
+                        self.gc_point(top);
+                        let start = base + first as usize;
+                        let items = self.regs[start..start + n as usize].to_vec();
+                        reg!(d) = Value::HeapId(self.heap.alloc(HeapValue::Vec(items)));
+                    }
+                    Op::IsTuple(d, s, n) => {
+                        let is = match reg!(s) {
+                            Value::HeapId(idx) => matches!(self.heap.get(idx), Some(HeapValue::Vec(items)) if items.len() == n as usize),
+                            _ => false,
+                        };
+                        reg!(d) = Value::Bool(is);
+                    }
+                    Op::GetItem(d, s, i) => {
+                        let item = match reg!(s) {
+                            Value::HeapId(idx) => match self.heap.get(idx) {
+                                Some(HeapValue::Vec(items)) => items.get(i as usize).copied(),
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        match item {
+                            Some(v) => reg!(d) = v,
+                            None => fail!(format!("{:?} has no item {i}", reg!(s))),
+                        }
+                    }
+                    Op::Close(r) => self.close(base + r as usize),
+                    Op::Return(r) => {
+                        let value = reg!(r);
+                        self.close(base);
+                        let frame = self.frames.pop().unwrap();
+                        if self.frames.len() == self.stop {
+                            return Ok(value);
+                        }
+                        match frame.ret {
+                            Ret::To(r) => self.regs[r] = value,
+                            Ret::This(r) => self.regs[r] = self.regs[frame.base],
+                            Ret::Drop => {}
+                        }
+                        continue 'frames;
+                    }
+                    Op::MatchError(r) => fail!(format!("match error: {:?}", reg!(r))),
+                    Op::Error(c) => fail!(program.strings[c as usize].clone()),
+                }
+            }
+        }
+    }
+}
+
+impl Host for Machine {
+    fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+    /// run a closure above the frame calling the built-in
+    fn call(&mut self, f: Value, args: Vec<Value>) -> Result<Value, String> {
+        let Value::Closure(id) = f else {
+            return Err(format!("{f:?} is not a function"));
+        };
+        let proto = &self.program.protos[self.closures[id].proto as usize];
+        if proto.params as usize != args.len() {
+            return Err(format!("`{}` takes {} arguments but {} were given", proto.name, proto.params, args.len()));
+        }
+        let last = self.frames.last().expect("a built-in runs in a frame");
+        let base = last.base + self.program.protos[last.proto as usize].max_regs as usize;
+        let stop = std::mem::replace(&mut self.stop, self.frames.len());
+        self.push_frame(self.closures[id].proto, Some(id), base, Ret::Drop);
+        self.regs[base..base + args.len()].copy_from_slice(&args);
+        let ret = self.execute();
+        self.stop = stop;
+        ret.map_err(|e| e.msg)
+    }
+    fn print(&mut self, line: &str) {
+        match &mut self.output {
+            Some(output) => {
+                output.push_str(line);
+                output.push('\n');
+            }
+            None => println!("{line}"),
+        }
+    }
+}
+
+#[inline]
+fn int_op(l: Value, r: Value, f: fn(i64, i64) -> Option<i64>) -> Result<Value, &'static str> {
+    match (l, r) {
+        (Value::Int(l), Value::Int(r)) => match f(l, r) {
+            Some(x) => Ok(Value::Int(x)),
+            None if r == 0 => Err("division by zero"),
+            None => Err("integer overflow"),
+        },
+        _ => Err("expect Int"),
+    }
+}
+
+#[inline]
+fn int_cmp(l: Value, r: Value, f: fn(i64, i64) -> bool) -> Result<Value, &'static str> {
+    match (l, r) {
+        (Value::Int(l), Value::Int(r)) => Ok(Value::Bool(f(l, r))),
+        _ => Err("expect Int"),
+    }
+}
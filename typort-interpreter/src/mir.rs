@@ -1,28 +1,119 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::Span;
+use crate::{Ident, Span, Symbol, TypeExpr};
+use typort_parser::ast::ClassKind;
+
+/// where a name lives at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Var {
+    /// a slot in the stack frame of the current function
+    Local(usize),
+    /// a value captured from an enclosing function
+    Upvalue(usize),
+    /// not bound in any enclosing scope: a class, a built-in or a native
+    Global(Symbol),
+}
+
+/// how a closure gets each captured value when it is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /// a slot in the frame of the enclosing function
+    Local(usize),
+    /// an upvalue of the enclosing function
+    Upvalue(usize),
+}
 
 #[derive(Debug, Clone)]
 pub enum Expression {
     Int(Span<i64>),
-    String(usize, Span<String>),
-    Bool(bool),
-    Name(Span<usize>),
+    String(Span<String>),
+    Bool(Span<bool>),
+    Name(Span<Var>),
+    Field(Box<Expression>, Ident),
     Add(Box<Expression>, Box<Expression>),
     Sub(Box<Expression>, Box<Expression>),
     Mul(Box<Expression>, Box<Expression>),
     Div(Box<Expression>, Box<Expression>),
+    Mod(Box<Expression>, Box<Expression>),
     Eq(Box<Expression>, Box<Expression>),
     Neq(Box<Expression>, Box<Expression>),
-    Call(Span<String>, Vec<Expression>),
+    Lt(Box<Expression>, Box<Expression>),
+    Le(Box<Expression>, Box<Expression>),
+    Gt(Box<Expression>, Box<Expression>),
+    Ge(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Neg(Box<Expression>),
+    Call(Ident, Vec<Expression>),
+    MethodCall(Box<Expression>, Ident, Vec<Expression>),
+    /// call the closure an expression evaluates to
+    Apply(Box<Expression>, Vec<Expression>),
     If(Box<Expression>, Vec<Stmt>, Option<Vec<Stmt>>),
+    Block(Vec<Stmt>),
+    Tuple(Vec<Expression>),
+    Lambda(Vec<usize>, Vec<Capture>, Vec<Stmt>),
+    Match(Box<Expression>, Vec<Case>),
+}
+
+impl Expression {
+    /// where runtime errors about this expression point to
+    pub fn span(&self) -> Option<Span<()>> {
+        match self {
+            Expression::Int(x) => Some(x.with_data(())),
+            Expression::String(x) => Some(x.with_data(())),
+            Expression::Bool(x) => Some(x.with_data(())),
+            Expression::Name(x) => Some(x.with_data(())),
+            Expression::Call(x, _) => Some(x.with_data(())),
+            Expression::Field(a, _)
+            | Expression::Add(a, _)
+            | Expression::Sub(a, _)
+            | Expression::Mul(a, _)
+            | Expression::Div(a, _)
+            | Expression::Mod(a, _)
+            | Expression::Eq(a, _)
+            | Expression::Neq(a, _)
+            | Expression::Lt(a, _)
+            | Expression::Le(a, _)
+            | Expression::Gt(a, _)
+            | Expression::Ge(a, _)
+            | Expression::And(a, _)
+            | Expression::Or(a, _)
+            | Expression::Not(a)
+            | Expression::Neg(a)
+            | Expression::MethodCall(a, _, _)
+            | Expression::Apply(a, _)
+            | Expression::If(a, _, _)
+            | Expression::Match(a, _) => a.span(),
+            Expression::Block(b) | Expression::Lambda(_, _, b) => b.iter().find_map(Stmt::span),
+            Expression::Tuple(v) => v.iter().find_map(Expression::span),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub block: Vec<Stmt>,
+}
+
+/// binds are pushed to the stack in the order they appear in the pattern
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Literal(Expression),
+    Bind(Span<usize>),
+    Tuple(Vec<Pattern>),
+    Extractor(Ident, Vec<Pattern>),
 }
 
 #[derive(Debug, Clone)]
 pub struct Func {
-    pub name: Span<String>,
+    pub name: Ident,
     pub params: Vec<usize>,
-    pub return_type: Option<Span<String>>,
+    pub captures: Vec<Capture>,
+    pub return_type: Option<TypeExpr>,
     pub block: Vec<Stmt>,
 }
 
@@ -30,157 +121,513 @@ pub struct Func {
 pub enum Stmt {
     Expr(Expression),
     Let(Span<usize>, Expression),
-    Assign(Span<usize>, Expression),
+    Assign(Span<Var>, Expression),
+    /// object, field, value
+    SetField(Expression, Ident, Expression),
     Return(Expression),
-    While(Expression, Vec<Stmt>),
+    Break(Span<()>),
+    Continue(Span<()>),
+    /// condition, body, and the statements run after each iteration
+    While(Expression, Vec<Stmt>, Vec<Stmt>),
     Block(Vec<Stmt>),
+    /// a `def` in a block, its closure is written to `slot`
     Func {
-        name: Span<String>,
+        name: Ident,
+        slot: usize,
         params: Vec<usize>,
-        return_type: Option<Span<String>>,
+        captures: Vec<Capture>,
+        return_type: Option<TypeExpr>,
         block: Vec<Stmt>,
     },
 }
 
+impl Stmt {
+    pub fn span(&self) -> Option<Span<()>> {
+        match self {
+            Stmt::Expr(e) | Stmt::Return(e) | Stmt::While(e, _, _) => e.span(),
+            Stmt::Break(a) | Stmt::Continue(a) => Some(a.clone()),
+            Stmt::Let(a, _) => Some(a.with_data(())),
+            Stmt::Assign(a, _) => Some(a.with_data(())),
+            Stmt::SetField(_, a, _) => Some(a.with_data(())),
+            Stmt::Block(b) => b.iter().find_map(Stmt::span),
+            Stmt::Func { name, .. } => Some(name.with_data(())),
+        }
+    }
+}
+
+/// the body of a class is its constructor, `this` is in slot 0 of it and of
+/// every method, the arguments follow
 #[derive(Debug, Clone)]
 pub struct Class {
     //TODO: add type for object, class, case class, abstract class
-    pub name: Span<String>,
-    pub args: Vec<(Span<usize>, Span<String>)>,
-    pub extends: Option<Span<String>>,
-    pub with: Vec<Span<String>>,
-    //pub value: Vec<Span<String>>,
-    pub func: HashMap<String, usize>,
-    pub funcs: HashMap<String, Func>,
+    pub name: Ident,
+    pub args: Vec<(Span<usize>, TypeExpr)>,
+    pub extends: Option<Ident>,
+    pub with: Vec<Ident>,
+    /// the class and the known classes it inherits from, see `hir::linearize`.
+    /// constructors run from the last one to the first
+    pub linearization: Vec<Symbol>,
+    pub func: HashMap<Symbol, usize>,
+    /// the methods declared in this class
+    pub funcs: HashMap<Symbol, Func>,
+    /// every method of an instance and the class it comes from
+    pub methods: HashMap<Symbol, Symbol>,
     pub block: Vec<Stmt>,
 }
 
+impl Class {
+    /// the method `name` of instances of this class
+    pub fn method<'a>(&self, classes: &'a HashMap<Symbol, Class>, name: Symbol) -> Option<&'a Func> {
+        classes.get(self.methods.get(&name)?)?.funcs.get(&name)
+    }
+}
+
 pub fn hir_to_mir(from: Vec<crate::hir::Class>) -> Vec<Class> {
-    from.into_iter()
-        .map(|x| {
-            let mut converter = MirConverter::new();
-            converter.convert(x)
-        })
-        .collect()
+    let mut known = Known::default();
+    for c in from.iter() {
+        known.add(c);
+    }
+    let mut out = vec![];
+    // the bodies of `new A { .. }` become classes, converted after the others
+    let mut queue: std::collections::VecDeque<_> = from.into();
+    while let Some(x) = queue.pop_front() {
+        let linearization = known.linearize(x.name.data);
+        let mut converter = MirConverter::new();
+        for c in linearization.iter().rev() {
+            converter.fields.extend(known.fields[c].iter().copied());
+            converter.methods.extend(known.methods[c].iter().map(|m| (*m, *c)));
+            converter.abstract_methods.extend(known.abstract_methods[c].iter().copied());
+        }
+        let mut class = converter.convert(x);
+        class.linearization = linearization;
+        class.methods = converter.methods;
+        for (name, parent, block) in converter.anonymous {
+            let args = known.args.get(&parent.data).cloned().unwrap_or_default();
+            let anonymous = crate::hir::Class {
+                kind: ClassKind::Class,
+                name,
+                args,
+                extends: Some(parent),
+                with: vec![],
+                func: Default::default(),
+                block,
+            };
+            known.add(&anonymous);
+            queue.push_back(anonymous);
+        }
+        out.push(class);
+    }
+    out
+}
+
+/// the members of every class, the ones inherited are found from them
+#[derive(Default)]
+struct Known {
+    parents: HashMap<Symbol, Vec<Symbol>>,
+    args: HashMap<Symbol, Vec<(Ident, TypeExpr)>>,
+    fields: HashMap<Symbol, Vec<Symbol>>,
+    methods: HashMap<Symbol, Vec<Symbol>>,
+    abstract_methods: HashMap<Symbol, Vec<Symbol>>,
+}
+
+impl Known {
+    fn add(&mut self, c: &crate::hir::Class) {
+        self.parents.insert(c.name.data, c.extends.iter().chain(&c.with).map(|x| x.data).collect());
+        self.args.insert(c.name.data, c.args.clone());
+        let f = self.fields.entry(c.name.data).or_default();
+        f.extend(c.args.iter().map(|a| a.0.data));
+        let m = self.methods.entry(c.name.data).or_default();
+        let a = self.abstract_methods.entry(c.name.data).or_default();
+        for stmt in c.block.iter() {
+            match stmt {
+                crate::hir::Stmt::Let(x, _, _) | crate::hir::Stmt::Decl(x, _) => f.push(x.data),
+                crate::hir::Stmt::Func { name, block: Some(_), .. } => m.push(name.data),
+                crate::hir::Stmt::Func { name, block: None, .. } => a.push(name.data),
+                _ => {}
+            }
+        }
+    }
+    fn linearize(&self, name: Symbol) -> Vec<Symbol> {
+        crate::hir::linearize(name, &|c| self.parents.get(&c).cloned().unwrap_or_default())
+            .into_iter()
+            .filter(|c| self.parents.contains_key(c))
+            .collect()
+    }
+}
+
+/// names of one function being converted
+#[derive(Default)]
+struct FuncScope {
+    /// block scopes, a name maps to its stack slot
+    scopes: Vec<HashMap<Symbol, usize>>,
+    /// the stack slot the next `Let` goes to
+    next_slot: usize,
+    captures: Vec<(Symbol, Capture)>,
 }
 
 struct MirConverter {
-    rename: Vec<HashMap<String, usize>>,
-    rename_idx: Vec<usize>,
-    heap_idx: Vec<usize>,
+    funcs: Vec<FuncScope>,
+    /// fields of the class being converted, including inherited ones
+    fields: HashSet<Symbol>,
+    /// methods of the class being converted and the class they come from
+    methods: HashMap<Symbol, Symbol>,
+    /// methods without a body in the class being converted or what it
+    /// inherits from, a subclass implements them
+    abstract_methods: HashSet<Symbol>,
+    /// the class being converted
+    class: Symbol,
+    /// the name given to the body of each `new A { .. }`, the class it extends
+    /// and the body. it sees its own members, not the locals around it
+    anonymous: Vec<(Ident, Ident, Vec<crate::hir::Stmt>)>,
 }
 
 impl MirConverter {
     pub fn new() -> Self {
         Self {
-            rename: vec![Default::default()],
-            rename_idx: vec![0],
-            heap_idx: vec![0],
+            funcs: vec![],
+            fields: Default::default(),
+            methods: Default::default(),
+            abstract_methods: Default::default(),
+            class: Symbol::intern(""),
+            anonymous: vec![],
         }
     }
+    fn is_method(&self, name: Symbol) -> bool {
+        self.methods.contains_key(&name) || self.abstract_methods.contains(&name)
+    }
+    /// the `val`s and `var`s at the top of the class body become fields and
+    /// its `def`s become methods, the rest is the constructor
     pub fn convert(&mut self, from: crate::hir::Class) -> Class {
+        self.class = from.name.data;
+        self.push_func();
+        self.bind(Symbol::THIS);
+        let mut block = vec![];
         for p in from.args.iter() {
-            self.rename.last_mut().unwrap().insert(p.0.data.to_string(), *self.rename_idx.last().unwrap());
-            *self.rename_idx.last_mut().unwrap() += 1;
+            let slot = self.bind(p.0.data);
+            let this = self.this(&p.0);
+            block.push(Stmt::SetField(this, p.0.clone(), Expression::Name(p.0.with_data(Var::Local(slot)))));
         }
+        let mut funcs = HashMap::new();
+        for stmt in from.block {
+            match stmt {
+                crate::hir::Stmt::Let(a, _type, b) => {
+                    let b = self.convert_expr(b);
+                    let this = self.this(&a);
+                    block.push(Stmt::SetField(this, a, b));
+                }
+                // abstract members only matter to name resolution
+                crate::hir::Stmt::Decl(..) | crate::hir::Stmt::Func { block: None, .. } => {}
+                crate::hir::Stmt::Func { overrides: _, name, params, return_type, block: Some(body) } => {
+                    // methods see the fields, not the locals of the constructor
+                    let ctor = std::mem::take(&mut self.funcs);
+                    let params = std::iter::once(Symbol::THIS).chain(params.into_iter().map(|x| x.0.data));
+                    let (params, captures, body) = self.convert_func(params, body);
+                    self.funcs = ctor;
+                    funcs.insert(name.data, Func { name, params, captures, return_type, block: body });
+                }
+                stmt => block.push(self.convert_stmt(stmt)),
+            }
+        }
+        self.funcs.pop();
         Class {
-            name: from.name.map(|x| x.to_owned()),
+            name: from.name,
             args: from
                 .args
                 .into_iter()
                 .enumerate()
-                .map(|(idx, x)| (x.0.map(|_| idx), x.1.map(|x| x.to_owned())))
+                .map(|(idx, x)| (x.0.map(|_| idx + 1), x.1))
                 .collect(),
-            extends: from.extends.map(|x| x.map(|y| y.to_owned())),
-            with: from.with.into_iter().map(|x| x.map(|y| y.to_owned())).collect(),
+            extends: from.extends,
+            with: from.with,
+            linearization: vec![],
             func: from.func,
-            funcs: Default::default(),//TODO:
-            block: from.block.into_iter().map(|x| self.convert_stmt(x)).collect(),
+            funcs,
+            methods: Default::default(),
+            block,
         }
     }
+    /// `this` at the location of `at`
+    fn this(&mut self, at: &Ident) -> Expression {
+        let var = self.resolve(Symbol::THIS);
+        Expression::Name(at.with_data(var))
+    }
+    /// a name that is not a local is a field or a method of `this`
+    fn convert_name(&mut self, x: Ident) -> Expression {
+        match self.resolve(x.data) {
+            Var::Global(_) if self.fields.contains(&x.data) => {
+                Expression::Field(Box::new(self.this(&x)), x)
+            }
+            Var::Global(_) if self.is_method(x.data) => {
+                Expression::MethodCall(Box::new(self.this(&x)), x, vec![])
+            }
+            var => Expression::Name(x.map(|_| var)),
+        }
+    }
+    fn func(&mut self) -> &mut FuncScope {
+        self.funcs.last_mut().unwrap()
+    }
+    fn push_func(&mut self) {
+        self.funcs.push(FuncScope {
+            scopes: vec![Default::default()],
+            ..Default::default()
+        });
+    }
+    /// give `name` the next stack slot of the current function
+    fn bind(&mut self, name: Symbol) -> usize {
+        let func = self.func();
+        let slot = func.next_slot;
+        func.scopes.last_mut().unwrap().insert(name, slot);
+        func.next_slot += 1;
+        slot
+    }
+    /// convert the statements of a nested block, its slots are freed at the end
+    fn convert_block(&mut self, block: Vec<crate::hir::Stmt>) -> Vec<Stmt> {
+        let next_slot = self.func().next_slot;
+        self.func().scopes.push(Default::default());
+        let ret = self.convert_stmts(block);
+        self.func().scopes.pop();
+        self.func().next_slot = next_slot;
+        ret
+    }
+    /// convert the body of a `def` or a lambda, also returns what it captures
+    fn convert_func(
+        &mut self,
+        params: impl IntoIterator<Item = Symbol>,
+        block: Vec<crate::hir::Stmt>,
+    ) -> (Vec<usize>, Vec<Capture>, Vec<Stmt>) {
+        self.push_func();
+        let params = params.into_iter().map(|p| self.bind(p)).collect();
+        let block = self.convert_stmts(block);
+        let func = self.funcs.pop().unwrap();
+        (params, func.captures.into_iter().map(|x| x.1).collect(), block)
+    }
+    /// the `def`s of a block get their slots before anything is converted, so
+    /// they can call each other and themselves
+    fn convert_stmts(&mut self, block: Vec<crate::hir::Stmt>) -> Vec<Stmt> {
+        let mut stmts = vec![];
+        for stmt in &block {
+            if let crate::hir::Stmt::Func { name, .. } = stmt {
+                let slot = self.bind(name.data);
+                stmts.push(Stmt::Let(name.with_data(slot), Expression::Block(vec![])));
+            }
+        }
+        stmts.extend(block.into_iter().map(|x| self.convert_stmt(x)));
+        stmts
+    }
+    fn resolve(&mut self, name: Symbol) -> Var {
+        let depth = self.funcs.len() - 1;
+        self.resolve_in(depth, name)
+    }
+    /// look `name` up in the function at `depth`, capturing it from the enclosing
+    /// functions when needed
+    fn resolve_in(&mut self, depth: usize, name: Symbol) -> Var {
+        let func = &self.funcs[depth];
+        if let Some(slot) = func.scopes.iter().rev().find_map(|s| s.get(&name)) {
+            return Var::Local(*slot);
+        }
+        if let Some(idx) = func.captures.iter().position(|c| c.0 == name) {
+            return Var::Upvalue(idx);
+        }
+        if depth == 0 {
+            return Var::Global(name);
+        }
+        let capture = match self.resolve_in(depth - 1, name) {
+            Var::Local(slot) => Capture::Local(slot),
+            Var::Upvalue(idx) => Capture::Upvalue(idx),
+            Var::Global(name) => return Var::Global(name),
+        };
+        let captures = &mut self.funcs[depth].captures;
+        captures.push((name, capture));
+        Var::Upvalue(captures.len() - 1)
+    }
     fn convert_stmt(&mut self, x: crate::hir::Stmt) -> Stmt {
         match x {
             crate::hir::Stmt::Expr(e) => Stmt::Expr(self.convert_expr(e)),
-            crate::hir::Stmt::Let(a, b) => {
-                self.rename.last_mut().unwrap().insert(a.data.to_string(), *self.rename_idx.last().unwrap());
-                let ret = Stmt::Let(a.map(|_| *self.rename_idx.last().unwrap()), self.convert_expr(b));
-                *self.rename_idx.last_mut().unwrap() += 1;
-                ret
+            crate::hir::Stmt::Let(a, _type, b) => {
+                let b = self.convert_expr(b);
+                let slot = self.bind(a.data);
+                Stmt::Let(a.map(|_| slot), b)
             },
             crate::hir::Stmt::Assign(a, b) => {
-                let idx = self.rename.last().unwrap().get(&a.data).unwrap();
-                Stmt::Assign(a.map(|_| *idx), self.convert_expr(b))
+                let var = self.resolve(a.data);
+                let b = self.convert_expr(b);
+                match var {
+                    Var::Global(_) if self.fields.contains(&a.data) => Stmt::SetField(self.this(&a), a, b),
+                    var => Stmt::Assign(a.map(|_| var), b),
+                }
             },
             crate::hir::Stmt::Return(e) => Stmt::Return(self.convert_expr(e)),
-            crate::hir::Stmt::While(e, v) => {
-                Stmt::While(self.convert_expr(e), v.into_iter().map(|x| self.convert_stmt(x)).collect())
-            }
-            crate::hir::Stmt::Block(b) => Stmt::Block(b.into_iter().map(|bb| self.convert_stmt(bb)).collect()),
-            crate::hir::Stmt::Func { name, params, return_type, block } => {
-                self.rename.push(Default::default());
-                self.rename_idx.push(0);
-                self.heap_idx.push(0);
-                let mut param = vec![];
-                for (a, _type) in params {
-                    self.rename.last_mut().unwrap().insert(a.data.to_string(), *self.rename_idx.last().unwrap());
-                    param.push(*self.rename_idx.last_mut().unwrap());
-                    *self.rename_idx.last_mut().unwrap() += 1;
+            crate::hir::Stmt::Break(a) => Stmt::Break(a),
+            crate::hir::Stmt::Continue(a) => Stmt::Continue(a),
+            crate::hir::Stmt::While(e, v, step) => {
+                Stmt::While(self.convert_expr(e), self.convert_block(v), self.convert_block(step))
+            }
+            crate::hir::Stmt::Block(b) => Stmt::Block(self.convert_block(b)),
+            // abstract members are only at the top of a class body
+            crate::hir::Stmt::Decl(..) => Stmt::Block(vec![]),
+            crate::hir::Stmt::Func { overrides: _, name, params, return_type, block } => {
+                let slot = *self.func().scopes.last().unwrap().get(&name.data)
+                    .expect("bound by `convert_stmts`");
+                let block = block.unwrap_or_default();
+                let (params, captures, block) = self.convert_func(params.into_iter().map(|x| x.0.data), block);
+                Stmt::Func {
+                    name,
+                    slot,
+                    params,
+                    captures,
+                    return_type,
+                    block,
                 }
-                let ret = Stmt::Func {
-                    name: name.map(|x| x.to_owned()),
-                    params: param,//params.into_iter().map(|x| x.0.map(|y| y.to_owned())).collect(),//TODO:
-                    return_type: return_type.map(|x| x.map(|y| y.to_owned())),
-                    block: block.into_iter().map(|x| self.convert_stmt(x)).collect(),//TODO:
-                };
-                self.rename.pop();
-                self.rename_idx.pop();
-                self.heap_idx.pop();
-                ret
             },
         }
     }
+    fn convert_binary(
+        &mut self,
+        a: crate::hir::Expression,
+        b: crate::hir::Expression,
+    ) -> (Box<Expression>, Box<Expression>) {
+        (Box::new(self.convert_expr(a)), Box::new(self.convert_expr(b)))
+    }
     fn convert_expr(&mut self, x: crate::hir::Expression) -> Expression {
         match x {
             crate::hir::Expression::Int(x) => Expression::Int(x),
-            crate::hir::Expression::String(x) => {
-                let ret = Expression::String(*self.heap_idx.last().unwrap(), x);
-                *self.heap_idx.last_mut().unwrap() += 1;
-                ret
-            },
+            crate::hir::Expression::String(x) => Expression::String(x),
             crate::hir::Expression::Bool(x) => Expression::Bool(x),
-            crate::hir::Expression::Name(x) => {
-                let idx = self.rename.last().unwrap().get(&x.data).unwrap();
-                Expression::Name(x.map(|_| *idx))
-            },
+            crate::hir::Expression::Name(x) => self.convert_name(x),
+            crate::hir::Expression::Field(a, b) => Expression::Field(Box::new(self.convert_expr(*a)), b),
             crate::hir::Expression::Add(a, b) => {
-                Expression::Add(Box::new(self.convert_expr(*a)), Box::new(self.convert_expr(*b)))
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Add(a, b)
             }
             crate::hir::Expression::Sub(a, b) => {
-                Expression::Sub(Box::new(self.convert_expr(*a)), Box::new(self.convert_expr(*b)))
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Sub(a, b)
             }
             crate::hir::Expression::Mul(a, b) => {
-                Expression::Mul(Box::new(self.convert_expr(*a)), Box::new(self.convert_expr(*b)))
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Mul(a, b)
             }
             crate::hir::Expression::Div(a, b) => {
-                Expression::Div(Box::new(self.convert_expr(*a)), Box::new(self.convert_expr(*b)))
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Div(a, b)
+            }
+            crate::hir::Expression::Mod(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Mod(a, b)
             }
             crate::hir::Expression::Eq(a, b) => {
-                Expression::Eq(Box::new(self.convert_expr(*a)), Box::new(self.convert_expr(*b)))
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Eq(a, b)
             }
             crate::hir::Expression::Neq(a, b) => {
-                Expression::Neq(Box::new(self.convert_expr(*a)), Box::new(self.convert_expr(*b)))
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Neq(a, b)
             }
-            crate::hir::Expression::Call(a, b) => {
-                Expression::Call(a.map(|x| x.to_owned()), b.into_iter().map(|x| self.convert_expr(x)).collect())
+            crate::hir::Expression::Lt(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Lt(a, b)
+            }
+            crate::hir::Expression::Le(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Le(a, b)
+            }
+            crate::hir::Expression::Gt(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Gt(a, b)
             }
-            crate::hir::Expression::ObjCall(a, b, c) => {
-                Expression::Call(a.map(|x| x.to_owned()), c.into_iter().map(|x| self.convert_expr(x)).collect())//TODO: b
+            crate::hir::Expression::Ge(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Ge(a, b)
             }
+            crate::hir::Expression::And(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::And(a, b)
+            }
+            crate::hir::Expression::Or(a, b) => {
+                let (a, b) = self.convert_binary(*a, *b);
+                Expression::Or(a, b)
+            }
+            crate::hir::Expression::Not(a) => Expression::Not(Box::new(self.convert_expr(*a))),
+            crate::hir::Expression::Neg(a) => Expression::Neg(Box::new(self.convert_expr(*a))),
+            crate::hir::Expression::Call(a, b) => {
+                let args = b.into_iter().map(|x| self.convert_expr(x)).collect();
+                match self.resolve(a.data) {
+                    Var::Global(_) if self.is_method(a.data) => {
+                        Expression::MethodCall(Box::new(self.this(&a)), a, args)
+                    }
+                    Var::Global(_) => Expression::Call(a, args),
+                    // a `def` of a block or a function value
+                    var => Expression::Apply(Box::new(Expression::Name(a.with_data(var))), args),
+                }
+            }
+            crate::hir::Expression::MethodCall(a, b, c) if b.data == Symbol::APPLY => Expression::Apply(
+                Box::new(self.convert_expr(*a)),
+                c.into_iter().map(|x| self.convert_expr(x)).collect(),
+            ),
+            crate::hir::Expression::MethodCall(a, b, c) => Expression::MethodCall(
+                Box::new(self.convert_expr(*a)),
+                b,
+                c.into_iter().map(|x| self.convert_expr(x)).collect(),
+            ),
             crate::hir::Expression::If(c, b, e) => Expression::If(
                 Box::new(self.convert_expr(*c)),
-                b.into_iter().map(|x| self.convert_stmt(x)).collect(),
-                e.map(|x| x.into_iter().map(|y| self.convert_stmt(y)).collect()),
+                self.convert_block(b),
+                e.map(|x| self.convert_block(x)),
             ),
+            crate::hir::Expression::Block(b) => {
+                Expression::Block(self.convert_block(b))
+            }
+            crate::hir::Expression::Tuple(v) => {
+                Expression::Tuple(v.into_iter().map(|x| self.convert_expr(x)).collect())
+            }
+            crate::hir::Expression::New(a, b, body) => {
+                let args = b.into_iter().map(|x| self.convert_expr(x)).collect();
+                let Some(body) = body else {
+                    return Expression::Call(a, args);
+                };
+                let name = Symbol::intern(&format!("{}$anon{}", self.class, self.anonymous.len()));
+                self.anonymous.push((a.with_data(name), a.clone(), body));
+                Expression::Call(a.with_data(name), args)
+            }
+            crate::hir::Expression::Lambda(params, block) => {
+                let (params, captures, block) = self.convert_func(params.into_iter().map(|x| x.0.data), block);
+                Expression::Lambda(params, captures, block)
+            }
+            crate::hir::Expression::Match(e, cases) => {
+                let e = self.convert_expr(*e);
+                let cases = cases
+                    .into_iter()
+                    .map(|c| {
+                        // every case starts from the same stack height
+                        let next_slot = self.func().next_slot;
+                        self.func().scopes.push(Default::default());
+                        let pattern = self.convert_pattern(c.pattern);
+                        let guard = c.guard.map(|x| self.convert_expr(x));
+                        let block = self.convert_stmts(c.block);
+                        self.func().scopes.pop();
+                        self.func().next_slot = next_slot;
+                        Case { pattern, guard, block }
+                    })
+                    .collect();
+                Expression::Match(Box::new(e), cases)
+            }
+        }
+    }
+    fn convert_pattern(&mut self, x: crate::hir::Pattern) -> Pattern {
+        match x {
+            crate::hir::Pattern::Wildcard => Pattern::Wildcard,
+            crate::hir::Pattern::Literal(e) => Pattern::Literal(self.convert_expr(e)),
+            //TODO: check the type of a typed pattern
+            crate::hir::Pattern::Bind(a) | crate::hir::Pattern::Typed(a, _) => {
+                let slot = self.bind(a.data);
+                Pattern::Bind(a.map(|_| slot))
+            }
+            crate::hir::Pattern::Tuple(v) => {
+                Pattern::Tuple(v.into_iter().map(|x| self.convert_pattern(x)).collect())
+            }
+            crate::hir::Pattern::Extractor(a, v) => {
+                Pattern::Extractor(a, v.into_iter().map(|x| self.convert_pattern(x)).collect())
+            }
         }
     }
 }
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::built_in::Host;
+use crate::hir;
+use crate::ty::Type;
+use crate::value::{HeapValue, Value};
+use crate::{Diagnostic, Symbol};
+
+/// a function written in Rust, it reports a message like a built-in
+pub type NativeFn = dyn Fn(&mut dyn Host, Vec<Value>) -> Result<Value, String>;
+
+/// a function registered by a user of the crate
+#[derive(Clone)]
+pub struct Native {
+    pub name: Symbol,
+    pub params: Vec<Type>,
+    pub ret: Type,
+    f: Rc<NativeFn>,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({}): {}", self.name, self.params.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", "), self.ret)
+    }
+}
+
+impl Native {
+    pub fn call(&self, host: &mut dyn Host, args: Vec<Value>) -> Result<Value, String> {
+        if args.len() != self.params.len() {
+            return Err(format!("`{}` takes {} arguments but {} were given", self.name, self.params.len(), args.len()));
+        }
+        (self.f)(host, args)
+    }
+}
+
+/// a set of native functions to give to `compile_to_mir_with` and the vms.
+/// calls look for a built-in first, then for a native, then for a `def`
+#[derive(Clone, Debug, Default)]
+pub struct Natives {
+    funcs: HashMap<Symbol, Native>,
+}
+
+/// a library of native functions, e.g. a crate reading register maps
+pub trait Extension {
+    fn register(&self, natives: &mut Natives);
+}
+
+impl Natives {
+    /// register a Rust function or closure, its signature comes from the types
+    /// of its arguments and its result, a later one replaces an earlier one
+    pub fn register<Args, F: IntoNative<Args>>(&mut self, name: &str, f: F) -> &mut Self {
+        self.insert(name, F::params(), F::ret(), f.into_native())
+    }
+    /// register a function working on the values themselves, e.g. one calling
+    /// a function value it is given. the number of arguments is checked before
+    /// it is called
+    pub fn register_raw(
+        &mut self,
+        name: &str,
+        params: Vec<Type>,
+        ret: Type,
+        f: impl Fn(&mut dyn Host, Vec<Value>) -> Result<Value, String> + 'static,
+    ) -> &mut Self {
+        self.insert(name, params, ret, Rc::new(f))
+    }
+    fn insert(&mut self, name: &str, params: Vec<Type>, ret: Type, f: Rc<NativeFn>) -> &mut Self {
+        let name = Symbol::intern(name);
+        self.funcs.insert(name, Native { name, params, ret, f });
+        self
+    }
+    pub fn load(&mut self, extension: &dyn Extension) -> &mut Self {
+        extension.register(self);
+        self
+    }
+    pub fn get(&self, name: Symbol) -> Option<&Native> {
+        self.funcs.get(&name)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Native> {
+        self.funcs.values()
+    }
+    /// check the number of arguments of every call to a native, and the type
+    /// of the ones whose type is known
+    pub fn check(&self, classes: &[hir::Class]) -> Vec<Diagnostic> {
+        let mut diag = vec![];
+        let mut check = |e: &hir::Expression| {
+            let hir::Expression::Call(name, args) = e else { return };
+            let Some(native) = self.get(name.data) else { return };
+            if args.len() != native.params.len() {
+                diag.push(
+                    Diagnostic::new(
+                        format!("\"{}\" takes {} arguments but {} were given", name.data, native.params.len(), args.len()),
+                        name,
+                    )
+                    .with_code("E0201")
+                    .with_note(format!("the signature is {native:?}")),
+                );
+                return;
+            }
+            for (arg, param) in args.iter().zip(&native.params) {
+                match Type::of(arg) {
+                    Some(ty) if !param.accepts(&ty) => diag.push(
+                        Diagnostic::new(format!("expected {param}, found {ty}"), &arg.span().unwrap_or(name.with_data(())))
+                            .with_code("E0202")
+                            .with_note(format!("the signature is {native:?}")),
+                    ),
+                    _ => {}
+                }
+            }
+        };
+        for class in classes {
+            class.block.iter().for_each(|s| s.visit(&mut check));
+        }
+        diag
+    }
+}
+
+/// a Rust type a native takes
+pub trait FromValue: Sized {
+    fn ty() -> Type;
+    fn from_value(host: &mut dyn Host, value: Value) -> Result<Self, String>;
+}
+
+/// a Rust type a native gives back
+pub trait IntoValue {
+    fn ty() -> Type;
+    fn into_value(self, host: &mut dyn Host) -> Result<Value, String>;
+}
+
+impl FromValue for Value {
+    fn ty() -> Type {
+        Type::Any
+    }
+    fn from_value(_: &mut dyn Host, value: Value) -> Result<Self, String> {
+        Ok(value)
+    }
+}
+
+impl IntoValue for Value {
+    fn ty() -> Type {
+        Type::Any
+    }
+    fn into_value(self, _: &mut dyn Host) -> Result<Value, String> {
+        Ok(self)
+    }
+}
+
+impl FromValue for i64 {
+    fn ty() -> Type {
+        Type::I64
+    }
+    fn from_value(_: &mut dyn Host, value: Value) -> Result<Self, String> {
+        match value {
+            Value::Int(x) => Ok(x),
+            _ => Err("expect Int".to_owned()),
+        }
+    }
+}
+
+impl IntoValue for i64 {
+    fn ty() -> Type {
+        Type::I64
+    }
+    fn into_value(self, _: &mut dyn Host) -> Result<Value, String> {
+        Ok(Value::Int(self))
+    }
+}
+
+impl FromValue for bool {
+    fn ty() -> Type {
+        Type::Bool
+    }
+    fn from_value(_: &mut dyn Host, value: Value) -> Result<Self, String> {
+        match value {
+            Value::Bool(x) => Ok(x),
+            _ => Err("expect Bool".to_owned()),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn ty() -> Type {
+        Type::Bool
+    }
+    fn into_value(self, _: &mut dyn Host) -> Result<Value, String> {
+        Ok(Value::Bool(self))
+    }
+}
+
+impl IntoValue for () {
+    fn ty() -> Type {
+        Type::Unit
+    }
+    fn into_value(self, _: &mut dyn Host) -> Result<Value, String> {
+        Ok(Value::Unit)
+    }
+}
+
+impl FromValue for String {
+    fn ty() -> Type {
+        Type::Own("String".to_owned())
+    }
+    fn from_value(host: &mut dyn Host, value: Value) -> Result<Self, String> {
+        match value {
+            Value::HeapId(id) => match host.heap().get(id) {
+                Some(HeapValue::String(s)) => Ok(s.clone()),
+                _ => Err("expect String".to_owned()),
+            },
+            _ => Err("expect String".to_owned()),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn ty() -> Type {
+        Type::Own("String".to_owned())
+    }
+    fn into_value(self, host: &mut dyn Host) -> Result<Value, String> {
+        Ok(Value::HeapId(host.heap().alloc(HeapValue::String(self))))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn ty() -> Type {
+        Type::Own("Seq".to_owned())
+    }
+    fn from_value(host: &mut dyn Host, value: Value) -> Result<Self, String> {
+        let items = match value {
+            Value::HeapId(id) => match host.heap().get(id) {
+                Some(HeapValue::Vec(items)) => items.clone(),
+                _ => return Err("expect Seq".to_owned()),
+            },
+            _ => return Err("expect Seq".to_owned()),
+        };
+        items.into_iter().map(|x| T::from_value(host, x)).collect()
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn ty() -> Type {
+        Type::Own("Seq".to_owned())
+    }
+    fn into_value(self, host: &mut dyn Host) -> Result<Value, String> {
+        let items = self.into_iter().map(|x| x.into_value(host)).collect::<Result<_, _>>()?;
+        Ok(Value::HeapId(host.heap().alloc(HeapValue::Vec(items))))
+    }
+}
+
+/// a failing native reports the message
+impl<T: IntoValue> IntoValue for Result<T, String> {
+    fn ty() -> Type {
+        T::ty()
+    }
+    fn into_value(self, host: &mut dyn Host) -> Result<Value, String> {
+        self?.into_value(host)
+    }
+}
+
+/// a Rust function that can be registered, `Args` are the types it takes
+pub trait IntoNative<Args> {
+    fn params() -> Vec<Type>;
+    fn ret() -> Type;
+    fn into_native(self) -> Rc<NativeFn>;
+}
+
+macro_rules! into_native {
+    ($($a:ident $v:ident),*) => {
+        impl<F, R, $($a),*> IntoNative<($($a,)*)> for F
+        where
+            F: Fn($($a),*) -> R + 'static,
+            R: IntoValue,
+            $($a: FromValue,)*
+        {
+            fn params() -> Vec<Type> {
+                vec![$($a::ty()),*]
+            }
+            fn ret() -> Type {
+                R::ty()
+            }
+            #[allow(unused_mut, unused_variables)]
+            fn into_native(self) -> Rc<NativeFn> {
+                Rc::new(move |host, args| {
+                    let mut args = args.into_iter();
+                    $(let $v = $a::from_value(host, args.next().unwrap())?;)*
+                    self($($v),*).into_value(host)
+                })
+            }
+        }
+    };
+}
+
+into_native!();
+into_native!(A a);
+into_native!(A a, B b);
+into_native!(A a, B b, C c);
+into_native!(A a, B b, C c, D d);
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::hir::{Class, Expression, Stmt};
+use crate::index::{DefId, Direction, SymbolIndex};
+
+/// a port, a register or a wire of a component
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Net {
+    /// the path to it joined by `_`, e.g. `io_a`
+    pub name: String,
+    pub width: Option<i64>,
+    pub kind: NetKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetKind {
+    Port(Direction),
+    /// with the value it is reset to
+    Register(Option<String>),
+    Wire,
+}
+
+/// `target := value`, `value` is written in verilog
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub target: String,
+    pub value: String,
+    /// the nets `value` reads
+    pub sources: Vec<String>,
+    pub register: bool,
+}
+
+/// the hardware a component class describes, found from its `val`s and its
+/// `:=`. what can not be translated is kept to be reported
+#[derive(Debug, Clone)]
+pub struct Netlist {
+    pub name: String,
+    pub nets: Vec<Net>,
+    pub connections: Vec<Connection>,
+    /// the lines of the statements which were not translated
+    pub skipped: Vec<usize>,
+}
+
+impl Netlist {
+    pub fn new(class: &Class, index: &SymbolIndex) -> Self {
+        let mut netlist = Netlist {
+            name: class.name.data.to_string(),
+            nets: vec![],
+            connections: vec![],
+            skipped: vec![],
+        };
+        let mut names = HashMap::new();
+        netlist.add(&class.block, "", index, &mut names);
+        netlist
+    }
+    fn add(&mut self, block: &[Stmt], prefix: &str, index: &SymbolIndex, names: &mut HashMap<DefId, String>) {
+        for stmt in block {
+            match stmt {
+                Stmt::Let(name, _, value) => {
+                    let Some(def) = index.def_at(name) else { continue };
+                    let path = format!("{prefix}{}", name.data);
+                    if let Expression::New(_, _, Some(body)) = value {
+                        self.add(body, &format!("{path}_"), index, names);
+                        continue;
+                    }
+                    let Some(signal) = &index.defs[def].signal else { continue };
+                    names.insert(def, path.clone());
+                    let kind = match (&signal.direction, value) {
+                        (Some(direction), _) => NetKind::Port(*direction),
+                        (None, Expression::Call(f, args)) if f.data == "RegInit" => {
+                            NetKind::Register(args.first().and_then(|init| render(init, index, names, &mut vec![])))
+                        }
+                        _ if signal.register => NetKind::Register(None),
+                        _ => NetKind::Wire,
+                    };
+                    self.nets.push(Net { name: path.clone(), width: signal.width, kind });
+                    let driver = match value {
+                        Expression::Call(f, args) if f.data == "RegNext" => args.first(),
+                        Expression::Call(f, _) if is_constructor(f.data.as_str()) => None,
+                        value => Some(value),
+                    };
+                    if let Some(driver) = driver {
+                        self.connect(path, driver, signal.register, stmt, index, names);
+                    }
+                }
+                Stmt::Expr(Expression::MethodCall(target, op, args)) if op.data == ":=" && args.len() == 1 => {
+                    let mut sources = vec![];
+                    let Some(name) = render(target, index, names, &mut sources) else {
+                        self.skip(stmt);
+                        continue;
+                    };
+                    let register = self.nets.iter().any(|n| n.name == name && matches!(n.kind, NetKind::Register(_)));
+                    self.connect(name, &args[0], register, stmt, index, names);
+                }
+                Stmt::Func { .. } | Stmt::Decl(..) => {}
+                stmt => self.skip(stmt),
+            }
+        }
+    }
+    fn connect(&mut self, target: String, value: &Expression, register: bool, stmt: &Stmt, index: &SymbolIndex, names: &HashMap<DefId, String>) {
+        let mut sources = vec![];
+        match render(value, index, names, &mut sources) {
+            Some(value) => self.connections.push(Connection { target, value, sources, register }),
+            None => self.skip(stmt),
+        }
+    }
+    fn skip(&mut self, stmt: &Stmt) {
+        self.skipped.extend(stmt.span().map(|at| at.range.0.0 + 1));
+    }
+    /// a verilog module, with a clock and a reset when it has registers
+    pub fn to_verilog(&self) -> String {
+        let registers = self.nets.iter().any(|n| matches!(n.kind, NetKind::Register(_)));
+        let mut ports = vec![];
+        if registers {
+            ports.push("  input clk".to_owned());
+            ports.push("  input reset".to_owned());
+        }
+        for net in &self.nets {
+            if let NetKind::Port(direction) = net.kind {
+                let direction = match direction {
+                    Direction::In => "input",
+                    Direction::Out => "output",
+                };
+                ports.push(format!("  {direction} {}{}", range(net.width), net.name));
+            }
+        }
+        let mut out = format!("module {} (\n{}\n);\n", self.name, ports.join(",\n"));
+        for net in &self.nets {
+            match net.kind {
+                NetKind::Register(_) => writeln!(out, "  reg {}{};", range(net.width), net.name).unwrap(),
+                NetKind::Wire => writeln!(out, "  wire {}{};", range(net.width), net.name).unwrap(),
+                NetKind::Port(_) => {}
+            }
+        }
+        for c in &self.connections {
+            if !c.register {
+                writeln!(out, "  assign {} = {};", c.target, c.value).unwrap();
+                continue;
+            }
+            let init = self.nets.iter().find_map(|n| match &n.kind {
+                NetKind::Register(Some(init)) if n.name == c.target => Some(init),
+                _ => None,
+            });
+            match init {
+                Some(init) => writeln!(
+                    out,
+                    "  always @(posedge clk) begin\n    if (reset) {0} <= {init};\n    else {0} <= {1};\n  end",
+                    c.target, c.value,
+                ).unwrap(),
+                None => writeln!(out, "  always @(posedge clk) {} <= {};", c.target, c.value).unwrap(),
+            }
+        }
+        for line in &self.skipped {
+            writeln!(out, "  // line {line} is not translated").unwrap();
+        }
+        out.push_str("endmodule\n");
+        out
+    }
+    /// a graphviz graph of the nets, an edge for each net a connection reads
+    pub fn to_dot(&self) -> String {
+        let mut out = format!("digraph {} {{\n  rankdir=LR;\n", self.name);
+        for net in &self.nets {
+            let shape = match net.kind {
+                NetKind::Port(Direction::In) => "rarrow",
+                NetKind::Port(Direction::Out) => "larrow",
+                NetKind::Register(_) => "box",
+                NetKind::Wire => "ellipse",
+            };
+            let width = net.width.map(|w| format!("\\n{w} bits")).unwrap_or_default();
+            writeln!(out, "  {0} [shape={shape}, label=\"{0}{width}\"];", net.name).unwrap();
+        }
+        for c in &self.connections {
+            for source in &c.sources {
+                writeln!(out, "  {source} -> {};", c.target).unwrap();
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// the calls making a signal, they do not drive it
+fn is_constructor(name: &str) -> bool {
+    ["in", "out", "master", "slave", "Reg", "RegInit", "UInt", "SInt", "Bits", "Bool"].contains(&name)
+}
+
+/// `[7:0] ` for 8 bits
+fn range(width: Option<i64>) -> String {
+    match width {
+        Some(w) if w > 1 => format!("[{}:0] ", w - 1),
+        _ => String::new(),
+    }
+}
+
+/// an expression in verilog, `sources` gets the nets it reads. `None` when it
+/// is not hardware this knows
+fn render(e: &Expression, index: &SymbolIndex, names: &HashMap<DefId, String>, sources: &mut Vec<String>) -> Option<String> {
+    let mut r = |e| render(e, index, names, sources);
+    let binary = |a: String, op: &str, b: String| format!("({a} {op} {b})");
+    Some(match e {
+        Expression::Int(x) => x.data.to_string(),
+        Expression::Bool(x) => if x.data { "1'b1" } else { "1'b0" }.to_owned(),
+        Expression::Name(x) | Expression::Field(_, x) => {
+            let name = names.get(&index.ref_at(x)?)?.clone();
+            sources.push(name.clone());
+            name
+        }
+        Expression::Add(a, b) => binary(r(a)?, "+", r(b)?),
+        Expression::Sub(a, b) => binary(r(a)?, "-", r(b)?),
+        Expression::Mul(a, b) => binary(r(a)?, "*", r(b)?),
+        Expression::Eq(a, b) => binary(r(a)?, "==", r(b)?),
+        Expression::Neq(a, b) => binary(r(a)?, "!=", r(b)?),
+        Expression::Lt(a, b) => binary(r(a)?, "<", r(b)?),
+        Expression::Le(a, b) => binary(r(a)?, "<=", r(b)?),
+        Expression::Gt(a, b) => binary(r(a)?, ">", r(b)?),
+        Expression::Ge(a, b) => binary(r(a)?, ">=", r(b)?),
+        Expression::And(a, b) => binary(r(a)?, "&&", r(b)?),
+        Expression::Or(a, b) => binary(r(a)?, "||", r(b)?),
+        Expression::Not(a) => format!("~{}", r(a)?),
+        Expression::Neg(a) => format!("-{}", r(a)?),
+        Expression::Call(f, args) => match (f.data.as_str(), &args[..]) {
+            ("U" | "S" | "B", [a] | [a, _]) => r(a)?,
+            ("True", []) => "1'b1".to_owned(),
+            ("False", []) => "1'b0".to_owned(),
+            _ => return None,
+        },
+        // the width is the one of what it is assigned to
+        Expression::MethodCall(a, m, args) if args.is_empty() || m.data == "resize" => match m.data.as_str() {
+            "resize" | "asUInt" | "asSInt" | "asBits" => r(a)?,
+            _ => return None,
+        },
+        Expression::MethodCall(a, op, args) if args.len() == 1 => {
+            let op = match op.data.as_str() {
+                "+^" => "+",
+                "-^" => "-",
+                "===" => "==",
+                "=/=" => "!=",
+                "##" => return Some(format!("{{{}, {}}}", r(a)?, r(&args[0])?)),
+                op @ ("&" | "|" | "^" | "<<" | ">>") => op,
+                _ => return None,
+            };
+            binary(r(a)?, op, r(&args[0])?)
+        }
+        _ => return None,
+    })
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use typort_parser::ast::{self, ReplItem, TopItem};
+
+use crate::hir;
+use crate::mir::hir_to_mir;
+use crate::ty::Type;
+use crate::value::{HeapValue, Instance, Value};
+use crate::vm::Interpreter;
+use crate::built_in::display;
+use crate::{FileId, RunError, Span, Symbol};
+
+/// the state of `typort repl`. each input becomes an object extending the one
+/// of the previous input, its body runs on the same instance of the same
+/// `vm::Interpreter`, so its `val`s are fields and its `def`s are methods
+pub struct Repl {
+    /// the classes typed or loaded so far
+    items: Vec<TopItem>,
+    /// the objects of the inputs accepted so far
+    inputs: Vec<TopItem>,
+    vm: Interpreter,
+    this: Value,
+    /// the number of `resN` given so far
+    results: usize,
+    /// every input and loaded file has a file id of its own
+    files: u32,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// what an input compiled to, it is only kept if it runs
+struct Compiled {
+    classes: Vec<hir::Class>,
+    items: Vec<TopItem>,
+    input: Option<TopItem>,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        let mut vm = Interpreter::new(vec![]);
+        let this = Value::HeapId(vm.heap.alloc(HeapValue::Class(Instance {
+            class: Symbol::intern("repl"),
+            fields: Default::default(),
+        })));
+        Self {
+            items: vec![],
+            inputs: vec![],
+            vm,
+            this,
+            results: 0,
+            files: 0,
+        }
+    }
+    /// if `text` needs more lines, e.g. a block which is not closed yet
+    pub fn is_incomplete(text: &str) -> bool {
+        let mut depth = 0i64;
+        let mut chars = text.chars();
+        let mut in_string = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if in_string => {
+                    chars.next();
+                }
+                '"' => in_string = !in_string,
+                '{' | '(' | '[' if !in_string => depth += 1,
+                '}' | ')' | ']' if !in_string => depth -= 1,
+                _ => {}
+            }
+        }
+        depth > 0 || in_string
+    }
+    /// run one input, a command or definitions and statements, and give back
+    /// the lines to show
+    pub fn eval(&mut self, input: &str) -> Result<Vec<String>, String> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(vec![]);
+        }
+        if let Some(command) = input.strip_prefix(':') {
+            let (command, arg) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+            return match command {
+                "load" => self.load(Path::new(arg.trim())),
+                "type" => self.type_of(arg),
+                "help" => Ok(vec![
+                    ":load <file>  add the classes and objects of a file".to_owned(),
+                    ":type <expr>  show the type of an expression".to_owned(),
+                    ":quit         leave the repl".to_owned(),
+                ]),
+                _ => Err(format!("error: unknown command `:{command}`, see `:help`")),
+            };
+        }
+        let (tops, mut stmts) = self.parse(input)?;
+        let result = match stmts.pop() {
+            Some(ast::Stmt::Expr(e)) => {
+                let name = Symbol::intern(&format!("res{}", self.results));
+                let at = e.span().unwrap_or_else(|| self.span(()));
+                stmts.push(ast::Stmt::Val(at.with_data(name), None, e));
+                Some(name)
+            }
+            Some(s) => {
+                stmts.push(s);
+                None
+            }
+            None => None,
+        };
+        let compiled = self.compile(tops.clone(), stmts)?;
+        let hir_block = compiled.classes.iter()
+            .find(|c| compiled.input.as_ref().is_some_and(|i| name_of(i) == Some(c.name.data)))
+            .map(|c| c.block.clone())
+            .unwrap_or_default();
+        self.run(compiled)?;
+
+        let mut lines = vec![];
+        for item in tops.iter() {
+            let kind = match item {
+                TopItem::Class(c) if c.kind == ast::ClassKind::Trait => "trait",
+                TopItem::Class(_) => "class",
+                TopItem::Object(_) => "object",
+                TopItem::Import(_) => continue,
+            };
+            lines.push(format!("{kind} {}", name_of(item).unwrap()));
+        }
+        for stmt in hir_block.iter() {
+            match stmt {
+                hir::Stmt::Let(name, ty, e) => {
+                    let value = self.field(name.data);
+                    if Some(name.data) == result {
+                        if matches!(value, Value::Unit) {
+                            continue;
+                        }
+                        self.results += 1;
+                    }
+                    let ty = self.type_name(ty.as_ref(), e, value);
+                    let keyword = if Some(name.data) == result { "" } else { "val " };
+                    lines.push(format!("{keyword}{}: {ty} = {}", name.data, display(&self.vm.heap, value)));
+                }
+                hir::Stmt::Func { name, .. } => lines.push(format!("def {}", name.data)),
+                _ => {}
+            }
+        }
+        Ok(lines)
+    }
+    /// add the classes and objects of a file, objects extending `App` are not run
+    fn load(&mut self, path: &Path) -> Result<Vec<String>, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| RunError::Io(path.to_owned(), e.kind()).to_string())?;
+        let file = self.next_file();
+        let (items, errors) = typort_parser::parse_file(&text, file);
+        let items = match items {
+            Some(items) if errors.is_empty() => items,
+            _ => return Err(RunError::Parse(errors).to_string()),
+        };
+        let count = items.len();
+        let compiled = self.compile(items, vec![])?;
+        self.run(compiled)?;
+        Ok(vec![format!("loaded {count} definitions from {}", path.display())])
+    }
+    /// the type of an expression, it is only run when the type is not known
+    /// from the expression itself, nothing it defines is kept
+    fn type_of(&mut self, input: &str) -> Result<Vec<String>, String> {
+        let (tops, stmts) = self.parse(input)?;
+        if !tops.is_empty() || !matches!(&stmts[..], [ast::Stmt::Expr(_)]) {
+            return Err("error: `:type` takes an expression".to_owned());
+        }
+        let compiled = self.compile(vec![], stmts)?;
+        let input = compiled.input.as_ref().and_then(name_of).unwrap();
+        let class = compiled.classes.iter().find(|c| c.name.data == input).unwrap();
+        let Some(hir::Stmt::Expr(e)) = class.block.last() else { unreachable!() };
+        if let Some(ty) = Type::of(e) {
+            return Ok(vec![ty.to_string()]);
+        }
+        let e = e.clone();
+        let old_classes = std::mem::take(&mut self.vm.classes);
+        self.vm.classes = to_map(hir_to_mir(compiled.classes));
+        let ret = self.vm.extend(self.this, input);
+        self.vm.classes = old_classes;
+        self.set_class(self.inputs.last().and_then(name_of));
+        let value = ret.map_err(|e| e.to_string())?;
+        Ok(vec![self.type_name(None, &e, value)])
+    }
+    fn parse(&mut self, input: &str) -> Result<(Vec<TopItem>, Vec<ast::Stmt>), String> {
+        let file = self.next_file();
+        let (items, errors) = typort_parser::parse_repl(input, file);
+        let items = match items {
+            Some(items) if errors.is_empty() => items,
+            _ => return Err(RunError::Parse(errors).to_string()),
+        };
+        let mut tops = vec![];
+        let mut stmts = vec![];
+        for item in items {
+            match item {
+                ReplItem::Top(top) => tops.push(top),
+                ReplItem::Stmt(stmt) => stmts.push(stmt),
+            }
+        }
+        Ok((tops, stmts))
+    }
+    /// check `tops`, which replace the classes of the same name, together
+    /// with an object running `stmts` if there are any
+    fn compile(&mut self, tops: Vec<TopItem>, stmts: Vec<ast::Stmt>) -> Result<Compiled, String> {
+        let mut items = self.items.clone();
+        for top in tops {
+            items.retain(|i| name_of(i).is_none() || name_of(i) != name_of(&top));
+            items.push(top);
+        }
+        let input = (!stmts.is_empty()).then(|| {
+            let name = Symbol::intern(&format!("repl{}", self.files));
+            TopItem::Object(ast::Object {
+                name: self.span(name),
+                extends: self.inputs.last().and_then(name_of).map(|n| self.span(n)),
+                with: vec![],
+                block: ast::Block(stmts),
+            })
+        });
+        let all = items.iter().chain(&self.inputs).chain(&input).cloned().collect();
+        let (classes, diag) = hir::parse_to_hir(all);
+        if !diag.is_empty() {
+            return Err(RunError::Check(diag).to_string());
+        }
+        Ok(Compiled { classes, items, input })
+    }
+    /// run the object of an input on the instance, the input is kept if it
+    /// does not fail
+    fn run(&mut self, compiled: Compiled) -> Result<(), String> {
+        let classes = to_map(hir_to_mir(compiled.classes));
+        let old_classes = std::mem::replace(&mut self.vm.classes, classes);
+        if let Some(input) = &compiled.input {
+            if let Err(e) = self.vm.extend(self.this, name_of(input).unwrap()) {
+                self.vm.classes = old_classes;
+                self.set_class(self.inputs.last().and_then(name_of));
+                return Err(e.to_string());
+            }
+        }
+        self.items = compiled.items;
+        self.inputs.extend(compiled.input);
+        Ok(())
+    }
+    /// the declared type of a value, or the one known from its expression, or
+    /// the one of the value itself
+    fn type_name(&self, declared: Option<&ast::TypeExpr>, e: &hir::Expression, value: Value) -> String {
+        match (declared, Type::of(e)) {
+            (Some(ty), _) => ty.to_string(),
+            (None, Some(ty)) => ty.to_string(),
+            (None, None) => Type::of_value(&self.vm.heap, value).to_string(),
+        }
+    }
+    fn field(&self, name: Symbol) -> Value {
+        match self.this {
+            Value::HeapId(id) => match self.vm.heap.get(id) {
+                Some(HeapValue::Class(instance)) => instance.fields.get(&name).copied().unwrap_or(Value::Unit),
+                _ => Value::Unit,
+            },
+            _ => Value::Unit,
+        }
+    }
+    /// undo `Interpreter::extend` of an input that is not kept
+    fn set_class(&mut self, class: Option<Symbol>) {
+        if let Value::HeapId(id) = self.this {
+            if let Some(HeapValue::Class(instance)) = self.vm.heap.get_mut(id) {
+                instance.class = class.unwrap_or(Symbol::intern("repl"));
+            }
+        }
+    }
+    fn next_file(&mut self) -> FileId {
+        self.files += 1;
+        FileId(self.files)
+    }
+    fn span<T>(&self, data: T) -> Span<T> {
+        Span {
+            data,
+            file: FileId(self.files),
+            offset: 0,
+            len: 0,
+            range: ((0, 0), (0, 0)),
+        }
+    }
+}
+
+fn name_of(item: &TopItem) -> Option<Symbol> {
+    match item {
+        TopItem::Class(c) => Some(c.name.data),
+        TopItem::Object(o) => Some(o.name.data),
+        TopItem::Import(_) => None,
+    }
+}
+
+fn to_map(classes: Vec<crate::mir::Class>) -> HashMap<Symbol, crate::mir::Class> {
+    classes.into_iter().map(|c| (c.name.data, c)).collect()
+}
+
+/// read inputs from stdin until it ends or `:quit`
+pub fn main_repl() {
+    let mut repl = Repl::new();
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    println!("typort repl, `:help` for the commands");
+    loop {
+        let mut input = String::new();
+        loop {
+            print!("{}", if input.is_empty() { "typort> " } else { "      | " });
+            std::io::stdout().flush().ok();
+            let Some(Ok(line)) = lines.next() else { return };
+            input.push_str(&line);
+            input.push('\n');
+            if !Repl::is_incomplete(&input) {
+                break;
+            }
+        }
+        if input.trim() == ":quit" {
+            return;
+        }
+        match repl.eval(&input) {
+            Ok(lines) => lines.iter().for_each(|l| println!("{l}")),
+            Err(e) => println!("{e}"),
+        }
+    }
+}
+
@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+
+use crate::hir;
+use crate::machine::Machine;
+use crate::mir::hir_to_mir;
+use crate::native::Natives;
+use crate::built_in::display;
+use crate::diagnostic::{Diagnostic, Sources};
+use crate::value::{Heap, HeapValue, Value};
+use crate::{bytecode, find_top, FileId, RunError, Symbol};
+
+/// a source file of a `Session`, the index is its `FileId`
+struct Source {
+    path: PathBuf,
+    text: std::io::Result<String>,
+}
+
+/// the files of a design and the natives they may call, elaborating never
+/// prints, everything is in the result
+#[derive(Default)]
+pub struct Session {
+    sources: Vec<Source>,
+    natives: Natives,
+}
+
+/// the instance of a top class made by `Session::elaborate`
+#[derive(Debug)]
+pub struct Design {
+    pub top: Symbol,
+    pub instance: Value,
+    /// the value of the body of the top class
+    pub value: Value,
+    /// every value the design refers to
+    pub heap: Heap,
+    /// what `print` wrote
+    pub output: String,
+}
+
+impl Design {
+    /// a `val` or a `var` of the top class
+    pub fn field(&self, name: &str) -> Option<Value> {
+        match self.heap.get(self.instance_id()?) {
+            Some(HeapValue::Class(instance)) => instance.fields.get(&Symbol::intern(name)).copied(),
+            _ => None,
+        }
+    }
+    /// the fields of the top class, sorted by name
+    pub fn fields(&self) -> Vec<(Symbol, Value)> {
+        let Some(Some(HeapValue::Class(instance))) = self.instance_id().map(|id| self.heap.get(id)) else {
+            return vec![];
+        };
+        let mut fields: Vec<_> = instance.fields.iter().map(|(k, v)| (*k, *v)).collect();
+        fields.sort_by_key(|(name, _)| name.as_str());
+        fields
+    }
+    /// show a value the way `print` does
+    pub fn display(&self, value: Value) -> String {
+        display(&self.heap, value)
+    }
+    fn instance_id(&self) -> Option<usize> {
+        match self.instance {
+            Value::HeapId(id) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_natives(natives: Natives) -> Self {
+        Self {
+            sources: vec![],
+            natives,
+        }
+    }
+    /// a file that can not be read is reported by `elaborate`
+    pub fn add_source(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        let path = path.as_ref().to_owned();
+        let text = std::fs::read_to_string(&path);
+        self.sources.push(Source { path, text });
+        self
+    }
+    /// a source that is not read from a file, `path` is only used to name it
+    pub fn add_text(&mut self, path: impl Into<PathBuf>, text: impl Into<String>) -> &mut Self {
+        self.sources.push(Source {
+            path: path.into(),
+            text: Ok(text.into()),
+        });
+        self
+    }
+    pub fn path(&self, file: FileId) -> Option<&Path> {
+        self.sources.get(file.0 as usize).map(|s| s.path.as_path())
+    }
+    pub fn text(&self, file: FileId) -> Option<&str> {
+        self.sources.get(file.0 as usize)?.text.as_deref().ok()
+    }
+    /// parse and check every source together, then create an instance of
+    /// `top` on the vm
+    pub fn elaborate(&self, top: &str) -> Result<Design, RunError> {
+        let mut items = vec![];
+        let mut errors = vec![];
+        for (i, source) in self.sources.iter().enumerate() {
+            let text = match &source.text {
+                Ok(text) => text,
+                Err(e) => return Err(RunError::Io(source.path.clone(), e.kind())),
+            };
+            let (ast, e) = typort_parser::parse_file(text, FileId(i as _));
+            errors.extend(e);
+            items.extend(ast.unwrap_or_default());
+        }
+        if !errors.is_empty() {
+            return Err(RunError::Parse(errors));
+        }
+        let (hir, mut diag) = hir::parse_to_hir(items);
+        diag.extend(self.natives.check(&hir));
+        if diag.iter().any(Diagnostic::is_error) {
+            return Err(RunError::Check(diag));
+        }
+        let mir = hir_to_mir(hir);
+        let top = find_top(&mir, Some(top.to_owned()))?.name.data;
+        let mut vm = Machine::with_natives(bytecode::compile(&mir), &self.natives);
+        vm.output = Some(String::new());
+        let (instance, value) = vm.instantiate(top).map_err(RunError::Runtime)?;
+        Ok(Design {
+            top,
+            instance,
+            value,
+            heap: std::mem::take(&mut vm.heap),
+            output: vm.output.take().unwrap_or_default(),
+        })
+    }
+}
+
+impl Sources for Session {
+    fn path(&self, file: FileId) -> Option<&Path> {
+        Session::path(self, file)
+    }
+    fn text(&self, file: FileId) -> Option<&str> {
+        Session::text(self, file)
+    }
+}
@@ -1,5 +1,12 @@
 
+use crate::hir::Expression;
+use crate::TypeExpr;
+use crate::value::{Heap, HeapValue, Value};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Type {
+    /// every value has this type
+    Any,
     Unit,
     Bool,
     I64,
@@ -14,6 +21,7 @@ pub enum Type {
 impl std::fmt::Display for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Type::Any => write!(f, "Any"),
             Type::Unit => write!(f, "Unit"),
             Type::Bool => write!(f, "Bool"),
             Type::I64 => write!(f, "Int"),
@@ -34,3 +42,102 @@ impl std::fmt::Display for Type {
         }
     }
 }
+
+impl Type {
+    /// the type of `expr` if it is known without looking at any name
+    pub fn of(expr: &Expression) -> Option<Type> {
+        match expr {
+            Expression::Int(_)
+            | Expression::Add(_, _)
+            | Expression::Sub(_, _)
+            | Expression::Mul(_, _)
+            | Expression::Div(_, _)
+            | Expression::Mod(_, _)
+            | Expression::Neg(_) => Some(Type::I64),
+            Expression::Bool(_)
+            | Expression::Eq(_, _)
+            | Expression::Neq(_, _)
+            | Expression::Lt(_, _)
+            | Expression::Le(_, _)
+            | Expression::Gt(_, _)
+            | Expression::Ge(_, _)
+            | Expression::And(_, _)
+            | Expression::Or(_, _)
+            | Expression::Not(_) => Some(Type::Bool),
+            Expression::String(_) => Some(Type::Own("String".to_owned())),
+            // `s"..."`
+            Expression::MethodCall(a, m, _) if m.data == "format" && matches!(**a, Expression::String(_)) => {
+                Some(Type::Own("String".to_owned()))
+            }
+            Expression::Lambda(params, _) => Some(Type::Func(vec![Type::Any; params.len()], Box::new(Type::Any))),
+            Expression::Tuple(v) => {
+                let v = v.iter().map(|e| Type::of(e).unwrap_or(Type::Any)).collect::<Vec<_>>();
+                match &v[..] {
+                    [a, b] => Some(Type::Tuple2(Box::new(a.clone()), Box::new(b.clone()))),
+                    [a, b, c] => Some(Type::Tuple3(Box::new(a.clone()), Box::new(b.clone()), Box::new(c.clone()))),
+                    [a, b, c, d] => Some(Type::Tuple4(
+                        Box::new(a.clone()),
+                        Box::new(b.clone()),
+                        Box::new(c.clone()),
+                        Box::new(d.clone()),
+                    )),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+    /// the type a declared type is checked as, the other hardware types and
+    /// the classes are not known and are `Any`
+    pub fn of_type_expr(t: &TypeExpr) -> Type {
+        match t {
+            TypeExpr::Name(n, _) => match n.data.as_str() {
+                "Int" => Type::I64,
+                "Bool" | "Boolean" => Type::Bool,
+                "Unit" => Type::Unit,
+                "Float" | "Double" => Type::F64,
+                "String" => Type::Own("String".to_owned()),
+                _ => Type::Any,
+            },
+            TypeExpr::Tuple(v) => {
+                let v: Vec<_> = v.iter().map(|t| Box::new(Type::of_type_expr(t))).collect();
+                match &v[..] {
+                    [a, b] => Type::Tuple2(a.clone(), b.clone()),
+                    [a, b, c] => Type::Tuple3(a.clone(), b.clone(), c.clone()),
+                    [a, b, c, d] => Type::Tuple4(a.clone(), b.clone(), c.clone(), d.clone()),
+                    _ => Type::Any,
+                }
+            }
+            TypeExpr::Func(i, o) => Type::Func(i.iter().map(Type::of_type_expr).collect(), Box::new(Type::of_type_expr(o))),
+        }
+    }
+    /// the type of a value at runtime, sequences and tuples are all `Seq`
+    pub fn of_value(heap: &Heap, value: Value) -> Type {
+        match value {
+            Value::Int(_) => Type::I64,
+            Value::Bool(_) => Type::Bool,
+            Value::Unit => Type::Unit,
+            Value::Closure(_) => Type::Own("Function".to_owned()),
+            Value::HeapId(id) => match heap.get(id) {
+                Some(HeapValue::String(_)) => Type::Own("String".to_owned()),
+                Some(HeapValue::Vec(_)) => Type::Own("Seq".to_owned()),
+                Some(HeapValue::Map(_)) => Type::Own("Map".to_owned()),
+                Some(HeapValue::Class(instance)) => Type::Own(instance.class.to_string()),
+                None => Type::Any,
+            },
+        }
+    }
+    /// if a value of type `other` can be given where this type is expected
+    pub fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Tuple2(a, b), Type::Tuple2(c, d)) => a.accepts(c) && b.accepts(d),
+            (Type::Tuple3(a, b, c), Type::Tuple3(d, e, f)) => a.accepts(d) && b.accepts(e) && c.accepts(f),
+            (Type::Tuple4(a, b, c, d), Type::Tuple4(e, f, g, h)) => {
+                a.accepts(e) && b.accepts(f) && c.accepts(g) && d.accepts(h)
+            }
+            (Type::Func(a, _), Type::Func(b, _)) => a.len() == b.len(),
+            (a, b) => a == b,
+        }
+    }
+}
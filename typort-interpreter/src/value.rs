@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{Span, Symbol};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    HeapId(usize),
+    /// an index into the closures of the running vm
+    Closure(usize),
+    Unit,
+}
+
+#[derive(Clone, Debug)]
+pub enum HeapValue {
+    Vec(Vec<Value>),
+    String(String),
+    /// the entries in the order their keys were first added
+    Map(Vec<(Value, Value)>),
+    Class(Instance),
+}
+
+/// an object or an instance of a class
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub class: Symbol,
+    pub fields: HashMap<Symbol, Value>,
+}
+
+impl HeapValue {
+    /// the values this one refers to
+    fn children(&self) -> Box<dyn Iterator<Item = &Value> + '_> {
+        match self {
+            HeapValue::Vec(items) => Box::new(items.iter()),
+            HeapValue::String(_) => Box::new(std::iter::empty()),
+            HeapValue::Map(entries) => Box::new(entries.iter().flat_map(|(k, v)| [k, v])),
+            HeapValue::Class(instance) => Box::new(instance.fields.values()),
+        }
+    }
+}
+
+/// the live values needed before the first collection
+const FIRST_GC: usize = 1024;
+
+/// a mark and sweep heap, the id of a freed value is given to a later one
+#[derive(Clone, Debug)]
+pub struct Heap {
+    values: Vec<Option<HeapValue>>,
+    free: Vec<usize>,
+    live: usize,
+    /// `should_collect` once this many values are live
+    next_gc: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            values: vec![],
+            free: vec![],
+            live: 0,
+            next_gc: FIRST_GC,
+        }
+    }
+}
+
+impl Heap {
+    pub fn alloc(&mut self, value: HeapValue) -> usize {
+        self.live += 1;
+        match self.free.pop() {
+            Some(id) => {
+                self.values[id] = Some(value);
+                id
+            }
+            None => {
+                self.values.push(Some(value));
+                self.values.len() - 1
+            }
+        }
+    }
+    pub fn get(&self, id: usize) -> Option<&HeapValue> {
+        self.values.get(id)?.as_ref()
+    }
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut HeapValue> {
+        self.values.get_mut(id)?.as_mut()
+    }
+    /// the number of live values
+    pub fn len(&self) -> usize {
+        self.live
+    }
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+    /// if enough was allocated since the last collection
+    pub fn should_collect(&self) -> bool {
+        self.live >= self.next_gc
+    }
+    /// free every value not reachable from `roots`, returns how many were freed.
+    /// `trace` is given the values that are not on the heap, e.g. closures,
+    /// and adds the values they refer to
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = Value>, mut trace: impl FnMut(Value, &mut Vec<Value>)) -> usize {
+        let mut marked = vec![false; self.values.len()];
+        let mut work: Vec<Value> = roots.into_iter().collect();
+        while let Some(value) = work.pop() {
+            let Value::HeapId(id) = value else {
+                trace(value, &mut work);
+                continue;
+            };
+            if id >= marked.len() || marked[id] {
+                continue;
+            }
+            marked[id] = true;
+            if let Some(value) = &self.values[id] {
+                work.extend(value.children().copied());
+            }
+        }
+        let mut freed = 0;
+        for (id, slot) in self.values.iter_mut().enumerate() {
+            if !marked[id] && slot.is_some() {
+                *slot = None;
+                self.free.push(id);
+                freed += 1;
+            }
+        }
+        self.live -= freed;
+        self.next_gc = FIRST_GC.max(self.live * 2);
+        freed
+    }
+}
+
+/// a captured variable, it refers to the stack while the function owning the
+/// slot is running, and holds the value itself after that
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// one entry of a Typort stack trace
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub func: Symbol,
+    /// the location being evaluated in this function
+    pub at: Span<()>,
+}
+
+/// a runtime failure, the trace starts from the innermost frame
+#[derive(Clone, Debug)]
+pub struct RuntimeError {
+    pub msg: String,
+    pub at: Span<()>,
+    pub trace: Vec<Frame>,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error: {}", self.msg)?;
+        for frame in self.trace.iter() {
+            write!(f, "\n    at {} (line {})", frame.func, frame.at.range.0.0 + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+pub type VmResult<T = Value> = Result<T, RuntimeError>;
@@ -1,196 +1,574 @@
 use std::collections::HashMap;
 
-use crate::{mir::*, built_in::{bi_print, bi_array}};
+use crate::{mir::*, built_in::{built_in, built_in_method, Host}, native::Natives, Span, Symbol};
+use crate::value::{Frame, Heap, HeapValue, Instance, RuntimeError, Upvalue, Value, VmResult};
 
-#[derive(Clone, Copy, Debug)]
-pub enum Value {
-    Int(i64),
-    Bool(bool),
-    HeapId(usize),
-    Unit,
+/// a `def` or a lambda together with the values it captured
+#[derive(Clone, Debug)]
+pub struct Closure {
+    pub name: Symbol,
+    pub params: usize,
+    /// indexes into `Interpreter::upvalues`
+    pub upvalues: Vec<usize>,
+    pub block: Vec<Stmt>,
 }
 
+/// why evaluation left a block before its end
 #[derive(Clone, Debug)]
-pub enum HeapValue {
-    Vec(Vec<Value>),
-    String(String),
-    //Class(Class),
+pub enum Unwind {
+    Error(RuntimeError),
+    Return(Value),
+    Break(Span<()>),
+    Continue(Span<()>),
 }
 
-/*#[derive(Clone, Debug)]
-pub struct Class {
-    values: Vec<Value>,
+impl From<RuntimeError> for Unwind {
+    fn from(value: RuntimeError) -> Self {
+        Unwind::Error(value)
+    }
+}
 
-}*/
+pub type Flow<T = Value> = Result<T, Unwind>;
 
+/// runs MIR by walking it, `machine::Machine` is faster.
+/// kept as the reference the bytecode vm is checked against. its heap, closures
+/// and upvalues are never freed: the values being evaluated are only held on
+/// the Rust stack, so there are no roots to collect from
 pub struct Interpreter {
-    built_in_func: HashMap<String, Box<dyn Fn(&mut Interpreter, Vec<Value>) -> Value>>,
+    natives: Natives,
     stack: Vec<Value>,
-    pub heap: HashMap<usize, HeapValue>,
-    pub classes: HashMap<String, Class>,
-    funcs: HashMap<String, Func>,
+    pub heap: Heap,
+    pub classes: HashMap<Symbol, Class>,
+    pub closures: Vec<Closure>,
+    upvalues: Vec<Upvalue>,
+    /// upvalues still pointing to the stack
+    open_upvalues: Vec<usize>,
     func_stack_offset: usize,
+    /// the upvalues of the running closure
+    func_upvalues: Vec<usize>,
+    /// the functions being called and where they are called from
+    call_stack: Vec<(Symbol, Span<()>)>,
 }
 
 impl Interpreter {
     pub fn new(classes: Vec<Class>) -> Self {
+        Self::with_natives(classes, &Natives::default())
+    }
+    pub fn with_natives(classes: Vec<Class>, natives: &Natives) -> Self {
         let mut funcs_hash = HashMap::new();
         for f in classes {
-            funcs_hash.insert(f.name.data.to_owned(), f);
+            funcs_hash.insert(f.name.data, f);
         }
-        let mut built_in_func: HashMap<String, Box<dyn Fn(&mut Interpreter, Vec<Value>) -> Value>> = HashMap::new();
-        built_in_func.insert("print".to_owned(), Box::new(|vm, args| bi_print(vm, args)));
-        built_in_func.insert("Array".to_owned(), Box::new(|vm, args| bi_array(vm, args)));
 
-        Self {                                                                                                                                                                 
-            built_in_func,
+        Self {
+            natives: natives.clone(),
             stack: vec![],
             heap: Default::default(),
             classes: funcs_hash,
-            funcs: Default::default(),
+            closures: vec![],
+            upvalues: vec![],
+            open_upvalues: vec![],
             func_stack_offset: 0,
+            func_upvalues: vec![],
+            call_stack: vec![],
         }
     }
-    /*pub fn run(&'a mut self) -> Value {
-        //let main = self.classes.get("main").unwrap().clone();
-        //self.translate_block(&main.block)
-        self.translate_block(&self.classes.get("main").unwrap().block)
-    }*/
-    pub fn translate_block(&mut self, stmts: &[Stmt]) -> Value {
+    /// create an instance of a class, the value of its body is returned, it
+    /// is the outermost frame of a trace
+    pub fn run(&mut self, class: &Class) -> VmResult {
+        match self.construct(class.name.data, class.name.with_data(()), vec![]) {
+            Ok((_, value)) => Ok(value),
+            Err(Unwind::Error(e)) => Err(e),
+            Err(_) => unreachable!("left by leave_frame"),
+        }
+    }
+    /// run only the body of `class` on `this`, which becomes an instance of
+    /// `class`. the repl runs each input like this on the same instance
+    pub fn extend(&mut self, this: Value, class: Symbol) -> VmResult {
+        if let Value::HeapId(id) = this {
+            if let Some(HeapValue::Class(instance)) = self.heap.get_mut(id) {
+                instance.class = class;
+            }
+        }
+        let ctor = &self.classes[&class];
+        let (name, block) = (ctor.name.clone(), ctor.block.clone());
+        match self.call(class, name.with_data(()), vec![this], vec![], &block) {
+            Ok(value) => Ok(value),
+            Err(Unwind::Error(e)) => Err(e),
+            Err(_) => unreachable!("left by leave_frame"),
+        }
+    }
+    /// run the constructors of `class` and the classes it inherits from on a
+    /// new instance, returns it and the value of the body of `class`
+    fn construct(&mut self, class: Symbol, call_site: Span<()>, args: Vec<Value>) -> Flow<(Value, Value)> {
+        let this = Value::HeapId(self.heap.alloc(HeapValue::Class(Instance {
+            class,
+            fields: Default::default(),
+        })));
+        let linearization = self.classes[&class].linearization.clone();
         let mut ret = Value::Unit;
-        for s in stmts {
-            ret = self.translate_stmt(s);
+        for c in linearization.iter().rev() {
+            let ctor = &self.classes[c];
+            let (params, block) = (ctor.args.len(), ctor.block.clone());
+            let args = if *c == class {
+                if args.len() != params {
+                    return Err(self.error(
+                        format!("`{class}` takes {params} arguments but {} were given", args.len()),
+                        Some(call_site),
+                    ));
+                }
+                std::iter::once(this).chain(args.iter().copied()).collect()
+            } else {
+                // the arguments of an inherited class are not given yet
+                std::iter::once(this).chain(std::iter::repeat_n(Value::Unit, params)).collect()
+            };
+            ret = self.call(*c, call_site.clone(), args, vec![], &block)?;
+        }
+        Ok((this, ret))
+    }
+    /// the instance `value` refers to
+    fn instance(&self, value: Value) -> Option<&Instance> {
+        match value {
+            Value::HeapId(id) => match self.heap.get(id) {
+                Some(HeapValue::Class(instance)) => Some(instance),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    fn call_method(&mut self, this: Value, name: &crate::Ident, call_site: Span<()>, args: Vec<Value>) -> Flow {
+        let method = self.instance(this)
+            .and_then(|i| self.classes[&i.class].method(&self.classes, name.data))
+            .map(|f| (f.params.len() - 1, f.block.clone()));
+        let Some((params, block)) = method else {
+            if let Some(f) = built_in_method(name.data.as_str()) {
+                let args = std::iter::once(this).chain(args).collect();
+                return f(self, args).map_err(|msg| self.error(msg, Some(call_site)));
+            }
+            return Err(self.error(format!("{this:?} has no member `{}`", name.data), Some(name.with_data(()))));
+        };
+        if args.len() != params {
+            return Err(self.error(
+                format!("`{}` takes {params} arguments but {} were given", name.data, args.len()),
+                Some(call_site),
+            ));
+        }
+        let args = std::iter::once(this).chain(args).collect();
+        self.call(name.data, call_site, args, vec![], &block)
+    }
+    /// stop a `return` at the function it leaves, `break` and `continue` can
+    /// not go further than that
+    fn leave_frame(&self, ret: Flow) -> Flow {
+        match ret {
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Break(at)) => Err(self.error("break outside of a loop", Some(at))),
+            Err(Unwind::Continue(at)) => Err(self.error("continue outside of a loop", Some(at))),
+            ret => ret,
         }
+    }
+    /// drop the stack above `len`, upvalues pointing there keep their values
+    fn truncate(&mut self, len: usize) {
+        let (upvalues, stack) = (&mut self.upvalues, &self.stack);
+        self.open_upvalues.retain(|&id| match upvalues[id] {
+            Upvalue::Open(idx) if idx >= len => {
+                upvalues[id] = Upvalue::Closed(stack[idx]);
+                false
+            }
+            _ => true,
+        });
+        self.stack.truncate(len);
+    }
+    /// run a nested block, the values defined in it are dropped at the end
+    fn scoped_block(&mut self, stmts: &[Stmt]) -> Flow {
+        let len = self.stack.len();
+        let ret = self.translate_block(stmts);
+        self.truncate(len);
         ret
     }
-    pub fn translate_stmt(&mut self, stmt: &Stmt) -> Value {
+    fn capture(&mut self, capture: &Capture) -> usize {
+        match capture {
+            Capture::Local(slot) => {
+                let idx = self.func_stack_offset + slot;
+                let open = self.open_upvalues.iter()
+                    .find(|&&id| matches!(self.upvalues[id], Upvalue::Open(x) if x == idx));
+                if let Some(id) = open {
+                    return *id;
+                }
+                self.upvalues.push(Upvalue::Open(idx));
+                self.open_upvalues.push(self.upvalues.len() - 1);
+                self.upvalues.len() - 1
+            }
+            Capture::Upvalue(idx) => self.func_upvalues[*idx],
+        }
+    }
+    fn make_closure(&mut self, name: Symbol, params: usize, captures: &[Capture], block: &[Stmt]) -> usize {
+        let upvalues = captures.iter().map(|c| self.capture(c)).collect();
+        self.closures.push(Closure {
+            name,
+            params,
+            upvalues,
+            block: block.to_vec(),
+        });
+        self.closures.len() - 1
+    }
+    fn read(&self, var: &Span<Var>) -> Flow {
+        let value = match var.data {
+            Var::Local(slot) => self.stack.get(self.func_stack_offset + slot).copied(),
+            Var::Upvalue(idx) => match self.upvalues[self.func_upvalues[idx]] {
+                Upvalue::Open(idx) => self.stack.get(idx).copied(),
+                Upvalue::Closed(value) => Some(value),
+            },
+            Var::Global(name) => return Err(self.error(format!("unknown value `{name}`"), Some(var.with_data(())))),
+        };
+        value.ok_or_else(|| self.error(format!("invalid variable {:?}", var.data), Some(var.with_data(()))))
+    }
+    fn write(&mut self, var: &Span<Var>, value: Value) -> Flow<()> {
+        let slot = match var.data {
+            Var::Local(slot) => self.stack.get_mut(self.func_stack_offset + slot),
+            Var::Upvalue(idx) => match &mut self.upvalues[self.func_upvalues[idx]] {
+                Upvalue::Open(idx) => self.stack.get_mut(*idx),
+                Upvalue::Closed(value) => Some(value),
+            },
+            Var::Global(name) => return Err(self.error(format!("can not assign to `{name}`"), Some(var.with_data(())))),
+        };
+        match slot {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(self.error(format!("invalid variable {:?}", var.data), Some(var.with_data(())))),
+        }
+    }
+    /// run `block` in a new frame with `args` as the first slots
+    fn call(
+        &mut self,
+        name: Symbol,
+        call_site: Span<()>,
+        args: Vec<Value>,
+        upvalues: Vec<usize>,
+        block: &[Stmt],
+    ) -> Flow {
+        let old_offset = self.func_stack_offset;
+        let old_upvalues = std::mem::replace(&mut self.func_upvalues, upvalues);
+        let next_offset = self.stack.len();
+        self.stack.extend(args);
+        self.func_stack_offset = next_offset;
+        self.call_stack.push((name, call_site));
+        let ret = self.translate_block(block);
+        let ret = self.leave_frame(ret);
+        self.call_stack.pop();
+        self.truncate(next_offset);
+        self.func_stack_offset = old_offset;
+        self.func_upvalues = old_upvalues;
+        ret
+    }
+    fn call_closure(&mut self, id: usize, call_site: Span<()>, args: Vec<Value>) -> Flow {
+        let closure = self.closures[id].clone();
+        if args.len() != closure.params {
+            return Err(self.error(
+                format!("`{}` takes {} arguments but {} were given", closure.name, closure.params, args.len()),
+                Some(call_site),
+            ));
+        }
+        self.call(closure.name, call_site, args, closure.upvalues, &closure.block)
+    }
+    fn translate_args(&mut self, args: &[Expression]) -> Flow<Vec<Value>> {
+        let mut values = vec![];
+        for arg in args {
+            values.push(self.translate_expr(arg)?);
+        }
+        Ok(values)
+    }
+    fn error(&self, msg: impl Into<String>, at: Option<Span<()>>) -> Unwind {
+        let at = at.unwrap_or_default();
+        let mut trace = vec![];
+        let mut pos = at.clone();
+        for (func, call_site) in self.call_stack.iter().rev() {
+            trace.push(Frame { func: *func, at: pos });
+            pos = call_site.clone();
+        }
+        Unwind::Error(RuntimeError { msg: msg.into(), at, trace })
+    }
+    pub fn translate_block(&mut self, stmts: &[Stmt]) -> Flow {
+        let mut ret = Value::Unit;
+        for s in stmts {
+            ret = self.translate_stmt(s)?;
+        }
+        Ok(ret)
+    }
+    pub fn translate_stmt(&mut self, stmt: &Stmt) -> Flow {
         match stmt {
             Stmt::Expr(e) => self.translate_expr(e),
             Stmt::Let(_, e) => {
-                let value = self.translate_expr(e);
+                let value = self.translate_expr(e)?;
                 self.stack.push(value);
-                Value::Unit
+                Ok(Value::Unit)
             },
             Stmt::Assign(name, e) => {
-                *self.stack.get_mut(self.func_stack_offset + name.data).unwrap() = self.translate_expr(e);
-                Value::Unit
+                let value = self.translate_expr(e)?;
+                self.write(name, value)?;
+                Ok(Value::Unit)
             },
-            Stmt::Return(e) => {
-                //TODO:
-                self.translate_expr(e)
+            Stmt::SetField(obj, name, e) => {
+                let obj = self.translate_expr(obj)?;
+                let value = self.translate_expr(e)?;
+                if let Value::HeapId(id) = obj {
+                    if let Some(HeapValue::Class(instance)) = self.heap.get_mut(id) {
+                        instance.fields.insert(name.data, value);
+                        return Ok(Value::Unit);
+                    }
+                }
+                Err(self.error(format!("{obj:?} has no field `{}`", name.data), Some(name.with_data(()))))
             },
-            Stmt::While(cond, block) => {
+            Stmt::Return(e) => Err(Unwind::Return(self.translate_expr(e)?)),
+            Stmt::Break(at) => Err(Unwind::Break(at.clone())),
+            Stmt::Continue(at) => Err(Unwind::Continue(at.clone())),
+            Stmt::While(cond, block, step) => {
                 let mut ret = Value::Unit;
-                while let Value::Bool(true) = self.translate_expr(cond) {
-                    ret = self.translate_block(block);
+                while let Value::Bool(true) = self.translate_expr(cond)? {
+                    match self.scoped_block(block) {
+                        Ok(value) => ret = value,
+                        Err(Unwind::Continue(_)) => {}
+                        Err(Unwind::Break(_)) => break,
+                        Err(e) => return Err(e),
+                    }
+                    self.scoped_block(step)?;
                 }
-                ret
+                Ok(ret)
             },
             Stmt::Block(b) => {
-                self.translate_block(b)
-            },
-            Stmt::Func { name, params, return_type, block } => {
-                let ret = Func {
-                    name: name.clone(),
-                    params: params.to_vec(),
-                    return_type: return_type.clone(),
-                    block: block.clone(),
-                };
-                self.funcs.insert(name.data.to_owned(), ret);
-                Value::Unit
+                self.scoped_block(b)
+            },
+            Stmt::Func { name, slot, params, captures, return_type: _, block } => {
+                let id = self.make_closure(name.data, params.len(), captures, block);
+                self.write(&name.with_data(Var::Local(*slot)), Value::Closure(id))?;
+                Ok(Value::Unit)
             }
         }
     }
-    pub fn translate_expr(&mut self, expr: &Expression) -> Value {
+    pub fn translate_expr(&mut self, expr: &Expression) -> Flow {
         match expr {
-            Expression::Int(x) => Value::Int(x.data),
-            Expression::String(idx, data) => {
-                self.heap.insert(*idx, HeapValue::String(data.data.to_owned()));
-                Value::HeapId(*idx)
+            Expression::Int(x) => Ok(Value::Int(x.data)),
+            Expression::String(data) => {
+                Ok(Value::HeapId(self.heap.alloc(HeapValue::String(data.data.to_owned()))))
             }
-            Expression::Bool(x) => Value::Bool(*x),
-            Expression::Name(name) => *self.stack.get(self.func_stack_offset + name.data).unwrap(),
-            Expression::Add(l, r) => self.int_func(l, r, |a, b| a + b),
-            Expression::Sub(l, r) => self.int_func(l, r, |a, b| a - b),
-            Expression::Mul(l, r) => self.int_func(l, r, |a, b| a * b),
-            Expression::Div(l, r) => self.int_func(l, r, |a, b| a / b),
+            Expression::Bool(x) => Ok(Value::Bool(x.data)),
+            Expression::Name(name) => self.read(name),
+            Expression::Add(l, r) => self.int_func(l, r, i64::checked_add),
+            Expression::Sub(l, r) => self.int_func(l, r, i64::checked_sub),
+            Expression::Mul(l, r) => self.int_func(l, r, i64::checked_mul),
+            Expression::Div(l, r) => self.int_func(l, r, i64::checked_div),
+            Expression::Mod(l, r) => self.int_func(l, r, i64::checked_rem),
             Expression::Eq(l, r) => {
-                let l = self.translate_expr(l);
-                let r = self.translate_expr(r);
-                match (l, r) {
-                    (Value::Int(l), Value::Int(r)) => Value::Bool(l == r),
-                    (Value::Bool(l), Value::Bool(r)) => Value::Bool(l == r),
-                    _ => panic!("expect same type"),
-                }
+                let l = self.translate_expr(l)?;
+                let r = self.translate_expr(r)?;
+                Ok(Value::Bool(self.value_eq(l, r, expr)?))
             },
             Expression::Neq(l, r) => {
-                let l = self.translate_expr(l);
-                let r = self.translate_expr(r);
-                match (l, r) {
-                    (Value::Int(l), Value::Int(r)) => Value::Bool(l != r),
-                    (Value::Bool(l), Value::Bool(r)) => Value::Bool(l != r),
-                    _ => panic!("expect same type"),
+                let l = self.translate_expr(l)?;
+                let r = self.translate_expr(r)?;
+                Ok(Value::Bool(!self.value_eq(l, r, expr)?))
+            },
+            Expression::Lt(l, r) => self.int_cmp(l, r, |a, b| a < b),
+            Expression::Le(l, r) => self.int_cmp(l, r, |a, b| a <= b),
+            Expression::Gt(l, r) => self.int_cmp(l, r, |a, b| a > b),
+            Expression::Ge(l, r) => self.int_cmp(l, r, |a, b| a >= b),
+            Expression::And(l, r) => match self.translate_expr(l)? {
+                Value::Bool(false) => Ok(Value::Bool(false)),
+                Value::Bool(true) => self.translate_expr(r),
+                _ => Err(self.error("expect Bool", l.span())),
+            },
+            Expression::Or(l, r) => match self.translate_expr(l)? {
+                Value::Bool(true) => Ok(Value::Bool(true)),
+                Value::Bool(false) => self.translate_expr(r),
+                _ => Err(self.error("expect Bool", l.span())),
+            },
+            Expression::Not(e) => match self.translate_expr(e)? {
+                Value::Bool(x) => Ok(Value::Bool(!x)),
+                _ => Err(self.error("expect Bool", e.span())),
+            },
+            Expression::Neg(e) => match self.translate_expr(e)? {
+                Value::Int(x) => Ok(Value::Int(-x)),
+                _ => Err(self.error("expect Int", e.span())),
+            },
+            Expression::Field(obj, name) => {
+                let obj = self.translate_expr(obj)?;
+                match self.instance(obj).map(|i| i.fields.get(&name.data).copied()) {
+                    Some(Some(value)) => Ok(value),
+                    // a method without arguments is read like a field
+                    _ => self.call_method(obj, name, name.with_data(()), vec![]),
                 }
             },
-            Expression::Call(name, p) => {
-                let mut args = vec![];
-                for arg in p {
-                    args.push(self.translate_expr(arg))
+            Expression::MethodCall(obj, name, p) => {
+                let call_site = expr.span().unwrap_or_default();
+                let obj = self.translate_expr(obj)?;
+                let args = self.translate_args(p)?;
+                self.call_method(obj, name, call_site, args)
+            },
+            Expression::Lambda(params, captures, block) => {
+                let id = self.make_closure(Symbol::LAMBDA, params.len(), captures, block);
+                Ok(Value::Closure(id))
+            },
+            Expression::Apply(f, p) => {
+                let call_site = expr.span().unwrap_or_default();
+                let f = self.translate_expr(f)?;
+                let args = self.translate_args(p)?;
+                match f {
+                    Value::Closure(id) => self.call_closure(id, call_site, args),
+                    // indexing a Seq or a Map
+                    _ => self.call_method(f, &call_site.with_data(Symbol::APPLY), call_site, args),
                 }
-                let mut built_in_func: HashMap<String, Box<dyn Fn(&mut Interpreter, Vec<Value>) -> Value>> = HashMap::new();
-                built_in_func.insert("print".to_owned(), Box::new(bi_print));
-                built_in_func.insert("Array".to_owned(), Box::new(bi_array));
-
-                if let Some(f) = built_in_func.get(&name.data) {
-                    f(self, args)
-                } else if let Some(func) = self.funcs.clone().get(&name.data) {
-                    let old_offset = self.func_stack_offset;
-                    let next_offset = self.stack.len();
-                    for (_, e) in func.params.iter().zip(p.iter()) {
-                        let value = self.translate_expr(e);
-                        self.stack.push(value);
-                    }
-                    self.func_stack_offset = next_offset;
-                    let ret = self.translate_block(&func.block);
-                    self.stack.truncate(next_offset);
-                    self.func_stack_offset = old_offset;
-                    ret
+            },
+            Expression::Call(name, p) => {
+                let call_site = name.with_data(());
+                let args = self.translate_args(p)?;
+                if let Some(f) = built_in(name.data.as_str()) {
+                    f(self, args).map_err(|msg| self.error(msg, Some(call_site)))
+                } else if let Some(native) = self.natives.get(name.data).cloned() {
+                    native.call(self, args).map_err(|msg| self.error(msg, Some(call_site)))
+                } else if self.classes.contains_key(&name.data) {
+                    Ok(self.construct(name.data, call_site, args)?.0)
                 } else {
-                    let func = self.classes.get(&name.data).unwrap().clone();
-                    let old_offset = self.func_stack_offset;
-                    let next_offset = self.stack.len();
-                    for ((_, _), e) in func.args.iter().zip(p.iter()) {
-                        let value = self.translate_expr(e);
-                        self.stack.push(value);
-                    }
-                    self.func_stack_offset = next_offset;
-                    let ret = self.translate_block(&func.block);
-                    self.stack.truncate(next_offset);
-                    self.func_stack_offset = old_offset;
-                    ret
+                    Err(self.error(format!("unknown function `{}`", name.data), Some(call_site)))
                 }
             },
             Expression::If(cond, then_body, else_body) => {
-                if let Value::Bool(true) = self.translate_expr(cond) {
-                    self.translate_block(then_body)
+                if let Value::Bool(true) = self.translate_expr(cond)? {
+                    self.scoped_block(then_body)
                 }else if let Some(e) = else_body {
-                    self.translate_block(e)
+                    self.scoped_block(e)
                 }else {
-                    Value::Unit
+                    Ok(Value::Unit)
+                }
+            },
+            Expression::Block(b) => self.scoped_block(b),
+            Expression::Tuple(v) => {
+                let mut values = vec![];
+                for e in v {
+                    values.push(self.translate_expr(e)?);
+                }
+                Ok(Value::HeapId(self.heap.alloc(HeapValue::Vec(values))))
+            },
+            Expression::Match(e, cases) => {
+                let value = self.translate_expr(e)?;
+                for case in cases {
+                    let stack_len = self.stack.len();
+                    let ret = match self.match_case(case, value) {
+                        Ok(true) => Some(self.translate_block(&case.block)),
+                        Ok(false) => None,
+                        Err(e) => Some(Err(e)),
+                    };
+                    self.truncate(stack_len);
+                    if let Some(ret) = ret {
+                        return ret;
+                    }
+                }
+                Err(self.error(format!("match error: {value:?}"), e.span()))
+            },
+        }
+    }
+
+    fn match_case(&mut self, case: &Case, value: Value) -> Flow<bool> {
+        if !self.match_pattern(&case.pattern, value)? {
+            return Ok(false);
+        }
+        match &case.guard {
+            Some(g) => Ok(matches!(self.translate_expr(g)?, Value::Bool(true))),
+            None => Ok(true),
+        }
+    }
+
+    /// push the binds of `pattern` to the stack and tell if `value` matches it
+    fn match_pattern(&mut self, pattern: &Pattern, value: Value) -> Flow<bool> {
+        match pattern {
+            Pattern::Wildcard => Ok(true),
+            Pattern::Literal(e) => {
+                let lit = self.translate_expr(e)?;
+                self.value_eq(lit, value, e)
+            },
+            Pattern::Bind(_) => {
+                self.stack.push(value);
+                Ok(true)
+            },
+            Pattern::Tuple(v) => {
+                let items = match value {
+                    Value::HeapId(idx) => match self.heap.get(idx) {
+                        Some(HeapValue::Vec(items)) if items.len() == v.len() => items.clone(),
+                        _ => return Ok(false),
+                    },
+                    _ => return Ok(false),
+                };
+                for (p, x) in v.iter().zip(items) {
+                    if !self.match_pattern(p, x)? {
+                        return Ok(false);
+                    }
                 }
+                Ok(true)
+            },
+            Pattern::Extractor(name, _) => Err(self.error(
+                format!("extractor pattern `{}` is not supported yet", name.data),
+                Some(name.with_data(())),
+            )),
+        }
+    }
+
+    fn value_eq(&self, l: Value, r: Value, at: &Expression) -> Flow<bool> {
+        match (l, r) {
+            (Value::Int(l), Value::Int(r)) => Ok(l == r),
+            (Value::Bool(l), Value::Bool(r)) => Ok(l == r),
+            (Value::Unit, Value::Unit) => Ok(true),
+            (Value::Closure(l), Value::Closure(r)) => Ok(l == r),
+            (Value::HeapId(l), Value::HeapId(r)) => match (self.heap.get(l), self.heap.get(r)) {
+                (Some(HeapValue::String(l)), Some(HeapValue::String(r))) => Ok(l == r),
+                _ => Ok(l == r),
             },
+            _ => Err(self.error(format!("can not compare {l:?} with {r:?}"), at.span())),
         }
     }
 
-    fn int_func<F>(&mut self, l: &Expression, r: &Expression, f: F) -> Value
+    fn int_func<F>(&mut self, l: &Expression, r: &Expression, f: F) -> Flow
     where
-        F: Fn(i64, i64) -> i64
+        F: Fn(i64, i64) -> Option<i64>
     {
-        let l = self.translate_expr(l);
-        let r = self.translate_expr(r);
+        let at = l.span();
+        let l = self.translate_expr(l)?;
+        let r = self.translate_expr(r)?;
         match (l, r) {
-            (Value::Int(l), Value::Int(r)) => Value::Int(f(l, r)),
-            _ => panic!("expect Int"),
+            (Value::Int(l), Value::Int(r)) => match f(l, r) {
+                Some(x) => Ok(Value::Int(x)),
+                None if r == 0 => Err(self.error("division by zero", at)),
+                None => Err(self.error("integer overflow", at)),
+            },
+            _ => Err(self.error("expect Int", at)),
+        }
+    }
+
+    fn int_cmp<F>(&mut self, l: &Expression, r: &Expression, f: F) -> Flow
+    where
+        F: Fn(i64, i64) -> bool
+    {
+        let at = l.span();
+        let l = self.translate_expr(l)?;
+        let r = self.translate_expr(r)?;
+        match (l, r) {
+            (Value::Int(l), Value::Int(r)) => Ok(Value::Bool(f(l, r))),
+            _ => Err(self.error("expect Int", at)),
+        }
+    }
+}
+
+impl Host for Interpreter {
+    fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+    fn call(&mut self, f: Value, args: Vec<Value>) -> Result<Value, String> {
+        let Value::Closure(id) = f else {
+            return Err(format!("{f:?} is not a function"));
+        };
+        match self.call_closure(id, Span::default(), args) {
+            Ok(value) => Ok(value),
+            Err(Unwind::Error(e)) => Err(e.msg),
+            Err(_) => unreachable!("left by leave_frame"),
         }
     }
 }
@@ -1,70 +1,408 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
 use dashmap::DashMap;
+use tower_lsp::lsp_types::notification::Progress;
+use tower_lsp::lsp_types::request::WorkDoneProgressCreate;
 use tower_lsp::{lsp_types::*, Client, LanguageServer};
-use tower_lsp::jsonrpc::Result;
-use typort_interpreter::hir::parse_to_hir;
-use typort_interpreter::mir::hir_to_mir;
-use typort_parser::simple_example::TopItem;
+use tower_lsp::jsonrpc::{Error, Result};
+use typort_interpreter::index::{doc_comment, DefId, SymbolIndex};
+use typort_interpreter::netlist::Netlist;
+use typort_interpreter::{Session, Severity, Span};
+use typort_parser::ast::{FileId, Ident, TopItem};
+
+use crate::db::{Analysis, Database, Lines};
+use crate::{completion, inlay, semantic, signature, symbols};
+
+/// the commands of the lenses on a component, run by the server
+const COMMANDS: &[(&str, &str)] = &[
+    ("typort.generateVerilog", "Generate Verilog"),
+    ("typort.simulate", "Simulate"),
+    ("typort.showSchematic", "Show schematic"),
+];
 
 #[derive(Debug)]
 pub struct Backend {
     pub client: Client,
-    ast_map: DashMap<String, Vec<TopItem>>,
-    hir_map: DashMap<String, Vec<typort_interpreter::hir::Class>>,
-    mir_map: DashMap<String, Vec<typort_interpreter::mir::Class>>
+    file_ids: DashMap<String, FileId>,
+    next_file_id: AtomicU32,
+    /// the text of every file of the workspace, open or not, and what is
+    /// known from it
+    db: Mutex<Database>,
+    /// the open documents, with their version and the diagnostics last
+    /// published for them
+    documents: DashMap<String, (i32, Vec<Diagnostic>)>,
+    /// the semantic tokens last sent for each document, a delta is from them
+    tokens_map: DashMap<String, SemanticTokens>,
+    next_result_id: AtomicU64,
+    /// if the client asks again for the inlay hints and the semantic tokens
+    /// of its documents when told to
+    hints_refresh: AtomicBool,
+    tokens_refresh: AtomicBool,
+    /// the folders of the workspace
+    roots: RwLock<Vec<PathBuf>>,
+    /// where the verilog and the schematics are written, the `outputDir` of
+    /// the initialization options, else next to their source
+    output_dir: RwLock<Option<PathBuf>>,
 }
 
 impl Backend {
     pub fn new(client: Client) -> Self {
         Backend {
             client,
-            ast_map: Default::default(),
-            hir_map: Default::default(),
-            mir_map: Default::default(),
-        }
-    }
-    pub async fn on_change(&self, params: TextDocumentItem) {
-        let (ast, parse_fail, _) = typort_parser::simple_example::file()
-            .run_with_out(&params.text, Default::default());
-        //TODO: diagnostic
-        if let Some(ast) = ast {
-            self.client.log_message(MessageType::INFO, "parse success").await;
-            self.ast_map.insert(params.uri.to_string(), ast.clone());
-            let hir = parse_to_hir(ast);
-            self.hir_map.insert(params.uri.to_string(), hir.clone());
-            let mir = hir_to_mir(hir);
-            self.mir_map.insert(params.uri.to_string(), mir);
-        } else {
-            self.client.log_message(MessageType::INFO, "parse fail").await;
-            self.client
-                .publish_diagnostics(
-                    params.uri,
-                    vec![],//TODO:
-                    None
-                ).await
+            file_ids: Default::default(),
+            next_file_id: Default::default(),
+            db: Default::default(),
+            documents: Default::default(),
+            tokens_map: Default::default(),
+            next_result_id: Default::default(),
+            hints_refresh: Default::default(),
+            tokens_refresh: Default::default(),
+            roots: Default::default(),
+            output_dir: Default::default(),
+        }
+    }
+    /// every file gets its own id, kept for the whole session
+    fn file_id(&self, uri: &Url) -> FileId {
+        // taken in the entry, two new files at once get different ids
+        *self.file_ids.entry(uri.to_string())
+            .or_insert_with(|| FileId(self.next_file_id.fetch_add(1, Ordering::Relaxed)))
+    }
+    /// the document a file id was given to
+    fn uri(&self, file: FileId) -> Option<Url> {
+        self.file_ids.iter()
+            .find(|x| *x.value() == file)
+            .and_then(|x| Url::parse(x.key()).ok())
+    }
+    pub async fn on_change(&self, uri: Url, version: i32) {
+        self.documents.entry(uri.to_string()).or_default().0 = version;
+        self.publish_diagnostics(Some(&uri)).await;
+        self.refresh(Some(&uri)).await;
+    }
+    /// have the client ask again for the inlay hints and the semantic tokens
+    /// of the open documents, a change in one file may change them. those
+    /// of `edited` are asked for anyway
+    async fn refresh(&self, edited: Option<&Url>) {
+        if self.documents.iter().all(|d| Some(d.key().as_str()) == edited.map(Url::as_str)) {
+            return;
+        }
+        // a client which can not refresh keeps what it has
+        if self.hints_refresh.load(Ordering::Relaxed) {
+            let _ = self.client.inlay_hint_refresh().await;
+        }
+        if self.tokens_refresh.load(Ordering::Relaxed) {
+            let _ = self.client.semantic_tokens_refresh().await;
+        }
+    }
+    /// publish the diagnostics of every open document whose diagnostics
+    /// changed, a change in one file may fix or break the others. those of
+    /// `edited` are always published
+    async fn publish_diagnostics(&self, edited: Option<&Url>) {
+        let analysis = self.analysis();
+        let documents: Vec<(String, i32)> = self.documents.iter().map(|d| (d.key().clone(), d.value().0)).collect();
+        for (key, version) in documents {
+            let Ok(uri) = Url::parse(&key) else { continue };
+            let file = self.file_id(&uri);
+            let errors = self.db.lock().unwrap().parse(file);
+            // the last good tree is used while the text does not parse
+            let diagnostics: Vec<_> = if errors.is_empty() {
+                analysis.diagnostics.iter().filter(|d| d.file == file).cloned().collect()
+            } else {
+                errors.iter().map(typort_interpreter::Diagnostic::from_parse_error).collect()
+            };
+            let diagnostics: Vec<_> = diagnostics.iter().map(|d| self.to_lsp_diagnostic(&uri, d)).collect();
+            let changed = self.documents.get_mut(&key).is_some_and(|mut d| {
+                let changed = d.1 != diagnostics;
+                d.1 = diagnostics.clone();
+                changed
+            });
+            // an empty list clears the diagnostics of an earlier version
+            if changed || edited == Some(&uri) {
+                self.client.publish_diagnostics(uri, diagnostics, Some(version)).await;
+            }
+        }
+    }
+    /// what is known of every file, it is only computed again when a file changed
+    fn analysis(&self) -> Arc<Analysis> {
+        self.db.lock().unwrap().analysis()
+    }
+    /// the lines of a file, to convert the columns of its spans for the client
+    fn lines(&self, file: FileId) -> Lines {
+        self.db.lock().unwrap().lines(file)
+    }
+    /// the semantic tokens of a document, kept to compute the next delta from
+    fn semantic_tokens(&self, uri: &Url) -> SemanticTokens {
+        let file = self.file_id(uri);
+        let data = semantic::semantic_tokens(&self.analysis().index, file, &self.lines(file));
+        let id = self.next_result_id.fetch_add(1, Ordering::Relaxed);
+        let tokens = SemanticTokens { result_id: Some(id.to_string()), data };
+        self.tokens_map.insert(uri.to_string(), tokens.clone());
+        tokens
+    }
+    async fn progress(&self, token: &ProgressToken, progress: WorkDoneProgress) {
+        self.client.send_notification::<Progress>(ProgressParams {
+            token: token.clone(),
+            value: ProgressParamsValue::WorkDone(progress),
+        }).await
+    }
+    async fn report(&self, token: &ProgressToken, message: &str) {
+        self.progress(token, WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(false),
+            message: Some(message.to_owned()),
+            percentage: None,
+        })).await
+    }
+    /// write the verilog or the schematic of a component to the output
+    /// directory and show it
+    async fn generate(&self, token: &ProgressToken, uri: &Url, name: &str, command: &str) -> Result<String> {
+        self.report(token, "finding the hardware").await;
+        let analysis = self.analysis();
+        let Some(class) = analysis.classes.iter().find(|c| c.name.data == name) else {
+            return Err(Error::invalid_params(format!("no component {name}")));
+        };
+        let netlist = Netlist::new(class, &analysis.index);
+        let (text, extension) = match command {
+            "typort.generateVerilog" => (netlist.to_verilog(), "v"),
+            _ => (netlist.to_dot(), "dot"),
+        };
+        let dir = self.output_dir.read().unwrap().clone();
+        let Some(dir) = dir.or_else(|| Some(uri.to_file_path().ok()?.parent()?.to_owned())) else {
+            return Err(Error::invalid_params(format!("{uri} is not a file")));
+        };
+        let path = dir.join(format!("{name}.{extension}"));
+        self.report(token, &format!("writing {}", path.display())).await;
+        tokio::fs::create_dir_all(&dir).await
+            .map_err(|e| Error::invalid_params(format!("can not create {}: {e}", dir.display())))?;
+        tokio::fs::write(&path, text).await
+            .map_err(|e| Error::invalid_params(format!("can not write {}: {e}", path.display())))?;
+        if let Ok(uri) = Url::from_file_path(&path) {
+            let show = ShowDocumentParams { uri, external: Some(false), take_focus: Some(true), selection: None };
+            self.client.show_document(show).await?;
+        }
+        Ok(match netlist.skipped.len() {
+            0 => format!("wrote {}", path.display()),
+            n => format!("wrote {}, {n} statements are not translated", path.display()),
+        })
+    }
+    /// elaborate a component on the bytecode vm with every file of the
+    /// workspace, what it prints and its fields are logged
+    async fn simulate(&self, token: &ProgressToken, name: &str) -> Result<String> {
+        let sources: Vec<_> = {
+            let db = self.db.lock().unwrap();
+            db.files().into_iter()
+                .filter_map(|file| Some((self.uri(file)?.to_file_path().ok()?, db.text(file)?)))
+                .collect()
+        };
+        self.report(token, &format!("elaborating {name} with {} files", sources.len())).await;
+        let top = name.to_owned();
+        let elaborated = tokio::task::spawn_blocking(move || elaborate(sources, &top))
+            .await
+            .map_err(|_| Error::internal_error())?;
+        match elaborated {
+            Ok((log, fields)) => {
+                self.report(token, &format!("logging {fields} fields")).await;
+                self.client.log_message(MessageType::INFO, log).await;
+                Ok(format!("{name} elaborated, {fields} fields"))
+            }
+            Err(e) => {
+                self.client.show_message(MessageType::ERROR, &e).await;
+                Ok(format!("{name} failed"))
+            }
+        }
+    }
+    fn text(&self, file: FileId) -> Option<String> {
+        self.db.lock().unwrap().text(file)
+    }
+    /// read the files of the workspace which are not open from the disk
+    fn load_workspace(&self) {
+        let mut db = self.db.lock().unwrap();
+        for path in self.workspace_files() {
+            let Ok(uri) = Url::from_file_path(&path) else { continue };
+            let file = self.file_id(&uri);
+            if db.contains(file) {
+                continue;
+            }
+            if let Ok(text) = std::fs::read_to_string(&path) {
+                db.set_text(file, &text);
+            }
+        }
+    }
+    fn workspace_files(&self) -> Vec<PathBuf> {
+        let mut files = vec![];
+        for root in self.roots.read().unwrap().iter() {
+            typort_files(root, &mut files);
+        }
+        files
+    }
+    /// the definition named or defined at a position
+    fn def_at(&self, index: &SymbolIndex, uri: &Url, pos: Position) -> Option<DefId> {
+        let file = self.file_id(uri);
+        // the client counts UTF-16 code units, the spans bytes
+        index.at(file, pos.line as usize, self.lines(file).byte_column(pos))
+    }
+    fn location(&self, at: &Span<()>) -> Option<Location> {
+        Some(Location {
+            uri: self.uri(at.file)?,
+            range: self.lines(at.file).range(at.range),
+        })
+    }
+    /// the signature of the definition at a position, what is known of the
+    /// hardware it is, and the comment above it
+    fn hover_at(&self, uri: &Url, pos: Position) -> Option<Hover> {
+        let analysis = self.analysis();
+        let index = &analysis.index;
+        let def = self.def_at(index, uri, pos)?;
+        let d = &index.defs[def];
+        let mut value = format!("```typort\n{}\n```", index.signature(def));
+        if let Some(signal) = &d.signal {
+            let mut facts = vec![];
+            if let Some(width) = signal.width {
+                facts.push(format!("width: {width} bits"));
+            }
+            if let Some(direction) = signal.direction {
+                facts.push(format!("direction: {direction}"));
+            }
+            if signal.register {
+                facts.push("register".to_owned());
+            }
+            if let Some(domain) = &signal.clock_domain {
+                facts.push(format!("clock domain: `{domain}`"));
+            }
+            if !facts.is_empty() {
+                value.push_str(&format!("\n\n{}", facts.join("  \n")));
+            }
+        }
+        if let Some(doc) = self.text(d.at.file).and_then(|text| doc_comment(&text, d.at.range.0.0)) {
+            value.push_str(&format!("\n\n---\n\n{doc}"));
+        }
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+            range: None,
+        })
+    }
+    fn to_lsp_diagnostic(&self, uri: &Url, d: &typort_interpreter::Diagnostic) -> Diagnostic {
+        let related: Vec<_> = d.labels.iter()
+            .filter_map(|l| Some(DiagnosticRelatedInformation {
+                location: Location {
+                    uri: if l.file == d.file { uri.clone() } else { self.uri(l.file)? },
+                    range: self.lines(l.file).range(l.range),
+                },
+                message: l.msg.clone(),
+            }))
+            .collect();
+        let mut message = d.msg.clone();
+        for note in d.notes.iter() {
+            message.push_str(&format!("\nnote: {note}"));
+        }
+        for s in d.suggestions.iter() {
+            message.push_str(&format!("\nhelp: {}: `{}`", s.msg, s.replacement));
+        }
+        Diagnostic {
+            range: self.lines(d.file).range(d.range),
+            severity: Some(match d.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+                Severity::Note => DiagnosticSeverity::INFORMATION,
+            }),
+            code: d.code.map(|c| NumberOrString::String(c.to_owned())),
+            source: Some("typort".to_owned()),
+            message,
+            related_information: (!related.is_empty()).then_some(related),
+            ..Default::default()
+        }
+    }
+}
+
+/// what the design prints and its fields, with the number of fields
+fn elaborate(sources: Vec<(PathBuf, String)>, name: &str) -> std::result::Result<(String, usize), String> {
+    let mut session = Session::new();
+    for (path, text) in sources {
+        session.add_text(path, text);
+    }
+    let design = session.elaborate(name).map_err(|e| e.to_string())?;
+    let fields: Vec<_> = design.fields().into_iter()
+        .map(|(field, value)| format!("{field} = {}", design.display(value)))
+        .collect();
+    Ok((format!("{}{}", design.output, fields.join("\n")), fields.len()))
+}
+
+/// the `.typort` files in `dir` and the folders in it, hidden folders and
+/// `target` are skipped
+fn typort_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if path.is_dir() {
+            if !name.starts_with('.') && name != "target" {
+                typort_files(&path, files);
+            }
+        } else if path.extension().is_some_and(|e| e == "typort") {
+            files.push(path);
         }
     }
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let mut roots: Vec<_> = params.workspace_folders.unwrap_or_default()
+            .iter()
+            .filter_map(|f| f.uri.to_file_path().ok())
+            .collect();
+        if roots.is_empty() {
+            roots.extend(params.root_uri.and_then(|uri| uri.to_file_path().ok()));
+        }
+        // a relative output directory is in the first folder of the workspace
+        let output_dir = params.initialization_options.as_ref()
+            .and_then(|o| o.get("outputDir")?.as_str())
+            .map(|dir| roots.first().map_or_else(|| PathBuf::from(dir), |root| root.join(dir)));
+        *self.output_dir.write().unwrap() = output_dir;
+        *self.roots.write().unwrap() = roots;
+        let workspace = params.capabilities.workspace.as_ref();
+        let hints_refresh = workspace.and_then(|w| w.inlay_hint.as_ref()?.refresh_support);
+        let tokens_refresh = workspace.and_then(|w| w.semantic_tokens.as_ref()?.refresh_support);
+        self.hints_refresh.store(hints_refresh.unwrap_or(false), Ordering::Relaxed);
+        self.tokens_refresh.store(tokens_refresh.unwrap_or(false), Ordering::Relaxed);
         Ok(InitializeResult {
             server_info: None,
             offset_encoding: None,
             capabilities: ServerCapabilities {
-                //inlay_hint_provider: Some(OneOf::Left(true)),
-                //completion_provider: Some(CompletionOptions {
-                //    resolve_provider: Some(false),
-                //    trigger_characters: Some(vec![".".to_string()]),
-                //    work_done_progress_options: Default::default(),
-                //    all_commit_characters: None,
-                //    completion_item: None,
-                //}),
-                code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(true) }),
-
-                //definition_provider: Some(OneOf::Left(true)),
-                //references_provider: Some(OneOf::Left(true)),
-                //rename_provider: Some(OneOf::Left(true)),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                completion_provider: Some(CompletionOptions {
+                    resolve_provider: Some(false),
+                    trigger_characters: Some(vec![".".to_string()]),
+                    work_done_progress_options: Default::default(),
+                    all_commit_characters: None,
+                    completion_item: None,
+                }),
+                code_lens_provider: Some(CodeLensOptions { resolve_provider: None }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: COMMANDS.iter().map(|(command, _)| command.to_string()).collect(),
+                    work_done_progress_options: WorkDoneProgressOptions { work_done_progress: Some(true) },
+                }),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                    retrigger_characters: None,
+                    work_done_progress_options: Default::default(),
+                }),
+                definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+                    SemanticTokensOptions {
+                        legend: semantic::legend(),
+                        full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+                        range: None,
+                        work_done_progress_options: Default::default(),
+                    },
+                )),
                 workspace: Some(WorkspaceServerCapabilities {
                     workspace_folders: Some(WorkspaceFoldersServerCapabilities {
                         supported: Some(true),
@@ -73,13 +411,14 @@ impl LanguageServer for Backend {
                     file_operations: None,
                 }),
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 ..ServerCapabilities::default()
             },
         })
     }
     async fn initialized(&self, _: InitializedParams) {
+        self.load_workspace();
         self.client
             .log_message(MessageType::INFO, "initialized!")
             .await;
@@ -94,61 +433,251 @@ impl LanguageServer for Backend {
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
         self.client.log_message(MessageType::INFO, "on open").await;
-        self.on_change(TextDocumentItem {
-            uri:params.text_document.uri,
-            text:params.text_document.text,
-            version:params.text_document.version,
-            language_id: "typort".to_owned(),
-        })
-        .await
-    }
-    async fn did_change(&self, mut params: DidChangeTextDocumentParams) {
-        self.client.log_message(MessageType::INFO, "on change").await;
-        self.on_change(TextDocumentItem {
-            uri: params.text_document.uri,
-            text: std::mem::take(&mut params.content_changes[0].text),
-            version: params.text_document.version,
-            language_id: "typort".to_owned()
-        })
-        .await
+        let document = params.text_document;
+        let file = self.file_id(&document.uri);
+        self.db.lock().unwrap().set_text(file, &document.text);
+        self.on_change(document.uri, document.version).await
+    }
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let document = params.text_document;
+        let file = self.file_id(&document.uri);
+        {
+            let mut db = self.db.lock().unwrap();
+            // the changes are applied in order, each range is in the text the previous one gave
+            for change in params.content_changes {
+                db.edit(file, change.range, &change.text);
+            }
+        }
+        self.on_change(document.uri, document.version).await
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        // it is read from the disk again, the changes which are not saved are lost
+        let uri = params.text_document.uri;
+        let file = self.file_id(&uri);
+        {
+            let mut db = self.db.lock().unwrap();
+            match uri.to_file_path().ok().and_then(|path| std::fs::read_to_string(path).ok()) {
+                Some(text) if self.workspace_files().iter().any(|p| Url::from_file_path(p).ok().as_ref() == Some(&uri)) => {
+                    db.set_text(file, &text)
+                }
+                _ => db.remove(file),
+            }
+        }
+        // the diagnostics of a file which is not open are not shown
+        self.documents.remove(uri.as_str());
+        self.tokens_map.remove(uri.as_str());
+        self.client.publish_diagnostics(uri, vec![], None).await;
+        self.publish_diagnostics(None).await;
+        self.refresh(None).await;
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        {
+            let mut roots = self.roots.write().unwrap();
+            for folder in params.event.removed {
+                roots.retain(|r| Some(r) != folder.uri.to_file_path().ok().as_ref());
+            }
+            roots.extend(params.event.added.iter().filter_map(|f| f.uri.to_file_path().ok()));
+        }
+        self.load_workspace();
+    }
+
+    async fn goto_definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>> {
+        let position = params.text_document_position_params;
+        let analysis = self.analysis();
+        let index = &analysis.index;
+        let def = self.def_at(index, &position.text_document.uri, position.position);
+        Ok(def.and_then(|d| self.location(&index.defs[d].at)).map(GotoDefinitionResponse::Scalar))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let position = params.text_document_position;
+        let analysis = self.analysis();
+        let index = &analysis.index;
+        let Some(def) = self.def_at(index, &position.text_document.uri, position.position) else {
+            return Ok(None);
+        };
+        let declaration = params.context.include_declaration.then_some(&index.defs[def].at);
+        Ok(Some(declaration.into_iter()
+            .chain(index.references(def))
+            .filter_map(|at| self.location(at))
+            .collect()))
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        if !typort_parser::is_identifier(&params.new_name) {
+            return Err(Error::invalid_params(format!("`{}` is not a valid name", params.new_name)));
+        }
+        let position = params.text_document_position;
+        let analysis = self.analysis();
+        let index = &analysis.index;
+        let Some(def) = self.def_at(index, &position.text_document.uri, position.position) else {
+            return Ok(None);
+        };
+        let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+        for at in std::iter::once(&index.defs[def].at).chain(index.references(def)) {
+            let Some(location) = self.location(at) else { continue };
+            changes.entry(location.uri).or_default().push(TextEdit {
+                range: location.range,
+                new_text: params.new_name.clone(),
+            });
+        }
+        Ok(Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..Default::default()
+        }))
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let position = params.text_document_position;
+        let uri = position.text_document.uri;
+        let file = self.file_id(&uri);
+        let Some(text) = self.text(file) else { return Ok(None) };
+        let at = (position.position.line as usize, self.lines(file).byte_column(position.position));
+        let items = completion::completions(&self.analysis().index, &text, file, at);
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        let position = params.text_document_position_params;
+        let file = self.file_id(&position.text_document.uri);
+        let Some(text) = self.text(file) else { return Ok(None) };
+        let at = (position.position.line as usize, self.lines(file).byte_column(position.position));
+        Ok(signature::signature_help(&self.analysis().index, &text, file, at))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let uri = params.text_document.uri;
+        if !self.documents.contains_key(uri.as_str()) {
+            return Ok(None);
+        }
+        // from the last good tree of every file
+        let file = self.file_id(&uri);
+        let hints = inlay::inlay_hints(&self.analysis().index, file, &self.lines(file));
+        let range = params.range;
+        Ok(Some(hints.into_iter().filter(|h| range.start <= h.position && h.position <= range.end).collect()))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let position = params.text_document_position_params;
+        Ok(self.hover_at(&position.text_document.uri, position.position))
+    }
+
+    async fn document_symbol(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>> {
+        let file = self.file_id(&params.text_document.uri);
+        let items = self.db.lock().unwrap().items(file);
+        let symbols = symbols::document_symbols(&self.analysis().index, file, &self.lines(file), items.0.iter().map(|(item, _)| item));
+        Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+    }
+
+    async fn symbol(&self, params: WorkspaceSymbolParams) -> Result<Option<Vec<SymbolInformation>>> {
+        let analysis = self.analysis();
+        Ok(Some(symbols::workspace_symbols(&analysis.index, &params.query, |at| self.location(at))))
+    }
+
+    async fn semantic_tokens_full(&self, params: SemanticTokensParams) -> Result<Option<SemanticTokensResult>> {
+        Ok(Some(SemanticTokensResult::Tokens(self.semantic_tokens(&params.text_document.uri))))
+    }
+
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        let uri = params.text_document.uri;
+        let old = self.tokens_map.get(&uri.to_string()).map(|t| t.clone());
+        let new = self.semantic_tokens(&uri);
+        // all the tokens are sent when the client has others than the last ones
+        Ok(Some(match old.filter(|old| old.result_id.as_ref() == Some(&params.previous_result_id)) {
+            Some(old) => SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+                result_id: new.result_id,
+                edits: semantic::delta(&old.data, &new.data),
+            }),
+            None => SemanticTokensFullDeltaResult::Tokens(new),
+        }))
     }
 
     async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
         let uri = params.text_document.uri;
-        let codelens = || -> Option<Vec<CodeLens>> {
-            let ast = self.ast_map.get(&uri.to_string())?;
-            Some(ast.iter()
-                .flat_map(|x| {
-                    let extends = match x {
-                        TopItem::Class(c) => (&c.name, &c.extends),
-                        TopItem::Object(o) => (&o.name, &o.extends),
-                    };
-                    extends.1.as_ref().and_then(|x| if x.data == "App" {
-                        let name = extends.0;
-                        Some(CodeLens {
-                            range: Range { start: Position {
-                                line: name.range.0.0 as u32,
-                                character: name.range.0.1 as u32,
-                            }, end: Position {
-                                line: name.range.1.0 as u32,
-                                character: name.range.1.1 as u32,
-                            } },
-                            command: Some(Command {
-                                title: "run code".to_owned(),
-                                command: "typort".to_owned(),
-                                arguments: Some(vec![
-                                    serde_json::Value::String("cli".to_owned()),
-                                    serde_json::Value::String(uri.path().to_owned()),
-                                    serde_json::Value::String(name.data.clone()),
-                                ]),
-                            }),
-                            data: Some(serde_json::Value::String("Run".to_owned())),
-                        })
-                    } else {
-                        None
-                    })
-                }).collect())
-        }();
-        Ok(codelens)
+        let file = self.file_id(&uri);
+        let (items, lines) = {
+            let mut db = self.db.lock().unwrap();
+            (db.items(file), db.lines(file))
+        };
+        let analysis = self.analysis();
+        let argument = |name: &Ident| vec![
+            serde_json::Value::String(uri.to_string()),
+            serde_json::Value::String(name.data.to_string()),
+        ];
+        let codelens = items.0.iter()
+            .flat_map(|(x, _)| {
+                let extends = match x {
+                    TopItem::Class(c) => (&c.name, &c.extends),
+                    TopItem::Object(o) => (&o.name, &o.extends),
+                    TopItem::Import(_) => return vec![],
+                };
+                let name = extends.0;
+                let lens = |title: &str, command: &str, arguments| CodeLens {
+                    range: lines.range(name.range),
+                    command: Some(Command {
+                        title: title.to_owned(),
+                        command: command.to_owned(),
+                        arguments: Some(arguments),
+                    }),
+                    data: Some(serde_json::Value::String(title.to_owned())),
+                };
+                if extends.1.as_ref().is_some_and(|x| x.data == "App") {
+                    vec![CodeLens {
+                        data: Some(serde_json::Value::String("Run".to_owned())),
+                        ..lens("run code", "typort", vec![
+                            serde_json::Value::String("cli".to_owned()),
+                            serde_json::Value::String(uri.path().to_owned()),
+                            serde_json::Value::String(name.data.to_string()),
+                        ])
+                    }]
+                } else if matches!(x, TopItem::Class(_)) && analysis.index.inherits(name.data, "Component") {
+                    COMMANDS.iter().map(|(command, title)| lens(title, command, argument(name))).collect()
+                } else {
+                    vec![]
+                }
+            }).collect();
+        Ok(Some(codelens))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<serde_json::Value>> {
+        let (Some(uri), Some(name)) = (
+            params.arguments.first().and_then(|x| x.as_str()).and_then(|x| Url::parse(x).ok()),
+            params.arguments.get(1).and_then(|x| x.as_str()).map(str::to_owned),
+        ) else {
+            return Err(Error::invalid_params("expected the document and the name of a component"));
+        };
+        let Some((_, title)) = COMMANDS.iter().find(|(command, _)| *command == params.command) else {
+            return Err(Error::invalid_params(format!("unknown command {}", params.command)));
+        };
+        let token = match params.work_done_progress_params.work_done_token {
+            Some(token) => token,
+            None => {
+                let token = NumberOrString::String(format!("typort/{}", self.next_result_id.fetch_add(1, Ordering::Relaxed)));
+                let create = WorkDoneProgressCreateParams { token: token.clone() };
+                self.client.send_request::<WorkDoneProgressCreate>(create).await?;
+                token
+            }
+        };
+        self.progress(&token, WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: title.to_string(),
+            cancellable: Some(false),
+            message: Some(name.clone()),
+            percentage: None,
+        })).await;
+        let ret = match params.command.as_str() {
+            "typort.simulate" => self.simulate(&token, &name).await,
+            command => self.generate(&token, &uri, &name, command).await,
+        };
+        let message = match &ret {
+            Ok(message) => message.clone(),
+            Err(e) => e.message.to_string(),
+        };
+        self.progress(&token, WorkDoneProgress::End(WorkDoneProgressEnd { message: Some(message) })).await;
+        ret.map(|message| Some(serde_json::Value::String(message)))
     }
 }
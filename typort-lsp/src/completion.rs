@@ -0,0 +1,194 @@
+use tower_lsp::lsp_types::*;
+use typort_interpreter::built_in::{BUILT_INS, BUILT_IN_METHODS};
+use typort_interpreter::index::{DefId, SymbolIndex, SymbolKind, HARDWARE_MEMBERS};
+use typort_parser::ast::FileId;
+
+/// templates for the code written most often, with the places to fill in
+const SNIPPETS: &[(&str, &str, &str)] = &[
+    (
+        "Component",
+        "a component with its io bundle",
+        "class ${1:Name} extends Component {\n  val io = new Bundle {\n    $0\n  }\n}",
+    ),
+    ("Bundle", "a bundle of signals", "val ${1:io} = new Bundle {\n  $0\n}"),
+    ("when", "when/otherwise", "when(${1:cond}) {\n  $2\n}.otherwise {\n  $0\n}"),
+];
+
+/// the completions at `at`, a line and a byte column. `index` is the one of
+/// the last text which parsed, only `text` is read around `at` so the line
+/// may be incomplete
+pub fn completions(index: &SymbolIndex, text: &str, file: FileId, at: (usize, usize)) -> Vec<CompletionItem> {
+    let line = text.lines().nth(at.0).unwrap_or("");
+    let before = line.get(..at.1).unwrap_or(line);
+    let word_start = before.trim_end_matches(is_name_char).len();
+    match before[..word_start].strip_suffix('.') {
+        Some(receiver) => {
+            let path: String = receiver.chars().rev().take_while(|c| is_name_char(*c) || *c == '.').collect();
+            let path: Vec<String> = path.chars().rev().collect::<String>().split('.').map(str::to_owned).collect();
+            members(index, text, file, at, &path)
+        }
+        None => in_scope(index, text, file, at),
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// the names that can be used at `at`, with the keywords, the built-ins
+/// and the snippets
+fn in_scope(index: &SymbolIndex, text: &str, file: FileId, at: (usize, usize)) -> Vec<CompletionItem> {
+    let mut items: Vec<_> = visible(index, text, file, at).into_iter().map(|def| def_item(index, def)).collect();
+    items.extend(index.classes.values().map(|def| def_item(index, *def)));
+    items.extend(typort_parser::KEYWORDS.iter().map(|k| CompletionItem {
+        label: k.to_string(),
+        kind: Some(CompletionItemKind::KEYWORD),
+        ..Default::default()
+    }));
+    items.extend(BUILT_INS.iter().map(|(name, _)| CompletionItem {
+        label: name.to_string(),
+        kind: Some(CompletionItemKind::FUNCTION),
+        detail: Some("built-in".to_owned()),
+        ..Default::default()
+    }));
+    items.extend(SNIPPETS.iter().map(|(label, detail, body)| CompletionItem {
+        label: label.to_string(),
+        kind: Some(CompletionItemKind::SNIPPET),
+        detail: Some(detail.to_string()),
+        insert_text: Some(body.to_string()),
+        insert_text_format: Some(InsertTextFormat::SNIPPET),
+        ..Default::default()
+    }));
+    items
+}
+
+/// the members of what `path` names, e.g. the fields of a bundle for `io`
+fn members(index: &SymbolIndex, text: &str, file: FileId, at: (usize, usize), path: &[String]) -> Vec<CompletionItem> {
+    let hardware = |ty: &str| -> Vec<CompletionItem> {
+        HARDWARE_MEMBERS.iter()
+            .filter(|(t, _)| *t == ty)
+            .flat_map(|(_, members)| members.iter())
+            .map(|m| CompletionItem {
+                label: m.to_string(),
+                kind: Some(CompletionItemKind::FIELD),
+                detail: Some(ty.to_owned()),
+                ..Default::default()
+            })
+            .collect()
+    };
+    let Some((first, rest)) = path.split_first() else { return vec![] };
+    let found = visible(index, text, file, at)
+        .into_iter()
+        .find(|def| index.defs[*def].name == first.as_str())
+        .or_else(|| index.classes.iter().find(|(c, _)| c.as_str() == first).map(|(_, def)| *def));
+    let Some(mut def) = found else {
+        // a hardware type which is not defined in the source, e.g. `Stream`
+        return hardware(first);
+    };
+    for name in rest {
+        let Some(member) = class_of(index, def).and_then(|c| index.members(c).into_iter().find(|m| index.defs[*m].name == name.as_str())) else {
+            return vec![];
+        };
+        def = member;
+    }
+    if let Some(class) = class_of(index, def) {
+        return index.members(class).into_iter().map(|m| def_item(index, m)).collect();
+    }
+    match &index.defs[def].signal {
+        Some(signal) => hardware(&signal.ty),
+        None => BUILT_IN_METHODS.iter()
+            .map(|(name, _)| CompletionItem {
+                label: name.to_string(),
+                kind: Some(CompletionItemKind::METHOD),
+                detail: Some("built-in".to_owned()),
+                ..Default::default()
+            })
+            .collect(),
+    }
+}
+
+/// the class of the instances a definition holds, a class or an object is
+/// its own
+fn class_of(index: &SymbolIndex, def: DefId) -> Option<typort_interpreter::Symbol> {
+    match index.defs[def].kind {
+        SymbolKind::Class | SymbolKind::Trait | SymbolKind::Object => Some(index.defs[def].name),
+        _ => index.class_of_def(def),
+    }
+}
+
+/// the definitions whose names can be used at `at`, the closest first: the
+/// members of the enclosing class and the locals of the blocks still open
+fn visible(index: &SymbolIndex, text: &str, file: FileId, at: (usize, usize)) -> Vec<DefId> {
+    let open = open_braces(text, at);
+    let class = index.defs.iter()
+        .enumerate()
+        .filter(|(_, d)| d.at.file == file && d.class.is_none() && d.at.range.0 <= at)
+        .max_by_key(|(_, d)| d.at.range.0)
+        .map(|(id, _)| id);
+    let mut ret: Vec<DefId> = index.defs.iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, d)| d.at.file == file && d.at.range.0 < at)
+        .filter(|(_, d)| match d.kind {
+            SymbolKind::Local | SymbolKind::Func | SymbolKind::Field => {
+                open_braces(text, d.at.range.0).last().is_none_or(|b| open.contains(b))
+            }
+            // a parameter is in scope on its line and in the block opened after it
+            SymbolKind::Param => d.at.range.0.0 == at.0 || open.last().is_some_and(|b| *b > d.at.range.0),
+            _ => false,
+        })
+        .map(|(id, _)| id)
+        .collect();
+    if let Some(class) = class {
+        ret.extend(index.members(index.defs[class].name));
+    }
+    let mut seen = std::collections::HashSet::new();
+    ret.retain(|def| seen.insert(index.defs[*def].name));
+    ret
+}
+
+/// where the `{` are which are not closed yet at `at`, the columns count
+/// bytes as the spans do
+fn open_braces(text: &str, at: (usize, usize)) -> Vec<(usize, usize)> {
+    let mut open = vec![];
+    for (line, l) in text.lines().enumerate().take(at.0 + 1) {
+        let mut chars = l.char_indices().peekable();
+        let mut in_string = false;
+        while let Some((col, c)) = chars.next() {
+            if (line, col) >= at {
+                break;
+            }
+            match c {
+                '\\' if in_string => {
+                    chars.next();
+                }
+                '"' => in_string = !in_string,
+                '/' if !in_string && chars.peek().is_some_and(|(_, c)| *c == '/') => break,
+                '{' if !in_string => open.push((line, col)),
+                '}' if !in_string => {
+                    open.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    open
+}
+
+fn def_item(index: &SymbolIndex, def: DefId) -> CompletionItem {
+    let d = &index.defs[def];
+    CompletionItem {
+        label: d.name.to_string(),
+        kind: Some(match d.kind {
+            SymbolKind::Class => CompletionItemKind::CLASS,
+            SymbolKind::Trait => CompletionItemKind::INTERFACE,
+            SymbolKind::Object => CompletionItemKind::MODULE,
+            SymbolKind::Field => CompletionItemKind::FIELD,
+            SymbolKind::Method => CompletionItemKind::METHOD,
+            SymbolKind::Func => CompletionItemKind::FUNCTION,
+            SymbolKind::Param | SymbolKind::Local => CompletionItemKind::VARIABLE,
+        }),
+        detail: Some(index.signature(def)),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use ropey::Rope;
+use tower_lsp::lsp_types::{Position, Range};
+use typort_interpreter::hir::{self, Outline};
+use typort_interpreter::index::SymbolIndex;
+use typort_interpreter::{Diagnostic, Symbol};
+use typort_parser::ast::{FileId, TopItem};
+use typort_parser::ParseError;
+
+/// bumped on every change of an input
+type Revision = u64;
+
+/// the text of a file, from the editor when it is open, else from the disk
+#[derive(Debug)]
+struct Input {
+    text: Rope,
+    changed_at: Revision,
+}
+
+/// the top items of the last text of a file which parsed, each with a hash
+/// of its code and of where it is
+#[derive(Debug, Default)]
+pub struct Items(pub Vec<(TopItem, u64)>);
+
+/// the classes of every file converted together
+#[derive(Debug)]
+pub struct Analysis {
+    pub classes: Vec<hir::Class>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub index: SymbolIndex,
+}
+
+/// a class converted on its own, see `Outline::convert`
+#[derive(Debug)]
+struct Converted {
+    class: hir::Class,
+    diagnostics: Vec<Diagnostic>,
+    index: SymbolIndex,
+}
+
+/// the inputs and the memoized results of the queries on them. a query only
+/// runs again when what it read changed, so a change in one class converts
+/// that class and the ones inheriting from it, the others are reused
+#[derive(Debug, Default)]
+pub struct Database {
+    revision: Revision,
+    inputs: HashMap<FileId, Input>,
+    /// the syntax errors of each file, with the revision of the input they are of
+    parsed: HashMap<FileId, (Revision, Arc<Vec<ParseError>>)>,
+    items: HashMap<FileId, Arc<Items>>,
+    /// the converted classes by the hash of everything their conversion read
+    converted: HashMap<u64, Arc<Converted>>,
+    analysis: Option<(Revision, Arc<Analysis>)>,
+}
+
+impl Database {
+    pub fn set_text(&mut self, file: FileId, text: &str) {
+        self.revision += 1;
+        self.inputs.insert(file, Input {
+            text: Rope::from_str(text),
+            changed_at: self.revision,
+        });
+    }
+    /// replace the text in `range`, or the whole text
+    pub fn edit(&mut self, file: FileId, range: Option<Range>, text: &str) {
+        let Some(input) = self.inputs.get_mut(&file) else { return };
+        match range {
+            Some(range) => {
+                let start = char_index(&input.text, range.start);
+                let end = char_index(&input.text, range.end).max(start);
+                input.text.remove(start..end);
+                input.text.insert(start, text);
+            }
+            None => input.text = Rope::from_str(text),
+        }
+        self.revision += 1;
+        input.changed_at = self.revision;
+    }
+    pub fn remove(&mut self, file: FileId) {
+        if self.inputs.remove(&file).is_some() {
+            self.revision += 1;
+        }
+    }
+    pub fn contains(&self, file: FileId) -> bool {
+        self.inputs.contains_key(&file)
+    }
+    pub fn files(&self) -> Vec<FileId> {
+        let mut files: Vec<FileId> = self.inputs.keys().copied().collect();
+        files.sort_by_key(|f| f.0);
+        files
+    }
+    pub fn text(&self, file: FileId) -> Option<String> {
+        self.inputs.get(&file).map(|i| i.text.to_string())
+    }
+    /// the lines of the text of a file, empty if it has none
+    pub fn lines(&self, file: FileId) -> Lines {
+        Lines(self.inputs.get(&file).map(|i| i.text.clone()).unwrap_or_default())
+    }
+    /// the syntax errors of the text of a file
+    pub fn parse(&mut self, file: FileId) -> Arc<Vec<ParseError>> {
+        let Some(input) = self.inputs.get(&file) else { return Default::default() };
+        if let Some((at, errors)) = self.parsed.get(&file) {
+            if *at == input.changed_at {
+                return errors.clone();
+            }
+        }
+        let changed_at = input.changed_at;
+        let (ast, errors) = typort_parser::parse_file(&input.text.to_string(), file);
+        // the items of an older text are kept while this one does not parse
+        if let Some(ast) = ast.filter(|_| errors.is_empty()) {
+            let items = ast.into_iter().map(|item| {
+                let hash = hash(&format!("{item:?}"));
+                (item, hash)
+            });
+            self.items.insert(file, Arc::new(Items(items.collect())));
+        }
+        let errors = Arc::new(errors);
+        self.parsed.insert(file, (changed_at, errors.clone()));
+        errors
+    }
+    pub fn items(&mut self, file: FileId) -> Arc<Items> {
+        self.parse(file);
+        self.items.get(&file).cloned().unwrap_or_default()
+    }
+    /// every file converted together, as `Session` does with the files of a design
+    pub fn analysis(&mut self) -> Arc<Analysis> {
+        if let Some((at, analysis)) = &self.analysis {
+            if *at == self.revision {
+                return analysis.clone();
+            }
+        }
+        let items: Vec<Arc<Items>> = self.files().into_iter().map(|f| self.items(f)).collect();
+        let all: Vec<TopItem> = items.iter().flat_map(|i| i.0.iter().map(|(item, _)| item.clone())).collect();
+        let outline = Outline::new(&all);
+        let hashes: HashMap<Symbol, u64> = items.iter()
+            .flat_map(|i| i.0.iter())
+            .filter_map(|(item, hash)| Some((name_of(item)?, *hash)))
+            .collect();
+        let mut converted = HashMap::new();
+        let mut classes = vec![];
+        for (item, item_hash) in items.iter().flat_map(|i| i.0.iter()) {
+            let Some(name) = name_of(item) else { continue };
+            // what the conversion reads: the class, the outline of every
+            // class and the classes it inherits from
+            let mut hasher = DefaultHasher::new();
+            (outline.fingerprint(), item_hash).hash(&mut hasher);
+            for parent in outline.linearize(name).iter().skip(1) {
+                hashes.get(parent).hash(&mut hasher);
+            }
+            let key = hasher.finish();
+            let class = match self.converted.get(&key) {
+                Some(class) => class.clone(),
+                None => {
+                    let Some((class, diagnostics, index)) = outline.convert(item.clone()) else { continue };
+                    Arc::new(Converted { class, diagnostics, index })
+                }
+            };
+            converted.insert(key, class.clone());
+            classes.push(class);
+        }
+        // the classes of the older texts are dropped
+        self.converted = converted;
+        let analysis = Arc::new(Analysis {
+            classes: classes.iter().map(|c| c.class.clone()).collect(),
+            diagnostics: classes.iter().flat_map(|c| c.diagnostics.iter().cloned()).collect(),
+            index: outline.merge(classes.iter().map(|c| &c.index)),
+        });
+        self.analysis = Some((self.revision, analysis.clone()));
+        analysis
+    }
+}
+
+/// the lines of a text, the spans count bytes in them and the client UTF-16
+/// code units. a position after the text is kept as it is
+#[derive(Debug, Clone, Default)]
+pub struct Lines(Rope);
+
+impl Lines {
+    /// the byte column of a position in its line, as the spans count them
+    pub fn byte_column(&self, pos: Position) -> usize {
+        let text = &self.0;
+        if pos.line as usize >= text.len_lines() {
+            return pos.character as usize;
+        }
+        text.char_to_byte(char_index(text, pos)) - text.line_to_byte(pos.line as usize)
+    }
+    /// the position of a line and a byte column, as the client counts it
+    pub fn position(&self, (line, col): (usize, usize)) -> Position {
+        let text = &self.0;
+        if line >= text.len_lines() {
+            return Position::new(line as u32, col as u32);
+        }
+        let start = text.line_to_byte(line);
+        let char = text.byte_to_char((start + col).min(text.len_bytes()));
+        let character = text.char_to_utf16_cu(char) - text.char_to_utf16_cu(text.line_to_char(line));
+        Position::new(line as u32, character as u32)
+    }
+    pub fn range(&self, (start, end): typort_interpreter::diagnostic::Range) -> Range {
+        Range::new(self.position(start), self.position(end))
+    }
+}
+
+fn name_of(item: &TopItem) -> Option<Symbol> {
+    match item {
+        TopItem::Class(c) => Some(c.name.data),
+        TopItem::Object(o) => Some(o.name.data),
+        TopItem::Import(_) => None,
+    }
+}
+
+fn hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// the char index of a position, its character counts UTF-16 code units
+fn char_index(rope: &Rope, pos: Position) -> usize {
+    let line = pos.line as usize;
+    if line >= rope.len_lines() {
+        return rope.len_chars();
+    }
+    let start = rope.line_to_char(line);
+    let end = if line + 1 < rope.len_lines() { rope.line_to_char(line + 1) } else { rope.len_chars() };
+    let unit = rope.char_to_utf16_cu(start) + pos.character as usize;
+    rope.utf16_cu_to_char(unit.min(rope.len_utf16_cu())).min(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_change() {
+        let mut db = Database::default();
+        db.set_text(FileId(0), "class Fifo(depth: Int) {\n  val size = depth\n}\n");
+        db.set_text(FileId(1), "object Top {\n  val f = new Fifo(4)\n}\n");
+        assert!(db.analysis().diagnostics.is_empty());
+        // `Top` did not change, but the parameter it passes an `Int` to did
+        db.set_text(FileId(0), "class Fifo(depth: String) {\n  val size = depth\n}\n");
+        let found: Vec<_> = db.analysis().diagnostics.iter().map(|d| (d.code.unwrap(), d.msg.clone())).collect();
+        assert_eq!(found, vec![("E0116", "expected String, found Int".to_string())]);
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut db = Database::default();
+        db.set_text(FileId(0), "val s = \"é😀\"; x\n");
+        // `é` is 1 UTF-16 code unit and 2 bytes, the emoji 2 code units and 4 bytes
+        let lines = db.lines(FileId(0));
+        let pos = |character| Position { line: 0, character };
+        assert_eq!(lines.byte_column(pos(15)), 18);
+        assert_eq!(lines.byte_column(pos(3)), 3);
+        assert_eq!(lines.position((0, 18)), pos(15));
+        assert_eq!(lines.range(((0, 9), (0, 15))), Range::new(pos(9), pos(12)));
+        // a file without a text is read as it is
+        assert_eq!(db.lines(FileId(1)).position((2, 7)), Position::new(2, 7));
+    }
+}
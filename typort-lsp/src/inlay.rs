@@ -0,0 +1,52 @@
+use tower_lsp::lsp_types::*;
+use typort_interpreter::hir::Expression;
+use typort_interpreter::index::{start_of, SymbolIndex, SymbolKind};
+use typort_parser::ast::FileId;
+
+use crate::db::Lines;
+
+/// the hints of a file: the types of the `val`s and `var`s declared without
+/// one, the clock domains of the registers and the names of the parameters
+/// the arguments of calls are given to
+pub fn inlay_hints(index: &SymbolIndex, file: FileId, lines: &Lines) -> Vec<InlayHint> {
+    let mut hints = vec![];
+    for (id, d) in index.defs.iter().enumerate().filter(|(_, d)| d.at.file == file) {
+        let end = lines.position(d.at.range.1);
+        if matches!(d.kind, SymbolKind::Local | SymbolKind::Field) && d.ty.is_none() && d.value.is_some() {
+            if let Some(ty) = index.type_of(id) {
+                hints.push(hint(end, format!(": {ty}"), InlayHintKind::TYPE, false));
+            }
+        }
+        if let Some(domain) = d.signal.as_ref().filter(|s| s.register).and_then(|s| s.clock_domain.as_ref()) {
+            hints.push(hint(end, format!("@{domain}"), InlayHintKind::TYPE, true));
+        }
+    }
+    for call in index.calls.iter().filter(|c| c.at.file == file) {
+        for ((name, _), arg) in index.defs[call.callee].params.iter().zip(&call.args) {
+            // `f(width)` already says it is the width
+            if matches!(arg, Expression::Name(x) if x.data == name.data) {
+                continue;
+            }
+            if let Some(at) = start_of(arg).filter(|at| at.file == file) {
+                hints.push(InlayHint {
+                    padding_right: Some(true),
+                    ..hint(lines.position(at.range.0), format!("{}:", name.data), InlayHintKind::PARAMETER, false)
+                });
+            }
+        }
+    }
+    hints
+}
+
+fn hint(position: Position, label: String, kind: InlayHintKind, padding_left: bool) -> InlayHint {
+    InlayHint {
+        position,
+        label: InlayHintLabel::String(label),
+        kind: Some(kind),
+        text_edits: None,
+        tooltip: None,
+        padding_left: Some(padding_left),
+        padding_right: None,
+        data: None,
+    }
+}
@@ -2,6 +2,12 @@ use backend::Backend;
 use tower_lsp::{LspService, Server};
 
 mod backend;
+mod completion;
+mod db;
+mod inlay;
+mod semantic;
+mod signature;
+mod symbols;
 
 pub async fn main_lsp() {
 
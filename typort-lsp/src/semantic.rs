@@ -0,0 +1,129 @@
+use tower_lsp::lsp_types::*;
+use typort_interpreter::hir::Expression;
+use typort_interpreter::index::{Def, Direction, SymbolIndex, SymbolKind};
+use typort_parser::ast::FileId;
+
+use crate::db::Lines;
+
+/// the token types, the first ones are the standard ones, the others say
+/// what hardware a signal is. a token is encoded as its index in this list
+pub const TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::CLASS,
+    SemanticTokenType::INTERFACE,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::METHOD,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::new("register"),
+    SemanticTokenType::new("wire"),
+    SemanticTokenType::new("input"),
+    SemanticTokenType::new("output"),
+    SemanticTokenType::new("clock"),
+    SemanticTokenType::new("reset"),
+];
+
+pub const TOKEN_MODIFIERS: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::READONLY,
+];
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: TOKEN_MODIFIERS.to_vec(),
+    }
+}
+
+/// the tokens of the definitions of a file and of the names referring to them,
+/// each position relative to the previous token
+pub fn semantic_tokens(index: &SymbolIndex, file: FileId, lines: &Lines) -> Vec<SemanticToken> {
+    let defs = index.defs.iter().enumerate().map(|(id, d)| (&d.at, id, true));
+    let refs = index.refs.iter().map(|(at, def)| (at, *def, false));
+    let mut found: Vec<_> = defs.chain(refs)
+        // a token can not span several lines
+        .filter(|(at, _, _)| at.file == file && at.range.0.0 == at.range.1.0)
+        .collect();
+    found.sort_by_key(|(at, _, declaration)| (at.range.0, !declaration));
+    found.dedup_by_key(|(at, _, _)| at.range.0);
+    let mut tokens = vec![];
+    let mut last = (0, 0);
+    for (at, def, declaration) in found {
+        let d = &index.defs[def];
+        let ty = token_type(index, d);
+        let mut modifiers = 0;
+        if declaration {
+            modifiers |= 1;
+        }
+        if !d.mutable && matches!(d.kind, SymbolKind::Field | SymbolKind::Local | SymbolKind::Param) {
+            modifiers |= 2;
+        }
+        let (start, end) = (lines.position(at.range.0), lines.position(at.range.1));
+        let (line, col) = (start.line, start.character);
+        tokens.push(SemanticToken {
+            delta_line: line - last.0,
+            delta_start: if line == last.0 { col - last.1 } else { col },
+            length: end.character - col,
+            token_type: TOKEN_TYPES.iter().position(|t| *t == ty).unwrap_or_default() as u32,
+            token_modifiers_bitset: modifiers,
+        });
+        last = (line, col);
+    }
+    tokens
+}
+
+/// a signal is a register, a port or a wire, the clock and the reset are found
+/// by their names, e.g. `clk` and `rstN`
+fn token_type(index: &SymbolIndex, d: &Def) -> SemanticTokenType {
+    let name = d.name.as_str().to_lowercase();
+    let named = |names: &[&str]| names.iter().any(|n| name.contains(n));
+    match d.kind {
+        SymbolKind::Class | SymbolKind::Object => return SemanticTokenType::CLASS,
+        SymbolKind::Trait => return SemanticTokenType::INTERFACE,
+        SymbolKind::Func => return SemanticTokenType::FUNCTION,
+        SymbolKind::Method => return SemanticTokenType::METHOD,
+        SymbolKind::Param if d.signal.is_none() => return SemanticTokenType::PARAMETER,
+        _ => {}
+    }
+    if matches!(&d.value, Some(Expression::Call(f, _)) if f.data == "ClockDomain") {
+        return SemanticTokenType::new("clock");
+    }
+    let Some(signal) = &d.signal else {
+        return match d.kind {
+            SymbolKind::Field => SemanticTokenType::PROPERTY,
+            _ => SemanticTokenType::VARIABLE,
+        };
+    };
+    match signal {
+        s if s.ty == "Bool" && named(&["clk", "clock"]) => SemanticTokenType::new("clock"),
+        s if s.ty == "Bool" && named(&["rst", "reset"]) => SemanticTokenType::new("reset"),
+        s if s.register => SemanticTokenType::new("register"),
+        s if s.direction == Some(Direction::In) => SemanticTokenType::new("input"),
+        s if s.direction == Some(Direction::Out) => SemanticTokenType::new("output"),
+        // a bundle is not a wire, its fields are
+        s if !index.inherits(s.ty.as_str().into(), "Bundle") => SemanticTokenType::new("wire"),
+        _ => match d.kind {
+            SymbolKind::Field => SemanticTokenType::PROPERTY,
+            _ => SemanticTokenType::VARIABLE,
+        },
+    }
+}
+
+/// the edits turning `old` into `new`, the tokens both start and end with
+/// are kept
+pub fn delta(old: &[SemanticToken], new: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if prefix == old.len() && prefix == new.len() {
+        return vec![];
+    }
+    // the edits count the integers of the encoding, five for a token
+    vec![SemanticTokensEdit {
+        start: 5 * prefix as u32,
+        delete_count: 5 * (old.len() - prefix - suffix) as u32,
+        data: Some(new[prefix..new.len() - suffix].to_vec()),
+    }]
+}
@@ -0,0 +1,79 @@
+use tower_lsp::lsp_types::*;
+use typort_interpreter::index::{SymbolIndex, SymbolKind};
+use typort_parser::ast::FileId;
+
+/// the parameters of the function or the class called at `at`, a line and a
+/// byte column, the one the argument being written is given to is active.
+/// only `text` is read, the call is usually not complete
+pub fn signature_help(index: &SymbolIndex, text: &str, file: FileId, at: (usize, usize)) -> Option<SignatureHelp> {
+    let mut before: Vec<&str> = text.lines().take(at.0).collect();
+    let line = text.lines().nth(at.0).unwrap_or("");
+    before.push(line.get(..at.1).unwrap_or(line));
+    let before = before.join("\n");
+    let (open, commas) = open_call(&before)?;
+    let callee: String = before[..open].trim_end().chars().rev().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').collect();
+    let callee: String = callee.chars().rev().collect();
+    let def = index.classes.get(&callee.as_str().into()).copied()
+        .filter(|def| index.defs[*def].kind == SymbolKind::Class)
+        .or_else(|| {
+            index.defs.iter()
+                .enumerate()
+                .filter(|(_, d)| d.name == callee.as_str() && matches!(d.kind, SymbolKind::Func | SymbolKind::Method))
+                .min_by_key(|(_, d)| d.at.file != file)
+                .map(|(id, _)| id)
+        })?;
+    let d = &index.defs[def];
+    let label = index.signature(def);
+    // the parameters are found in the label from its `(`
+    let mut from = label.find('(')?;
+    let parameters = d.params.iter().map(|(name, ty)| {
+        let param = format!("{}: {ty}", name.data);
+        let start = from + label[from..].find(&param).unwrap_or(0);
+        from = start + param.len();
+        ParameterInformation {
+            label: ParameterLabel::LabelOffsets([utf16_len(&label[..start]), utf16_len(&label[..from])]),
+            documentation: None,
+        }
+    }).collect();
+    // `name = ` gives the argument to the parameter `name`, wherever it is
+    let argument = before[open + 1..].rsplit(',').next().unwrap_or("");
+    let active = match argument.split_once('=') {
+        Some((name, value)) if !value.starts_with('=') => {
+            d.params.iter().position(|(p, _)| p.data == name.trim()).unwrap_or(commas)
+        }
+        _ => commas,
+    };
+    Some(SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label,
+            documentation: None,
+            parameters: Some(parameters),
+            active_parameter: None,
+        }],
+        active_signature: Some(0),
+        active_parameter: Some(active as u32),
+    })
+}
+
+/// where the `(` of the call the end of `text` is in is, and how many
+/// arguments are before the end
+fn open_call(text: &str) -> Option<(usize, usize)> {
+    let mut depth = 0;
+    let mut commas = 0;
+    for (i, c) in text.char_indices().rev() {
+        match c {
+            ')' | ']' => depth += 1,
+            '(' | '[' if depth > 0 => depth -= 1,
+            '(' => return Some((i, commas)),
+            ',' if depth == 0 => commas += 1,
+            // a block is not in the arguments
+            '{' | '}' if depth == 0 => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn utf16_len(text: &str) -> u32 {
+    text.encode_utf16().count() as u32
+}
@@ -0,0 +1,115 @@
+use tower_lsp::lsp_types::*;
+use typort_interpreter::index::{class_name, SymbolIndex, SymbolKind as Kind};
+use typort_parser::ast::{Block, ClassKind, Expression, FileId, Ident, Span, Stmt, TopItem, TypeExpr};
+
+use crate::db::Lines;
+
+/// the outline of a file: its classes and objects with their members, the
+/// fields of a `new Bundle { .. }` nested under the `val` holding it
+pub fn document_symbols<'a>(index: &SymbolIndex, file: FileId, lines: &Lines, items: impl Iterator<Item = &'a TopItem>) -> Vec<DocumentSymbol> {
+    items.filter_map(|item| {
+        let (name, kind, extends, block) = match item {
+            TopItem::Class(c) => {
+                let kind = match (c.kind, c.extends.as_ref().map(|e| e.data.as_str())) {
+                    (ClassKind::Trait, _) => SymbolKind::INTERFACE,
+                    (_, Some("Bundle")) => SymbolKind::STRUCT,
+                    _ => SymbolKind::CLASS,
+                };
+                (&c.name, kind, &c.extends, &c.block)
+            }
+            TopItem::Object(o) => (&o.name, SymbolKind::OBJECT, &o.extends, &o.block),
+            TopItem::Import(_) => return None,
+        };
+        let detail = extends.as_ref().map(|e| format!("extends {}", e.data));
+        Some(symbol(lines, name, kind, detail, members(index, file, lines, block)))
+    }).collect()
+}
+
+fn members(index: &SymbolIndex, file: FileId, lines: &Lines, block: &Block) -> Vec<DocumentSymbol> {
+    block.0.iter().filter_map(|stmt| match stmt {
+        Stmt::Val(name, ty, value) | Stmt::Var(name, ty, value) => {
+            let children = match value {
+                Expression::New(_, _, Some(block)) => members(index, file, lines, block),
+                _ => vec![],
+            };
+            let kind = match (value, children.is_empty()) {
+                (Expression::New(c, _, _), false) if c.data == "Bundle" => SymbolKind::STRUCT,
+                (_, false) => SymbolKind::OBJECT,
+                (Expression::Call(f, _), _) if f.data == "in" || f.data == "out" => SymbolKind::PROPERTY,
+                _ if matches!(stmt, Stmt::Var(..)) => SymbolKind::VARIABLE,
+                _ => SymbolKind::FIELD,
+            };
+            Some(symbol(lines, name, kind, detail(index, file, name, ty.as_ref()), children))
+        }
+        Stmt::Decl(name, ty) => Some(symbol(lines, name, SymbolKind::FIELD, Some(ty.to_string()), vec![])),
+        Stmt::Func(f) => {
+            let params: Vec<_> = f.params.iter().map(|(p, ty)| format!("{}: {ty}", p.data)).collect();
+            let ret = f.return_type.as_ref().map(|t| format!(": {t}")).unwrap_or_default();
+            Some(symbol(lines, &f.name, SymbolKind::METHOD, Some(format!("({}){ret}", params.join(", "))), vec![]))
+        }
+        _ => None,
+    }).collect()
+}
+
+/// `in UInt(8 bits)` for a port, else the declared or the inferred type
+fn detail(index: &SymbolIndex, file: FileId, name: &Ident, ty: Option<&TypeExpr>) -> Option<String> {
+    if let Some(ty) = ty {
+        return Some(ty.to_string());
+    }
+    let def = index.at(file, name.range.0.0, name.range.0.1)?;
+    match &index.defs[def].signal {
+        Some(signal) => Some(match &signal.direction {
+            Some(direction) => format!("{direction} {signal}"),
+            None => signal.to_string(),
+        }),
+        None => index.type_of(def),
+    }
+}
+
+#[allow(deprecated)]
+fn symbol(lines: &Lines, name: &Ident, kind: SymbolKind, detail: Option<String>, children: Vec<DocumentSymbol>) -> DocumentSymbol {
+    let selection_range = lines.range(name.range);
+    // the ast only knows where the names are, a symbol spans to its last child
+    let end = children.iter().map(|c| c.range.end).max().unwrap_or(selection_range.end);
+    DocumentSymbol {
+        name: name.data.to_string(),
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: Range::new(selection_range.start, end),
+        selection_range,
+        children: (!children.is_empty()).then_some(children),
+    }
+}
+
+/// the classes, objects and members of every file whose name contains `query`,
+/// ignoring case. `location` gives where a definition is, `None` if its file
+/// has no document
+#[allow(deprecated)]
+pub fn workspace_symbols(index: &SymbolIndex, query: &str, location: impl Fn(&Span<()>) -> Option<Location>) -> Vec<SymbolInformation> {
+    let query = query.to_lowercase();
+    index.defs.iter()
+        .filter(|d| d.name.as_str().to_lowercase().contains(&query))
+        .filter_map(|d| {
+            let kind = match d.kind {
+                Kind::Class => SymbolKind::CLASS,
+                Kind::Trait => SymbolKind::INTERFACE,
+                Kind::Object => SymbolKind::OBJECT,
+                Kind::Field if d.signal.as_ref().is_some_and(|s| s.direction.is_some()) => SymbolKind::PROPERTY,
+                Kind::Field => SymbolKind::FIELD,
+                Kind::Method => SymbolKind::METHOD,
+                Kind::Func => SymbolKind::FUNCTION,
+                Kind::Param | Kind::Local => return None,
+            };
+            Some(SymbolInformation {
+                name: d.name.to_string(),
+                kind,
+                tags: None,
+                deprecated: None,
+                location: location(&d.at)?,
+                container_name: d.class.map(|c| class_name(c).to_owned()),
+            })
+        })
+        .collect()
+}
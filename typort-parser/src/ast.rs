@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+/// identify a source file, all spans from the same file share it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct FileId(pub u32);
+
+type Line = usize;
+type Col = usize;
+
+#[derive(Debug, Clone, Default)]
+pub struct Span<T> {
+    pub data: T,
+    pub file: FileId,
+    pub offset: usize,
+    pub len: usize,
+    pub range: ((Line, Col), (Line, Col)),
+}
+
+impl<T> Span<T> {
+    pub fn map<F, O>(self, f: F) -> Span<O>
+    where
+        F: FnOnce(T) -> O
+    {
+        Span {
+            data: f(self.data),
+            file: self.file,
+            offset: self.offset,
+            len: self.len,
+            range: self.range,
+        }
+    }
+    /// a span at the same source location holding other data
+    pub fn with_data<O>(&self, data: O) -> Span<O> {
+        Span {
+            data,
+            file: self.file,
+            offset: self.offset,
+            len: self.len,
+            range: self.range,
+        }
+    }
+}
+
+/// an interned identifier, cheap to copy, hash and compare
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// interned before any other name, in the order of the constants of `Symbol`
+const PREDEFINED: &[&str] = &["this", "apply", "<lambda>", "to", "until", "format"];
+
+/// the names by symbol, chunk `i` holds `CHUNK << i` of them. a chunk never
+/// moves, so names are read without the lock
+static NAMES: [OnceLock<Box<[OnceLock<&'static str>]>>; CHUNKS] = [const { OnceLock::new() }; CHUNKS];
+const CHUNK: usize = 256;
+const CHUNKS: usize = 24;
+
+/// the chunk of `NAMES` a symbol is in and its index there
+fn slot(id: u32) -> (usize, usize) {
+    let chunk = (id as usize / CHUNK + 1).ilog2() as usize;
+    (chunk, id as usize - CHUNK * ((1 << chunk) - 1))
+}
+
+/// the text of the names, in blocks that are never freed
+const BLOCK: usize = 4096;
+
+struct Interner {
+    ids: HashMap<&'static str, Symbol>,
+    /// the block being filled, it is never grown so the names in it stay
+    block: String,
+}
+
+impl Interner {
+    fn alloc(&mut self, name: &str) -> &'static str {
+        if self.block.capacity() - self.block.len() < name.len() {
+            let full = std::mem::replace(&mut self.block, String::with_capacity(BLOCK.max(name.len())));
+            // the names in it are still referred to
+            std::mem::forget(full);
+        }
+        let start = self.block.len();
+        self.block.push_str(name);
+        let name: *const str = &self.block[start..];
+        // Safety: the block has the capacity for `name`, so pushing did not
+        // move it, and it is never freed. it is only written after `len`
+        unsafe { &*name }
+    }
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(sym) = self.ids.get(name) {
+            return *sym;
+        }
+        let name = self.alloc(name);
+        let sym = Symbol(self.ids.len() as u32);
+        let (chunk, idx) = slot(sym.0);
+        let names = NAMES[chunk].get_or_init(|| (0..CHUNK << chunk).map(|_| OnceLock::new()).collect());
+        names[idx].set(name).expect("a symbol is interned once");
+        self.ids.insert(name, sym);
+        sym
+    }
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| {
+        let mut interner = Interner { ids: HashMap::new(), block: String::with_capacity(BLOCK) };
+        for name in PREDEFINED {
+            interner.intern(name);
+        }
+        Mutex::new(interner)
+    })
+}
+
+impl Symbol {
+    pub const THIS: Symbol = Symbol(0);
+    pub const APPLY: Symbol = Symbol(1);
+    /// the name of the closure of a lambda
+    pub const LAMBDA: Symbol = Symbol(2);
+    pub const TO: Symbol = Symbol(3);
+    pub const UNTIL: Symbol = Symbol(4);
+    pub const FORMAT: Symbol = Symbol(5);
+
+    pub fn intern(name: &str) -> Self {
+        interner().lock().unwrap().intern(name)
+    }
+    pub fn as_str(self) -> &'static str {
+        // the predefined names are there once the interner is made
+        interner();
+        let (chunk, idx) = slot(self.0);
+        NAMES[chunk].get().and_then(|names| names[idx].get()).expect("an interned symbol")
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(value: &str) -> Self {
+        Symbol::intern(value)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+pub type Ident = Span<Symbol>;
+
+#[derive(Debug, Clone)]
+pub enum TypeExpr {
+    /// `Int`, `Stream[Bits]`
+    Name(Ident, Vec<TypeExpr>),
+    Tuple(Vec<TypeExpr>),
+    Func(Vec<TypeExpr>, Box<TypeExpr>),
+}
+
+impl fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(v: &[TypeExpr]) -> String {
+            v.iter()
+                .map(|t| format!("{t}"))
+                .reduce(|a, b| format!("{a}, {b}"))
+                .unwrap_or("".to_owned())
+        }
+        match self {
+            TypeExpr::Name(n, args) if args.is_empty() => write!(f, "{}", n.data),
+            TypeExpr::Name(n, args) => write!(f, "{}[{}]", n.data, list(args)),
+            TypeExpr::Tuple(v) => write!(f, "({})", list(v)),
+            TypeExpr::Func(i, o) => write!(f, "({}) => {}", list(i), o),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Expression {
+    Int(Span<i64>),
+    String(Span<String>),
+    Bool(Span<bool>),
+    Name(Ident),
+    Field(Box<Expression>, Ident),
+    Add(Box<Expression>, Box<Expression>),
+    Sub(Box<Expression>, Box<Expression>),
+    Mul(Box<Expression>, Box<Expression>),
+    Div(Box<Expression>, Box<Expression>),
+    Mod(Box<Expression>, Box<Expression>),
+    Eq(Box<Expression>, Box<Expression>),
+    Neq(Box<Expression>, Box<Expression>),
+    Lt(Box<Expression>, Box<Expression>),
+    Le(Box<Expression>, Box<Expression>),
+    Gt(Box<Expression>, Box<Expression>),
+    Ge(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Neg(Box<Expression>),
+    /// `a op b` for any operator without a dedicated variant, e.g. `io.a := b`
+    Infix(Box<Expression>, Ident, Box<Expression>),
+    Call(Ident, Vec<Expression>),
+    MethodCall(Box<Expression>, Ident, Vec<Expression>),
+    /// `f(a)(b)`: applying the result of an expression
+    Apply(Box<Expression>, Vec<Expression>),
+    If(Box<Expression>, Block, Option<Block>),
+    Block(Block),
+    Tuple(Vec<Expression>),
+    New(Ident, Vec<Expression>, Option<Block>),
+    Lambda(Vec<(Ident, Option<TypeExpr>)>, Box<Expression>),
+    Match(Box<Expression>, Vec<Case>),
+    /// `s"a $b ${c}"` at the `s"`, the text parts are `String`s
+    Interpolated(Span<()>, Vec<Expression>),
+    /// `width = 8` in `Fifo(width = 8)`, an argument given by the name of its parameter
+    Named(Ident, Box<Expression>),
+}
+
+impl Expression {
+    /// where to point diagnostics about this expression, `None` for an empty block or tuple
+    pub fn span(&self) -> Option<Span<()>> {
+        match self {
+            Expression::Int(x) => Some(x.with_data(())),
+            Expression::String(x) => Some(x.with_data(())),
+            Expression::Bool(x) => Some(x.with_data(())),
+            Expression::Name(x)
+            | Expression::Call(x, _)
+            | Expression::New(x, _, _)
+            | Expression::Named(x, _) => Some(x.with_data(())),
+            Expression::Field(a, _)
+            | Expression::Add(a, _)
+            | Expression::Sub(a, _)
+            | Expression::Mul(a, _)
+            | Expression::Div(a, _)
+            | Expression::Mod(a, _)
+            | Expression::Eq(a, _)
+            | Expression::Neq(a, _)
+            | Expression::Lt(a, _)
+            | Expression::Le(a, _)
+            | Expression::Gt(a, _)
+            | Expression::Ge(a, _)
+            | Expression::And(a, _)
+            | Expression::Or(a, _)
+            | Expression::Not(a)
+            | Expression::Neg(a)
+            | Expression::Infix(a, _, _)
+            | Expression::MethodCall(a, _, _)
+            | Expression::Apply(a, _)
+            | Expression::If(a, _, _)
+            | Expression::Match(a, _) => a.span(),
+            Expression::Interpolated(s, _) => Some(s.clone()),
+            Expression::Block(b) => b.0.iter().find_map(Stmt::span),
+            Expression::Tuple(v) => v.iter().find_map(Expression::span),
+            Expression::Lambda(params, body) => params
+                .first()
+                .map(|p| p.0.with_data(()))
+                .or_else(|| body.span()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub block: Block,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// `_`
+    Wildcard,
+    Literal(Expression),
+    /// a lowercase name, binds the matched value
+    Bind(Ident),
+    /// `x: Int`
+    Typed(Ident, TypeExpr),
+    Tuple(Vec<Pattern>),
+    /// `Foo(a, b)`, or a bare uppercase name such as `None`
+    Extractor(Ident, Vec<Pattern>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Func {
+    /// `override def`
+    pub overrides: bool,
+    pub name: Ident,
+    pub params: Vec<(Ident, TypeExpr)>,
+    pub return_type: Option<TypeExpr>,
+    /// `None` for an abstract method
+    pub block: Option<Block>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Block(pub Vec<Stmt>);
+
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub name: Ident,
+    pub extends: Option<Ident>,
+    pub with: Vec<Ident>,
+    pub block: Block,
+}
+
+/// what a `Class` is declared as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassKind {
+    Class,
+    /// `abstract class`
+    Abstract,
+    Trait,
+}
+
+#[derive(Debug, Clone)]
+pub struct Class {
+    pub kind: ClassKind,
+    pub name: Ident,
+    pub args: Vec<(Ident, TypeExpr)>,
+    pub extends: Option<Ident>,
+    pub with: Vec<Ident>,
+    pub block: Block,
+}
+
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub path: Vec<Ident>,
+    /// `import a.b._`
+    pub wildcard: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum TopItem {
+    Class(Class),
+    Object(Object),
+    Import(Import),
+}
+
+/// one thing typed at the repl
+#[derive(Debug, Clone)]
+pub enum ReplItem {
+    Top(TopItem),
+    Stmt(Stmt),
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Expr(Expression),
+    Val(Ident, Option<TypeExpr>, Expression),
+    Var(Ident, Option<TypeExpr>, Expression),
+    /// `val a: T` without a value, an abstract member
+    Decl(Ident, TypeExpr),
+    Assign(Ident, Expression),
+    Return(Expression),
+    /// `break` and `continue` leave or restart the innermost loop
+    Break(Span<()>),
+    Continue(Span<()>),
+    /// `for(x <- range) {}`, the range is usually `a until b` or `a to b`
+    For(Ident, Expression, Block),
+    While(Expression, Block),
+    Func(Func),
+}
+
+impl Stmt {
+    pub fn span(&self) -> Option<Span<()>> {
+        match self {
+            Stmt::Expr(e) | Stmt::Return(e) | Stmt::While(e, _) => e.span(),
+            Stmt::Val(a, _, _)
+            | Stmt::Var(a, _, _)
+            | Stmt::Decl(a, _)
+            | Stmt::Assign(a, _)
+            | Stmt::For(a, _, _) => {
+                Some(a.with_data(()))
+            }
+            Stmt::Break(a) | Stmt::Continue(a) => Some(a.clone()),
+            Stmt::Func(f) => Some(f.name.with_data(())),
+        }
+    }
+}